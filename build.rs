@@ -0,0 +1,5 @@
+fn main() -> std::io::Result<()> {
+	println!("cargo:rerun-if-changed=migrations");
+	println!("cargo:rerun-if-changed=proto/schedule.proto");
+	prost_build::compile_protos(&["proto/schedule.proto"], &["proto/"])
+}
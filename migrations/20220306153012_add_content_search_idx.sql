@@ -0,0 +1,2 @@
+-- Add a full text index over all block texts, used to narrow down the rows the search endpoint looks at
+CREATE INDEX content_search_idx ON substitution_json USING GIN (to_tsvector('simple', json -> 'entries'));
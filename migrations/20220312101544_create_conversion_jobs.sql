@@ -0,0 +1,13 @@
+-- Add the queue of conversions that haven't reached substitution_json yet
+CREATE TABLE conversion_jobs
+(
+    hash       TEXT PRIMARY KEY,
+    day        SMALLINT  NOT NULL,
+    pdf        BYTEA     NOT NULL,
+    attempts   INTEGER   NOT NULL DEFAULT 0,
+    state      TEXT      NOT NULL DEFAULT 'pending',
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+CREATE INDEX conversion_jobs_state_idx ON conversion_jobs(state, updated_at);
@@ -0,0 +1,3 @@
+-- Add the day a schedule was fetched for, so instances can serve the latest one per day from the database
+ALTER TABLE substitution_json ADD COLUMN day SMALLINT;
+CREATE INDEX day_idx ON substitution_json(day, pdf_date DESC);
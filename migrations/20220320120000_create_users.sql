@@ -0,0 +1,9 @@
+-- Add the accounts subscriptions, preferences and admin rights belong to
+CREATE TABLE users
+(
+    id            BIGSERIAL PRIMARY KEY,
+    username      TEXT      NOT NULL UNIQUE,
+    password_hash TEXT      NOT NULL,
+    role          TEXT      NOT NULL DEFAULT 'student',
+    created_at    TIMESTAMP NOT NULL
+);
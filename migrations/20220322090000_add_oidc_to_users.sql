@@ -0,0 +1,3 @@
+-- Accounts of the OpenID Connect provider have no password
+ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL;
+ALTER TABLE users ADD COLUMN oidc_subject TEXT UNIQUE;
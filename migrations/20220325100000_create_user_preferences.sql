@@ -0,0 +1,7 @@
+-- Add the classes of a user, which personalize /me/schedule and the notifications of the user
+CREATE TABLE user_preferences
+(
+    user_id BIGINT PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    classes TEXT[] NOT NULL DEFAULT '{}',
+    webhook TEXT
+);
@@ -0,0 +1,2 @@
+-- Keep the pdf every schedule was converted from, for reprocessing, /{schoolday}/pdf on every instance and audits
+ALTER TABLE substitution_json ADD COLUMN pdf BYTEA;
@@ -0,0 +1,2 @@
+-- Remember which algorithm the hash of a schedule was created with, everything stored before is SHA512
+ALTER TABLE substitution_json ADD COLUMN hash_algorithm TEXT NOT NULL DEFAULT 'sha512';
@@ -0,0 +1,5 @@
+-- Store superseded schedules as a JSON Patch turning the schedule that replaced them back into them,
+-- so only the newest schedule of a day is kept in full
+ALTER TABLE substitution_json ADD COLUMN base_hash TEXT REFERENCES substitution_json (hash);
+ALTER TABLE substitution_json ADD COLUMN patch jsonb;
+CREATE INDEX base_hash_idx ON substitution_json(base_hash);
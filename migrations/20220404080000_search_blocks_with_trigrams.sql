@@ -0,0 +1,12 @@
+-- Replace the full text index with a trigram index over the block texts, so the history search matches any part of a word like the search of the current schedules
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE FUNCTION block_texts(entries jsonb) RETURNS text
+	LANGUAGE sql IMMUTABLE STRICT
+	AS $$
+		SELECT coalesce(string_agg(block.value, E'\n'), '')
+		FROM jsonb_each(entries) AS class, jsonb_each_text(class.value) AS block
+	$$;
+
+DROP INDEX content_search_idx;
+CREATE INDEX block_texts_trgm_idx ON substitution_json USING GIN (block_texts(json -> 'entries') gin_trgm_ops);
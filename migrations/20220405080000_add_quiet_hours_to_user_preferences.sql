@@ -0,0 +1,5 @@
+-- Add the times of the day the changes aren't sent to the webhook of a user at, either both are set or neither
+ALTER TABLE user_preferences
+    ADD COLUMN quiet_start TIME,
+    ADD COLUMN quiet_end   TIME,
+    ADD CONSTRAINT quiet_hours_complete CHECK ((quiet_start IS NULL) = (quiet_end IS NULL));
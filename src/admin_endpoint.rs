@@ -0,0 +1,242 @@
+use std::path::{Path, PathBuf};
+use actix_web::{get, HttpRequest, HttpResponse, post, Responder, web};
+use actix_web::http::header;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+use tracing::{error, info};
+use futures_util::TryStreamExt;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::io::StreamReader;
+use crate::{backup, CONFIG, export, import, JSON_HANDLER, jobs, PDF_STORE_LOCATION, quarantine, Schoolday, storage};
+use crate::auth::{self, Role};
+use crate::blob_store::BLOBS;
+use crate::config::HashAlgorithm;
+use crate::storage::Pools;
+
+#[derive(Debug, Serialize)]
+struct Reprocessed {
+	hash: String,
+	/// The days whose served schedule was replaced.
+	days: Vec<Schoolday>,
+	warnings: Vec<String>,
+}
+
+/// Converts an archived pdf again with the current parser and replaces the stored schedule.
+/// The pdf is looked up in the quarantine, the job queue, the database, the blob store and the old pdf archive, in that order.
+#[post("/admin/reprocess/{hash}")]
+pub async fn post_reprocess(req: HttpRequest, hash: web::Path<String>, pools: web::Data<Pools>) -> impl Responder {
+	if let Some(response) = check_admin(&req) {
+		return response;
+	}
+
+	let hash = hash.into_inner();
+	// The hash ends up in file names, so only accept what a hex digest can look like.
+	if hash.contains('-') || HashAlgorithm::of(&hash).is_none() {
+		return HttpResponse::BadRequest().body("Expected a hex encoded SHA512 or BLAKE3 hash");
+	}
+	let hash = hash.to_ascii_lowercase();
+
+	let pdf = match find_pdf(&hash, &pools.write).await {
+		Ok(Some(pdf)) => pdf,
+		Ok(None) => return HttpResponse::NotFound().finish(),
+		Err(why) => {
+			error!("Couldn't look up the pdf {hash}: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	info!("Reprocessing the pdf {hash}");
+	match JSON_HANDLER.reprocess(&hash, &pdf, &pools.write).await {
+		Ok((days, warnings)) => HttpResponse::Ok().json(Reprocessed {
+			hash,
+			days,
+			warnings,
+		}),
+		Err(why) => HttpResponse::UnprocessableEntity().body(why.to_string()),
+	}
+}
+
+/// The body of the import and export endpoints.
+#[derive(Debug, Deserialize)]
+pub struct DirectoryRequest {
+	/// Directory on the server the pdfs are read from or the schedules are written to.
+	directory: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+struct Exported {
+	exported: usize,
+}
+
+/// Backfills the database with a directory of archived pdfs, e.g. plans collected before the server was used.
+/// Pdfs that are stored already are skipped, so an interrupted import can simply be started again.
+#[post("/admin/import")]
+pub async fn post_import(req: HttpRequest, body: web::Json<DirectoryRequest>, pools: web::Data<Pools>) -> impl Responder {
+	if let Some(response) = check_admin(&req) {
+		return response;
+	}
+
+	info!("Importing the pdfs in {}", body.directory.display());
+	match import::import_dir(&body.directory, &pools.write).await {
+		Ok(report) => HttpResponse::Ok().json(report),
+		Err(why) => HttpResponse::BadRequest().body(format!("Couldn't read {}: {why}", body.directory.display())),
+	}
+}
+
+/// Writes every stored schedule into a directory as json files with an index, for backups and offline analysis.
+#[post("/admin/export")]
+pub async fn post_export(req: HttpRequest, body: web::Json<DirectoryRequest>, pools: web::Data<Pools>) -> impl Responder {
+	if let Some(response) = check_admin(&req) {
+		return response;
+	}
+
+	info!("Exporting the schedules to {}", body.directory.display());
+	match export::export_dir(&body.directory, &pools.read).await {
+		Ok(index) => HttpResponse::Ok().json(Exported {
+			exported: index.len(),
+		}),
+		Err(why) => {
+			error!("Couldn't export to {}: {why}", body.directory.display());
+			HttpResponse::InternalServerError().body(why.to_string())
+		}
+	}
+}
+
+#[derive(Debug, Serialize)]
+struct Restored {
+	restored: usize,
+}
+
+/// Streams a gzip compressed backup of all stored schedules, see [`backup`] for the format.
+#[get("/admin/backup")]
+pub async fn get_backup(req: HttpRequest, pools: web::Data<Pools>) -> impl Responder {
+	if let Some(response) = check_admin(&req) {
+		return response;
+	}
+
+	let (sender, receiver) = mpsc::channel(4);
+	tokio::spawn(backup::write_backup(pools.read.clone(), sender));
+
+	let file_name = format!("substitutions-{}.jsonl.gz", chrono::Local::now().format("%F"));
+	HttpResponse::Ok()
+		.content_type("application/gzip")
+		.insert_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"{file_name}\"")))
+		.streaming(ReceiverStream::new(receiver))
+}
+
+#[derive(Debug, Serialize)]
+struct Archived {
+	/// The key of the backup in the blob store.
+	key: String,
+}
+
+/// Writes a backup like `/admin/backup` into the blob store instead of sending it.
+#[post("/admin/backup/archive")]
+pub async fn post_backup_archive(req: HttpRequest, pools: web::Data<Pools>) -> impl Responder {
+	if let Some(response) = check_admin(&req) {
+		return response;
+	}
+
+	match backup::archive(pools.read.clone()).await {
+		Ok(key) => {
+			info!("Archived a backup as {key}");
+			HttpResponse::Ok().json(Archived {
+				key,
+			})
+		}
+		Err(why) => {
+			error!("Couldn't archive a backup: {why}");
+			HttpResponse::InternalServerError().body(why.to_string())
+		}
+	}
+}
+
+/// Loads a backup created by `/admin/backup` into an instance without any stored schedules.
+#[post("/admin/restore")]
+pub async fn post_restore(req: HttpRequest, payload: web::Payload, pools: web::Data<Pools>) -> impl Responder {
+	if let Some(response) = check_admin(&req) {
+		return response;
+	}
+
+	// The upload is restored while it is received.
+	let backup = StreamReader::new(payload.map_err(|why| std::io::Error::other(why.to_string())));
+
+	match backup::restore(&pools.write, backup).await {
+		Ok(restored) => HttpResponse::Ok().json(Restored {
+			restored,
+		}),
+		Err(why) => HttpResponse::UnprocessableEntity().body(why.to_string()),
+	}
+}
+
+/// Returns the response to send instead if the request isn't allowed to use the admin endpoints.
+/// Either the admin token or the token of a user with the admin role is accepted.
+/// The endpoints pretend not to exist while neither the admin token nor accounts are configured.
+pub fn check_admin(req: &HttpRequest) -> Option<HttpResponse> {
+	if CONFIG.admin_token.is_none() && CONFIG.auth.is_none() {
+		return Some(HttpResponse::NotFound().finish());
+	}
+
+	let provided = auth::bearer_token(req);
+	let is_admin = match provided {
+		// Compared in constant time, so the time of a wrong guess doesn't tell how much of it was right.
+		Some(provided) if CONFIG.admin_token.as_deref().is_some_and(|token| bool::from(token.as_bytes().ct_eq(provided.as_bytes()))) => true,
+		// Users with the admin role are admins as well.
+		Some(provided) => CONFIG.auth.as_ref()
+			.and_then(|config| auth::decode_token(config, provided).ok())
+			.is_some_and(|claims| claims.role == Role::Admin),
+		None => false,
+	};
+
+	if is_admin {
+		None
+	} else {
+		Some(HttpResponse::Unauthorized()
+			.insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+			.finish())
+	}
+}
+
+async fn find_pdf(hash: &str, pool: &PgPool) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+	if let Some(pdf) = quarantine::load(hash).await? {
+		return Ok(Some(pdf));
+	}
+
+	if let Some(pdf) = jobs::load_pdf(pool, hash).await? {
+		return Ok(Some(pdf));
+	}
+
+	if let Some(pdf) = storage::load_pdf(pool, hash).await? {
+		return Ok(Some(pdf));
+	}
+
+	if let Some(pdf) = BLOBS.get(hash).await? {
+		return Ok(Some(pdf.to_vec()));
+	}
+
+	find_in_archive(hash, Path::new(PDF_STORE_LOCATION)).await
+}
+
+/// The archive from before the blob store isn't named by hash, so every pdf in it is hashed until one matches.
+async fn find_in_archive(hash: &str, dir: &Path) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+	let algorithm = HashAlgorithm::of(hash).ok_or("not a hash")?;
+	let mut day_dirs = tokio::fs::read_dir(dir).await?;
+
+	while let Some(day_dir) = day_dirs.next_entry().await? {
+		if !day_dir.file_type().await?.is_dir() {
+			continue;
+		}
+
+		let mut files = tokio::fs::read_dir(day_dir.path()).await?;
+		while let Some(file) = files.next_entry().await? {
+			let pdf = tokio::fs::read(file.path()).await?;
+			if algorithm.hash(&pdf) == hash {
+				return Ok(Some(pdf));
+			}
+		}
+	}
+
+	Ok(None)
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+use reqwest::Client;
+use serde_json::json;
+use tracing::{error, info};
+use crate::{CONFIG, JSON_HANDLER};
+
+const ALERT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodically checks for days that failed longer than configured and POSTs an alert to the operator webhook.
+/// Does nothing if no webhook is configured.
+pub async fn alert_loop(client: Client) {
+	let webhook_url = match &CONFIG.alert.webhook_url {
+		Some(url) => url,
+		None => return,
+	};
+	#[allow(clippy::cast_possible_wrap)]
+	let after_millis = (CONFIG.alert.after_secs * 1000) as i64;
+
+	info!("Alerting {webhook_url} about days failing for more than {}s", CONFIG.alert.after_secs);
+	loop {
+		tokio::time::sleep(ALERT_CHECK_INTERVAL).await;
+
+		let (failing, recovered) = JSON_HANDLER.take_alerts(after_millis).await;
+		let errors = JSON_HANDLER.get_errors().await;
+
+		for (day, status) in failing {
+			let last_error = errors.get(&day).map_or("unknown", |error| error.message.as_str());
+			let message = format!(
+				"Substitution plan for {day} failed to update {} times in a row. Last error: {last_error}",
+				status.consecutive_failures,
+			);
+			let payload = json!({
+				"text": message,
+				"content": message,
+				"day": day,
+				"status": status,
+				"last_error": errors.get(&day),
+			});
+			send_alert(&client, webhook_url, &payload).await;
+		}
+
+		for day in recovered {
+			let message = format!("Substitution plan for {day} is updating again.");
+			let payload = json!({
+				"text": message,
+				"content": message,
+				"day": day,
+			});
+			send_alert(&client, webhook_url, &payload).await;
+		}
+	}
+}
+
+/// POSTs the payload to the webhook, failures are only logged.
+async fn send_alert(client: &Client, webhook_url: &str, payload: &serde_json::Value) {
+	let result = client
+		.post(webhook_url)
+		.json(payload)
+		.send()
+		.await
+		.and_then(reqwest::Response::error_for_status);
+
+	if let Err(why) = result {
+		error!("Couldn't send alert: {why}");
+	}
+}
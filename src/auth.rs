@@ -0,0 +1,274 @@
+use std::future::{ready, Ready};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError};
+use actix_web::dev::Payload;
+use actix_web::http::{header, StatusCode};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use chrono::Utc;
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use lazy_static::lazy_static;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
+use crate::CONFIG;
+use crate::config::AuthConfig;
+
+/// The shortest password that is accepted for an account.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+lazy_static! {
+	/// The hash of a random password, with the same parameters as the hashes of the accounts.
+	static ref DUMMY_HASH: String = {
+		let mut password = [0; 32];
+		rand::thread_rng().fill_bytes(&mut password);
+		hash_password(&hex::encode(password)).expect("A random password can be hashed")
+	};
+}
+
+/// What a user may do, the admin role includes the `/admin` endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+	Student,
+	Teacher,
+	Admin,
+}
+
+impl Role {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Role::Student => "student",
+			Role::Teacher => "teacher",
+			Role::Admin => "admin",
+		}
+	}
+
+	/// Unknown roles are treated as the role with the least rights.
+	pub fn from_name(name: &str) -> Self {
+		match name {
+			"teacher" => Role::Teacher,
+			"admin" => Role::Admin,
+			_ => Role::Student,
+		}
+	}
+}
+
+/// The claims of the tokens issued on login.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+	/// The id of the user.
+	pub sub: i64,
+	pub name: String,
+	pub role: Role,
+	/// When the token expires, in seconds since the unix epoch.
+	pub exp: i64,
+}
+
+/// A stored account.
+#[derive(Debug, Clone, Serialize)]
+pub struct User {
+	pub id: i64,
+	pub username: String,
+	pub role: Role,
+	/// `None` for accounts of the OpenID Connect provider.
+	#[serde(skip)]
+	password_hash: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+	#[error("Accounts are disabled")]
+	Disabled,
+	#[error("Missing bearer token")]
+	MissingToken,
+	#[error("Invalid token: {0}")]
+	InvalidToken(#[from] jsonwebtoken::errors::Error),
+}
+
+impl ResponseError for AuthError {
+	fn status_code(&self) -> StatusCode {
+		match self {
+			AuthError::Disabled => StatusCode::NOT_FOUND,
+			AuthError::MissingToken | AuthError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+		}
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		match self {
+			AuthError::Disabled => HttpResponse::NotFound().finish(),
+			_ => HttpResponse::Unauthorized()
+				.insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+				.body(self.to_string()),
+		}
+	}
+}
+
+/// Extracts the claims of the bearer token of a request, rejecting it with `401` if the token is missing or invalid.
+impl FromRequest for Claims {
+	type Error = AuthError;
+	type Future = Ready<Result<Self, Self::Error>>;
+
+	fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+		ready(claims_of(req))
+	}
+}
+
+fn claims_of(req: &HttpRequest) -> Result<Claims, AuthError> {
+	let config = CONFIG.auth.as_ref().ok_or(AuthError::Disabled)?;
+
+	let token = bearer_token(req).ok_or(AuthError::MissingToken)?;
+	Ok(decode_token(config, token)?)
+}
+
+/// The token of the `Authorization: Bearer` header.
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+	req.headers()
+		.get(header::AUTHORIZATION)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Issues a token for `user` that is valid for `token_lifetime_secs`.
+///
+/// # Errors
+///
+/// Returns `Err` if the token couldn't be encoded.
+pub fn issue_token(config: &AuthConfig, user: &User) -> Result<String, jsonwebtoken::errors::Error> {
+	let claims = Claims {
+		sub: user.id,
+		name: user.username.clone(),
+		role: user.role,
+		#[allow(clippy::cast_possible_wrap)]
+		exp: Utc::now().timestamp() + config.token_lifetime_secs as i64,
+	};
+
+	jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(config.jwt_secret.as_bytes()))
+}
+
+/// Decodes the token and checks its signature and expiry.
+///
+/// # Errors
+///
+/// Returns `Err` if the token is invalid or expired.
+pub fn decode_token(config: &AuthConfig, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+	jsonwebtoken::decode(token, &DecodingKey::from_secret(config.jwt_secret.as_bytes()), &Validation::default())
+		.map(|data| data.claims)
+}
+
+impl User {
+	/// Accounts without a password, like the ones created by OpenID Connect, take as long to reject as wrong passwords.
+	pub fn verify_password(&self, password: &str) -> bool {
+		match self.password_hash.as_deref() {
+			Some(hash) => verify(hash, password),
+			None => {
+				reject_password(password);
+				false
+			}
+		}
+	}
+}
+
+/// Verifies `password` against a hash no password matches, so unknown usernames take as long to reject as known ones.
+pub fn reject_password(password: &str) {
+	let _ = verify(&DUMMY_HASH, password);
+}
+
+fn verify(hash: &str, password: &str) -> bool {
+	PasswordHash::new(hash)
+		.is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+	let mut salt = [0; 16];
+	rand::thread_rng().fill_bytes(&mut salt);
+	let salt = SaltString::encode_b64(&salt)?;
+
+	Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Creates an account with `role`.
+/// Returns `None` if the username is taken.
+///
+/// # Errors
+///
+/// Returns `Err` if the password couldn't be hashed or the user couldn't be stored.
+pub async fn create_user(pool: &PgPool, username: &str, password: &str, role: Role) -> Result<Option<User>, Box<dyn std::error::Error>> {
+	let password_hash = hash_password(password).map_err(|why| why.to_string())?;
+
+	let row = sqlx::query!(
+		r#"
+		INSERT INTO users (username, password_hash, role, created_at)
+		VALUES ($1, $2, $3, $4)
+		ON CONFLICT (username) DO NOTHING
+		RETURNING id
+		"#,
+		username,
+		password_hash,
+		role.as_str(),
+		Utc::now().naive_utc()
+	)
+		.fetch_optional(pool)
+		.await?;
+
+	Ok(row.map(|row| User {
+		id: row.id,
+		username: username.to_string(),
+		role,
+		password_hash: Some(password_hash),
+	}))
+}
+
+/// Looks up an account by its username.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be queried.
+pub async fn find_user(pool: &PgPool, username: &str) -> Result<Option<User>, sqlx::Error> {
+	let row = sqlx::query!("SELECT id, username, password_hash, role FROM users WHERE username = $1", username)
+		.fetch_optional(pool)
+		.await?;
+
+	Ok(row.map(|row| User {
+		id: row.id,
+		username: row.username,
+		role: Role::from_name(&row.role),
+		password_hash: row.password_hash,
+	}))
+}
+
+/// Creates or updates the account of a user of the OpenID Connect provider, identified by its subject.
+/// The role is taken from the provider on every login.
+/// Returns `None` if the username belongs to a local account.
+///
+/// # Errors
+///
+/// Returns `Err` if the user couldn't be stored.
+pub async fn upsert_oidc_user(pool: &PgPool, subject: &str, username: &str, role: Role) -> Result<Option<User>, sqlx::Error> {
+	let row = sqlx::query!(
+		r#"
+		INSERT INTO users (username, role, oidc_subject, created_at)
+		VALUES ($1, $2, $3, $4)
+		ON CONFLICT (oidc_subject) DO UPDATE SET username = EXCLUDED.username, role = EXCLUDED.role
+		RETURNING id
+		"#,
+		username,
+		role.as_str(),
+		subject,
+		Utc::now().naive_utc()
+	)
+		.fetch_one(pool)
+		.await;
+
+	match row {
+		Ok(row) => Ok(Some(User {
+			id: row.id,
+			username: username.to_string(),
+			role,
+			password_hash: None,
+		})),
+		// unique_violation, the username belongs to another account.
+		Err(sqlx::Error::Database(why)) if why.code().as_deref() == Some("23505") => Ok(None),
+		Err(why) => Err(why),
+	}
+}
@@ -0,0 +1,196 @@
+use std::time::Duration;
+use actix_web::{get, HttpRequest, HttpResponse, post, Responder, web};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::http::header;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use crate::auth::{self, Claims, MIN_PASSWORD_LENGTH, Role};
+use crate::{CONFIG, oidc};
+use crate::config::OidcConfig;
+use crate::storage::Pools;
+
+#[derive(Debug, Deserialize)]
+pub struct Credentials {
+	username: String,
+	password: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenResponse {
+	token: String,
+	/// Seconds until the token expires.
+	expires_in: u64,
+}
+
+/// Creates a student account and returns a token for it, admins are created with the `create-admin` subcommand.
+#[post("/auth/register")]
+pub async fn post_register(credentials: web::Json<Credentials>, pools: web::Data<Pools>) -> impl Responder {
+	let config = match &CONFIG.auth {
+		Some(config) if config.registration => config,
+		_ => return HttpResponse::NotFound().finish(),
+	};
+
+	let username = credentials.username.trim();
+	if username.is_empty() {
+		return HttpResponse::BadRequest().body("The username is empty");
+	}
+	if credentials.password.chars().count() < MIN_PASSWORD_LENGTH {
+		return HttpResponse::BadRequest().body(format!("The password has to be at least {MIN_PASSWORD_LENGTH} characters long"));
+	}
+
+	let user = match auth::create_user(&pools.write, username, &credentials.password, Role::Student).await {
+		Ok(Some(user)) => user,
+		Ok(None) => return HttpResponse::Conflict().body("The username is taken"),
+		Err(why) => {
+			error!("Couldn't create the user {username}: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	info!("Registered the user {username}");
+	match auth::issue_token(config, &user) {
+		Ok(token) => HttpResponse::Created().json(TokenResponse {
+			token,
+			expires_in: config.token_lifetime_secs,
+		}),
+		Err(why) => {
+			error!("Couldn't issue a token: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// Returns a token if the username and password match.
+/// Unknown usernames are rejected after verifying a dummy hash, so the response time doesn't tell which accounts exist.
+#[post("/auth/login")]
+pub async fn post_login(credentials: web::Json<Credentials>, pools: web::Data<Pools>) -> impl Responder {
+	let config = match &CONFIG.auth {
+		Some(config) => config,
+		None => return HttpResponse::NotFound().finish(),
+	};
+
+	let user = match auth::find_user(&pools.read, credentials.username.trim()).await {
+		Ok(Some(user)) if user.verify_password(&credentials.password) => user,
+		Ok(Some(_)) => return HttpResponse::Unauthorized().body("Wrong username or password"),
+		Ok(None) => {
+			auth::reject_password(&credentials.password);
+			return HttpResponse::Unauthorized().body("Wrong username or password");
+		}
+		Err(why) => {
+			error!("Couldn't look up the user: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	match auth::issue_token(config, &user) {
+		Ok(token) => HttpResponse::Ok().json(TokenResponse {
+			token,
+			expires_in: config.token_lifetime_secs,
+		}),
+		Err(why) => {
+			error!("Couldn't issue a token: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// The user the token belongs to.
+#[get("/me")]
+pub async fn get_me(claims: Claims) -> impl Responder {
+	HttpResponse::Ok().json(claims)
+}
+
+/// Redirects to the login of the OpenID Connect provider.
+/// The `state` of the login is kept in a cookie, so the callback only succeeds in the browser that started it.
+#[get("/auth/oidc/login")]
+pub async fn get_oidc_login() -> impl Responder {
+	let (config, oidc_config) = match &CONFIG.auth {
+		Some(config) => match &config.oidc {
+			Some(oidc_config) => (config, oidc_config),
+			None => return HttpResponse::NotFound().finish(),
+		},
+		None => return HttpResponse::NotFound().finish(),
+	};
+
+	match oidc::start_login(oidc_config, &config.jwt_secret).await {
+		Ok(login) => HttpResponse::Found()
+			.insert_header((header::LOCATION, login.url.as_str()))
+			.cookie(state_cookie(oidc_config, login.cookie, oidc::LOGIN_TIMEOUT))
+			.finish(),
+		Err(why) => {
+			error!("Couldn't start the OpenID Connect login: {why}");
+			HttpResponse::BadGateway().finish()
+		}
+	}
+}
+
+/// The cookie holding the `state` of a login, sent back on the redirect from the provider.
+fn state_cookie(config: &OidcConfig, value: String, max_age: Duration) -> Cookie<'static> {
+	#[allow(clippy::cast_possible_wrap)]
+		let max_age = CookieDuration::seconds(max_age.as_secs() as i64);
+
+	Cookie::build(oidc::STATE_COOKIE, value)
+		.path("/")
+		.http_only(true)
+		.secure(config.redirect_uri.starts_with("https://"))
+		// The provider redirects back with a top level navigation, which `Lax` cookies are sent with.
+		.same_site(SameSite::Lax)
+		.max_age(max_age)
+		.finish()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+	code: String,
+	state: String,
+}
+
+/// Where the provider redirects to after the login, creates the account on the first login.
+#[get("/auth/oidc/callback")]
+pub async fn get_oidc_callback(req: HttpRequest, query: web::Query<CallbackQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let (config, oidc_config) = match &CONFIG.auth {
+		Some(config) => match &config.oidc {
+			Some(oidc_config) => (config, oidc_config),
+			None => return HttpResponse::NotFound().finish(),
+		},
+		None => return HttpResponse::NotFound().finish(),
+	};
+
+	let cookie = req.cookie(oidc::STATE_COOKIE);
+	let oidc_user = match oidc::finish_login(oidc_config, &config.jwt_secret, &query.code, &query.state, cookie.as_ref().map(Cookie::value)).await {
+		Ok(user) => user,
+		Err(why) => return HttpResponse::Unauthorized().body(why.to_string()),
+	};
+
+	let user = match auth::upsert_oidc_user(&pools.write, &oidc_user.subject, &oidc_user.username, oidc_user.role).await {
+		Ok(Some(user)) => user,
+		Ok(None) => return HttpResponse::Conflict().body("The username belongs to a local account"),
+		Err(why) => {
+			error!("Couldn't store the user {}: {why}", oidc_user.username);
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	let token = match auth::issue_token(config, &user) {
+		Ok(token) => token,
+		Err(why) => {
+			error!("Couldn't issue a token: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	info!("{} logged in with OpenID Connect as {}", user.username, user.role.as_str());
+	// The state was used up.
+	let removal = state_cookie(oidc_config, String::new(), Duration::ZERO);
+	match &oidc_config.post_login_redirect {
+		Some(redirect) => HttpResponse::Found()
+			.insert_header((header::LOCATION, format!("{redirect}#token={token}")))
+			.cookie(removal)
+			.finish(),
+		None => HttpResponse::Ok().cookie(removal).json(TokenResponse {
+			token,
+			expires_in: config.token_lifetime_secs,
+		}),
+	}
+}
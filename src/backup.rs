@@ -0,0 +1,170 @@
+use std::io::Write;
+use async_compression::tokio::bufread::GzipDecoder;
+use bytes::Bytes;
+use chrono::NaiveDateTime;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+use crate::{blob_store, storage};
+
+/// Version of the backup format, restoring refuses other versions.
+const FORMAT_VERSION: u32 = 1;
+/// Compressed bytes collected before they are sent as a chunk.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A line of a backup. A backup is gzip compressed json lines, starting with the header.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+	Header {
+		version: u32,
+	},
+	/// A row of `substitution_json`.
+	Schedule {
+		hash: String,
+		pdf_date: NaiveDateTime,
+		insertion_time: Option<NaiveDateTime>,
+		day: Option<i16>,
+		json: Option<serde_json::Value>,
+		/// The hex encoded pdf, missing in backups from before pdfs were stored.
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		pdf: Option<String>,
+	},
+}
+
+/// Writes a backup of all stored schedules into `chunks`, stopping early if the receiver is dropped.
+/// Pending conversion jobs aren't part of it, they are retried from the pdf archive instead.
+pub async fn write_backup(pool: PgPool, chunks: mpsc::Sender<Result<Bytes, std::io::Error>>) {
+	if let Err(why) = write_records(&pool, &chunks).await {
+		error!("Writing the backup failed: {why}");
+		let _ = chunks.send(Err(std::io::Error::other(why.to_string()))).await;
+	}
+}
+
+/// Writes a backup into the blob store, returns its key.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read or the backup couldn't be stored.
+pub async fn archive(pool: PgPool) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+	let (sender, mut receiver) = mpsc::channel(4);
+	tokio::spawn(write_backup(pool, sender));
+
+	let mut backup = Vec::new();
+	while let Some(chunk) = receiver.recv().await {
+		backup.extend_from_slice(&chunk?);
+	}
+
+	Ok(blob_store::archive(Bytes::from(backup), ".jsonl.gz").await?)
+}
+
+async fn write_records(pool: &PgPool, chunks: &mpsc::Sender<Result<Bytes, std::io::Error>>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+	let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+	write_record(&mut encoder, &Record::Header {
+		version: FORMAT_VERSION,
+	})?;
+
+	let hashes = sqlx::query_scalar!(r#"SELECT hash AS "hash!" FROM substitution_json WHERE hash IS NOT NULL ORDER BY pdf_date"#)
+		.fetch_all(pool)
+		.await?;
+
+	for hash in hashes {
+		let row = sqlx::query!("SELECT pdf_date, insertion_time, day, pdf FROM substitution_json WHERE hash = $1", hash)
+			.fetch_one(pool)
+			.await?;
+		// Superseded schedules are stored as patches, backups hold every schedule in full.
+		let json = storage::load_json(pool, &hash).await?;
+
+		write_record(&mut encoder, &Record::Schedule {
+			hash,
+			pdf_date: row.pdf_date,
+			insertion_time: row.insertion_time,
+			day: row.day,
+			json,
+			pdf: row.pdf.map(hex::encode),
+		})?;
+
+		if encoder.get_ref().len() >= CHUNK_SIZE {
+			let chunk = std::mem::take(encoder.get_mut());
+			if chunks.send(Ok(Bytes::from(chunk))).await.is_err() {
+				info!("The backup was aborted by the client");
+				return Ok(());
+			}
+		}
+	}
+
+	let rest = encoder.finish()?;
+	let _ = chunks.send(Ok(Bytes::from(rest))).await;
+
+	Ok(())
+}
+
+fn write_record(encoder: &mut GzEncoder<Vec<u8>>, record: &Record) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+	serde_json::to_writer(&mut *encoder, record)?;
+	encoder.write_all(b"\n")?;
+	Ok(())
+}
+
+/// Loads a backup into the database, which has to be empty so nothing is silently merged.
+/// The backup is decompressed while it is read, so only a line of it is held in memory at a time.
+/// Returns the number of restored schedules.
+///
+/// # Errors
+///
+/// Returns `Err` if the database isn't empty, the backup is invalid or writing it failed.
+/// Nothing is restored in that case.
+pub async fn restore<R: AsyncBufRead + Unpin>(pool: &PgPool, backup: R) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+	let has_schedules = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM substitution_json)")
+		.fetch_one(pool)
+		.await?
+		.unwrap_or(false);
+	if has_schedules {
+		return Err("Backups can only be restored into an empty database".into());
+	}
+
+	let mut lines = BufReader::new(GzipDecoder::new(backup)).lines();
+	match lines.next_line().await?.map(|line| serde_json::from_str(&line)).transpose()? {
+		Some(Record::Header { version: FORMAT_VERSION }) => {}
+		Some(Record::Header { version }) => return Err(format!("Unsupported backup version {version}").into()),
+		_ => return Err("The backup doesn't start with a header".into()),
+	}
+
+	let mut transaction = pool.begin().await?;
+	let mut restored = 0;
+	while let Some(line) = lines.next_line().await? {
+		if line.is_empty() {
+			continue;
+		}
+
+		match serde_json::from_str(&line)? {
+			Record::Schedule { hash, pdf_date, insertion_time, day, json, pdf } => {
+				let pdf = pdf.map(hex::decode).transpose()?;
+				sqlx::query!(
+					r#"
+					INSERT INTO substitution_json (hash, pdf_date, insertion_time, json, day, pdf, hash_algorithm)
+					VALUES($1, $2, $3, $4, $5, $6, $7)
+					"#,
+					hash,
+					pdf_date,
+					insertion_time,
+					json,
+					day,
+					pdf,
+					storage::hash_algorithm(&hash)
+				)
+					.execute(&mut transaction)
+					.await?;
+				restored += 1;
+			}
+			Record::Header { .. } => return Err("The backup has more than one header".into()),
+		}
+	}
+	transaction.commit().await?;
+
+	info!("Restored {restored} schedules");
+	Ok(restored)
+}
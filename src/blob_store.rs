@@ -0,0 +1,236 @@
+use std::path::PathBuf;
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use reqwest::{Client, Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{debug, info};
+use crate::{CONFIG, hashing};
+use crate::config::{BlobStoreConfig, S3Config};
+
+lazy_static! {
+	/// The store pdfs and exports are archived in, see [`BlobStoreConfig`].
+	pub static ref BLOBS: Box<dyn BlobStore> = from_config(&CONFIG.blobs);
+}
+
+#[derive(Debug, Error)]
+pub enum BlobError {
+	#[error(transparent)]
+	Io(#[from] std::io::Error),
+	#[error(transparent)]
+	Http(#[from] reqwest::Error),
+	#[error("The bucket answered with {0}: {1}")]
+	Status(StatusCode, String),
+	#[error("Invalid S3 endpoint {0}")]
+	InvalidEndpoint(String),
+}
+
+/// Stores blobs by key, which the callers derive from the content, so a key is never overwritten with other bytes.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+	fn name(&self) -> &'static str;
+
+	async fn put(&self, key: &str, blob: Bytes) -> Result<(), BlobError>;
+
+	/// Reads the blob, `None` if there is none with this key.
+	async fn get(&self, key: &str) -> Result<Option<Bytes>, BlobError>;
+
+	async fn contains(&self, key: &str) -> Result<bool, BlobError>;
+}
+
+/// Builds the configured store.
+pub fn from_config(config: &BlobStoreConfig) -> Box<dyn BlobStore> {
+	match config {
+		BlobStoreConfig::Disk { directory } => {
+			info!("Archiving into {directory}");
+			Box::new(DiskStore::new(directory))
+		}
+		BlobStoreConfig::S3(config) => {
+			info!("Archiving into the bucket {} at {}", config.bucket, config.endpoint);
+			Box::new(S3Store::new(config.clone()))
+		}
+	}
+}
+
+/// Stores the blob under the hash of its content followed by `suffix`, like the hashes of the schedules.
+/// Blobs that are stored already aren't uploaded again. Returns the key.
+///
+/// # Errors
+///
+/// Returns `Err` if the store couldn't be read or written.
+pub async fn archive(blob: Bytes, suffix: &str) -> Result<String, BlobError> {
+	let key = format!("{}{suffix}", hashing::hash(&blob));
+	if BLOBS.contains(&key).await? {
+		debug!("{key} is archived already");
+		return Ok(key);
+	}
+
+	BLOBS.put(&key, blob).await?;
+	debug!("Archived {key} in the {} store", BLOBS.name());
+	Ok(key)
+}
+
+/// Keeps every blob as a file named like its key in a directory.
+pub struct DiskStore {
+	directory: PathBuf,
+}
+
+impl DiskStore {
+	pub fn new(directory: impl Into<PathBuf>) -> Self {
+		Self {
+			directory: directory.into(),
+		}
+	}
+}
+
+#[async_trait]
+impl BlobStore for DiskStore {
+	fn name(&self) -> &'static str {
+		"disk"
+	}
+
+	async fn put(&self, key: &str, blob: Bytes) -> Result<(), BlobError> {
+		tokio::fs::create_dir_all(&self.directory).await?;
+
+		// Renaming is atomic, so a crash can't leave a partial blob under the key.
+		let partial = self.directory.join(format!("{key}.partial"));
+		tokio::fs::write(&partial, &blob).await?;
+		tokio::fs::rename(&partial, self.directory.join(key)).await?;
+
+		Ok(())
+	}
+
+	async fn get(&self, key: &str) -> Result<Option<Bytes>, BlobError> {
+		match tokio::fs::read(self.directory.join(key)).await {
+			Ok(blob) => Ok(Some(Bytes::from(blob))),
+			Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(None),
+			Err(why) => Err(why.into()),
+		}
+	}
+
+	async fn contains(&self, key: &str) -> Result<bool, BlobError> {
+		Ok(tokio::fs::try_exists(self.directory.join(key)).await?)
+	}
+}
+
+/// Keeps the blobs in a bucket of S3 or a compatible service like MinIO or Garage.
+/// Requests are signed with AWS Signature Version 4.
+pub struct S3Store {
+	client: Client,
+	config: S3Config,
+}
+
+impl S3Store {
+	pub fn new(config: S3Config) -> Self {
+		Self {
+			client: Client::new(),
+			config,
+		}
+	}
+
+	/// The url of the object, in the path of the endpoint or as a subdomain of it.
+	fn object_url(&self, key: &str) -> Result<Url, BlobError> {
+		let invalid = || BlobError::InvalidEndpoint(self.config.endpoint.clone());
+		let mut url = Url::parse(&self.config.endpoint).map_err(|_| invalid())?;
+
+		if !self.config.path_style {
+			let host = format!("{}.{}", self.config.bucket, url.host_str().ok_or_else(invalid)?);
+			url.set_host(Some(&host)).map_err(|_| invalid())?;
+		}
+
+		{
+			let mut segments = url.path_segments_mut().map_err(|_| invalid())?;
+			segments.pop_if_empty();
+			if self.config.path_style {
+				segments.push(&self.config.bucket);
+			}
+			segments.extend(self.config.prefix.split('/').filter(|segment| !segment.is_empty()));
+			segments.push(key);
+		}
+
+		Ok(url)
+	}
+
+	/// Sends a signed request for the object with `body` as its content.
+	async fn send(&self, method: Method, key: &str, body: Bytes) -> Result<reqwest::Response, BlobError> {
+		let url = self.object_url(key)?;
+		let now = Utc::now();
+		let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+		let date = now.format("%Y%m%d").to_string();
+		let payload_hash = hex::encode(Sha256::digest(&body));
+
+		let host = match url.port() {
+			Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+			None => url.host_str().unwrap_or_default().to_string(),
+		};
+		let canonical_request = format!(
+			"{method}\n{}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\nhost;x-amz-content-sha256;x-amz-date\n{payload_hash}",
+			url.path()
+		);
+
+		let scope = format!("{date}/{}/s3/aws4_request", self.config.region);
+		let string_to_sign = format!("AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}", hex::encode(Sha256::digest(canonical_request.as_bytes())));
+
+		let mut key = hmac(format!("AWS4{}", self.config.secret_key).as_bytes(), date.as_bytes());
+		for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+			key = hmac(&key, part.as_bytes());
+		}
+		let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+		let authorization = format!(
+			"AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={signature}",
+			self.config.access_key
+		);
+
+		Ok(self.client
+			.request(method, url)
+			.header("x-amz-date", amz_date)
+			.header("x-amz-content-sha256", payload_hash)
+			.header("authorization", authorization)
+			.body(body)
+			.send()
+			.await?)
+	}
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+	fn name(&self) -> &'static str {
+		"s3"
+	}
+
+	async fn put(&self, key: &str, blob: Bytes) -> Result<(), BlobError> {
+		let response = self.send(Method::PUT, key, blob).await?;
+		match response.status() {
+			status if status.is_success() => Ok(()),
+			status => Err(BlobError::Status(status, response.text().await.unwrap_or_default())),
+		}
+	}
+
+	async fn get(&self, key: &str) -> Result<Option<Bytes>, BlobError> {
+		let response = self.send(Method::GET, key, Bytes::new()).await?;
+		match response.status() {
+			StatusCode::NOT_FOUND => Ok(None),
+			status if status.is_success() => Ok(Some(response.bytes().await?)),
+			status => Err(BlobError::Status(status, response.text().await.unwrap_or_default())),
+		}
+	}
+
+	async fn contains(&self, key: &str) -> Result<bool, BlobError> {
+		let response = self.send(Method::HEAD, key, Bytes::new()).await?;
+		match response.status() {
+			StatusCode::NOT_FOUND => Ok(false),
+			status if status.is_success() => Ok(true),
+			status => Err(BlobError::Status(status, String::new())),
+		}
+	}
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+	let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+	mac.update(message);
+	mac.finalize().into_bytes().to_vec()
+}
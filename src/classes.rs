@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+use regex::Regex;
+use substitution_pdf_to_json::{ClassName, ClassNameNormalizer};
+use crate::CONFIG;
+
+lazy_static! {
+	/// Normalizes class names from the PDFs and from requests the same way.
+	pub static ref NORMALIZER: ClassNameNormalizer = ClassNameNormalizer::new(&CONFIG.class_names)
+		.expect("Invalid class name rewrite");
+	static ref CLASS_GROUPS: HashMap<String, Vec<Regex>> = compile_groups(&CONFIG.class_groups)
+		.expect("Invalid class group pattern");
+}
+
+/// Compiles the member patterns of every class group.
+///
+/// # Errors
+///
+/// Returns `Err` if a pattern is not a valid regex.
+pub fn compile_groups(groups: &HashMap<String, Vec<String>>) -> Result<HashMap<String, Vec<Regex>>, regex::Error> {
+	groups
+		.iter()
+		.map(|(group, patterns)| {
+			let patterns = patterns
+				.iter()
+				.map(|pattern| Regex::new(pattern))
+				.collect::<Result<Vec<Regex>, regex::Error>>()?;
+			Ok((group.clone(), patterns))
+		})
+		.collect()
+}
+
+/// Selects classes by the name a client asked for.
+pub enum ClassSelector {
+	/// A single class, with its name normalized like the class names from the PDFs.
+	Class(ClassName),
+	/// A configured group, matching every class one of its patterns matches.
+	Group(&'static [Regex]),
+}
+
+impl ClassSelector {
+	/// Resolves a requested name, groups take precedence over classes with the same name.
+	pub fn new(requested: &str) -> Self {
+		match CLASS_GROUPS.get(requested) {
+			Some(patterns) => ClassSelector::Group(patterns),
+			None => ClassSelector::Class(NORMALIZER.normalize(requested)),
+		}
+	}
+
+	pub fn matches(&self, class: &ClassName) -> bool {
+		match self {
+			ClassSelector::Class(selected) => selected == class,
+			ClassSelector::Group(patterns) => patterns.iter().any(|pattern| pattern.is_match(class)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn group(patterns: &[&str]) -> ClassSelector {
+		let groups = HashMap::from([("11".to_string(), patterns.iter().map(|pattern| pattern.to_string()).collect())]);
+		let mut compiled = compile_groups(&groups).unwrap();
+		ClassSelector::Group(Box::leak(compiled.remove("11").unwrap().into_boxed_slice()))
+	}
+
+	#[test]
+	fn groups_match_every_member() {
+		let selector = group(&["^11", "^BGT11"]);
+		assert!(selector.matches(&ClassName::from("11A")));
+		assert!(selector.matches(&ClassName::from("BGT11-1")));
+		assert!(!selector.matches(&ClassName::from("10A")));
+	}
+
+	#[test]
+	fn classes_only_match_themselves() {
+		let selector = ClassSelector::Class(ClassName::from("11A"));
+		assert!(selector.matches(&ClassName::from("11A")));
+		assert!(!selector.matches(&ClassName::from("11B")));
+	}
+
+	#[test]
+	fn invalid_group_patterns_are_rejected() {
+		let groups = HashMap::from([("11".to_string(), vec!["(".to_string()])]);
+		assert!(compile_groups(&groups).is_err());
+	}
+}
+
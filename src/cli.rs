@@ -0,0 +1,118 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use clap::{Parser, Subcommand};
+use tracing::info;
+use crate::{auth, CONFIG, conversion, import};
+use crate::auth::{MIN_PASSWORD_LENGTH, Role};
+use crate::classes::NORMALIZER;
+use crate::config::DEFAULT_CONFIG_PATH;
+use crate::storage::{self, Pools};
+
+/// Serves the substitution schedules of the school as json, converted from the published pdfs.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+	/// The config file, every missing key falls back to its default.
+	#[arg(short, long, env = "CONFIG_PATH", default_value = DEFAULT_CONFIG_PATH)]
+	pub config: PathBuf,
+	/// The address the http server listens on.
+	#[arg(short, long, env = "BIND_ADDRESS", default_value = "127.0.0.1:8081")]
+	pub bind: String,
+	/// A tracing filter like `info` or `substitution_pdf_server=trace`, replacing `RUST_LOG`.
+	#[arg(short, long)]
+	pub log_level: Option<String>,
+	#[command(subcommand)]
+	pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// Fetches the pdfs and serves the schedules, the default.
+	Serve,
+	/// Converts a pdf and prints the schedules of the days it covers as json, without a database.
+	Convert {
+		pdf: PathBuf,
+		/// Indents the json.
+		#[arg(long)]
+		pretty: bool,
+	},
+	/// Converts the pdfs of a directory and stores the ones that aren't stored yet, like `/admin/import`.
+	Import {
+		directory: PathBuf,
+	},
+	/// Creates an admin account, registration only creates student accounts.
+	CreateAdmin {
+		username: String,
+		/// Read from the environment, so it doesn't show up in the process list or the shell history.
+		#[arg(long, env = "ADMIN_PASSWORD", hide_env_values = true)]
+		password: String,
+	},
+}
+
+/// Converts the pdf at `path` and prints its schedules.
+///
+/// # Errors
+///
+/// Returns `Err` if the pdf couldn't be read or converted.
+pub async fn convert(path: &Path, pretty: bool) -> Result<(), Box<dyn std::error::Error>> {
+	let pdf = tokio::fs::read(path).await?;
+	let temp_dir = tempfile::tempdir()?;
+	let (mut schedules, timings) = conversion::convert_pdf(&pdf, temp_dir.path()).await.map_err(|why| why.to_string())?;
+	info!("Converted {} in {:?}", path.display(), timings.tabula + timings.parse);
+
+	for schedule in &mut schedules {
+		schedule.normalize_class_names(&NORMALIZER);
+	}
+
+	let json = if pretty {
+		serde_json::to_string_pretty(&schedules)?
+	} else {
+		serde_json::to_string(&schedules)?
+	};
+	println!("{json}");
+
+	Ok(())
+}
+
+/// Imports the pdfs in `directory` into the database at `DATABASE_URL` and prints the report.
+///
+/// # Errors
+///
+/// Returns `Err` if the database or the directory couldn't be accessed.
+pub async fn import(directory: &Path) -> Result<(), Box<dyn std::error::Error>> {
+	let pools = Pools::connect_lazy(env::var("DATABASE_URL").expect("Couldn't find DB URL in env!").as_str(), &CONFIG.database)?;
+	storage::migrate(&pools.write).await?;
+
+	let report = import::import_dir(directory, &pools.write).await?;
+	println!("{}", serde_json::to_string_pretty(&report)?);
+
+	Ok(())
+}
+
+/// Creates the admin account `username` in the database at `DATABASE_URL`.
+///
+/// # Errors
+///
+/// Returns `Err` if the password is too short, the username is taken or the account couldn't be stored.
+pub async fn create_admin(username: &str, password: &str) -> Result<(), Box<dyn std::error::Error>> {
+	if CONFIG.auth.is_none() {
+		return Err("Accounts are disabled, configure [auth] first".into());
+	}
+	let username = username.trim();
+	if username.is_empty() {
+		return Err("The username is empty".into());
+	}
+	if password.chars().count() < MIN_PASSWORD_LENGTH {
+		return Err(format!("The password has to be at least {MIN_PASSWORD_LENGTH} characters long").into());
+	}
+
+	let pools = Pools::connect_lazy(env::var("DATABASE_URL").expect("Couldn't find DB URL in env!").as_str(), &CONFIG.database)?;
+	storage::migrate(&pools.write).await?;
+
+	match auth::create_user(&pools.write, username, password, Role::Admin).await? {
+		Some(user) => info!("Created the admin {} with the id {}", user.username, user.id),
+		None => return Err(format!("The username {username} is taken").into()),
+	}
+
+	Ok(())
+}
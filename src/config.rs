@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+use std::path::Path;
+use serde::Deserialize;
+use substitution_pdf_to_json::NormalizationConfig;
+use tracing::info;
+use crate::{PDF_STORE_LOCATION, Schoolday, SOURCE_URLS};
+use crate::auth::Role;
+
+pub const DEFAULT_CONFIG_PATH: &str = "./config.toml";
+
+/// Runtime configuration of the server.
+/// Read from the toml file passed as `--config` or `CONFIG_PATH` (default `./config.toml`), every missing key falls back to its default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+	/// Number of consecutive failed updates after which a day is escalated and the server reports itself as not ready.
+	pub failure_threshold: u32,
+	/// How many pdfs are converted at once at most, each conversion runs a JVM.
+	pub max_conversions: usize,
+	/// The hash telling whether a pdf changed, which the schedules and archived pdfs are stored under.
+	pub hash_algorithm: HashAlgorithm,
+	pub alert: AlertConfig,
+	pub source: SourceConfig,
+	pub schedule: ScheduleConfig,
+	pub database: DatabaseConfig,
+	pub stateless: StatelessConfig,
+	pub http: HttpConfig,
+	/// Request budgets of the clients by tier, enforced on every endpoint.
+	pub quotas: QuotaConfig,
+	pub convert: ConvertConfig,
+	/// How class names are cleaned up before the schedule is keyed by them.
+	pub class_names: NormalizationConfig,
+	/// Named groups of classes that can be requested like a single class.
+	/// Every member is a regex, a class belongs to the group if any of them matches its normalized name.
+	pub class_groups: HashMap<String, Vec<String>>,
+	/// Key the bodies of all responses are signed with, see `X-Signature`. Signing is disabled if this is not set.
+	pub signing_key: Option<String>,
+	/// Origins browsers may call the api from, any origin if empty.
+	pub cors_origins: Vec<String>,
+	/// Bearer token the `/admin` endpoints require. They are disabled if this is not set.
+	pub admin_token: Option<String>,
+	/// User accounts, authenticated with JWTs. Disabled if this is not set.
+	pub auth: Option<AuthConfig>,
+	/// What requests for the current day or a date get on weekends.
+	pub weekend_fallback: WeekendFallback,
+	pub preview: PreviewConfig,
+	/// Where the downloaded pdfs and archived backups are kept.
+	pub blobs: BlobStoreConfig,
+	pub notify: NotifyConfig,
+	/// Posting the changes of the schedules to Matrix rooms. Disabled if this is not set.
+	pub matrix: Option<MatrixConfig>,
+	/// Posting a status to Mastodon whenever a schedule changes. Disabled if this is not set.
+	pub mastodon: Option<MastodonConfig>,
+	/// Publishing the schedules to an MQTT broker whenever they change. Disabled if this is not set.
+	pub mqtt: Option<MqttConfig>,
+	/// Publishing an event to NATS whenever a schedule changes. Disabled if this is not set.
+	pub events: Option<EventsConfig>,
+}
+
+/// The accounts and the tokens issued to them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthConfig {
+	/// Secret the tokens are signed with.
+	pub jwt_secret: String,
+	/// Seconds a token is valid for.
+	#[serde(default = "default_token_lifetime_secs")]
+	pub token_lifetime_secs: u64,
+	/// Whether anyone can create a student account.
+	#[serde(default)]
+	pub registration: bool,
+	/// Logging in with an OpenID Connect provider. Disabled if this is not set.
+	pub oidc: Option<OidcConfig>,
+}
+
+/// The OpenID Connect provider users can log in with instead of a local account.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfig {
+	/// The provider is discovered at `<issuer>/.well-known/openid-configuration`.
+	pub issuer: String,
+	pub client_id: String,
+	pub client_secret: String,
+	/// The url of `/auth/oidc/callback` as the provider redirects to it.
+	pub redirect_uri: String,
+	#[serde(default = "default_scopes")]
+	pub scopes: String,
+	/// The claim of the userinfo listing the groups of the user.
+	#[serde(default = "default_groups_claim")]
+	pub groups_claim: String,
+	/// The role of the members of a group, users in several groups get the role with the most rights.
+	#[serde(default)]
+	pub role_groups: HashMap<String, Role>,
+	/// Where the user is sent after logging in, with the token in the fragment as `#token=<token>`.
+	/// The token is returned as json if this is not set.
+	pub post_login_redirect: Option<String>,
+}
+
+fn default_scopes() -> String {
+	"openid profile".to_string()
+}
+
+fn default_groups_claim() -> String {
+	"groups".to_string()
+}
+
+fn default_token_lifetime_secs() -> u64 {
+	60 * 60 * 24 * 7
+}
+
+/// The NATS server the change events are published to.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EventsConfig {
+	pub nats_url: String,
+	/// The events of a day are published to `<subject_prefix>.<day>`.
+	pub subject_prefix: String,
+	/// Whether the events contain the whole schedule instead of only what changed.
+	pub include_payload: bool,
+}
+
+/// The MQTT broker the schedules are published to.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+	pub host: String,
+	pub port: u16,
+	pub client_id: String,
+	pub username: Option<String>,
+	pub password: Option<String>,
+	/// The first level of every topic.
+	pub topic_prefix: String,
+}
+
+/// The Mastodon account that posts about updated schedules.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MastodonConfig {
+	/// Url of the instance, like `https://mastodon.social`.
+	pub instance: String,
+	/// Access token of the account, with the `write:statuses` scope.
+	pub access_token: String,
+	/// `public`, `unlisted`, `private` or `direct`.
+	#[serde(default = "default_visibility")]
+	pub visibility: String,
+	/// Seconds between two statuses about the same day, so quickly reissued pdfs end up in one status.
+	#[serde(default = "default_min_interval_secs")]
+	pub min_interval_secs: u64,
+}
+
+fn default_visibility() -> String {
+	"unlisted".to_string()
+}
+
+fn default_min_interval_secs() -> u64 {
+	10 * 60
+}
+
+/// What all notifiers about changed schedules share, and the plain webhooks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+	/// Urls the changes are POSTed to as json.
+	pub webhooks: Vec<String>,
+	/// How often a failed notification is retried.
+	pub retries: u32,
+	/// Handlebars templates of the messages by notifier name, replacing the default ones.
+	pub templates: HashMap<String, String>,
+}
+
+/// The Matrix account the changes are posted with and the rooms they are posted to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatrixConfig {
+	/// Url of the homeserver, like `https://matrix.example.org`.
+	pub homeserver: String,
+	/// Access token of the account, which has to be a member of every room.
+	pub access_token: String,
+	/// Rooms that get every change.
+	#[serde(default)]
+	pub rooms: Vec<String>,
+	/// Rooms that only get the changes of a class or class group.
+	#[serde(default)]
+	pub class_rooms: HashMap<String, Vec<String>>,
+}
+
+/// The store pdfs and backups are archived in, under the hash of their content.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BlobStoreConfig {
+	/// Files in a local directory.
+	Disk {
+		#[serde(default = "default_blob_directory")]
+		directory: String,
+	},
+	/// A bucket of S3 or a compatible service.
+	S3(S3Config),
+}
+
+fn default_blob_directory() -> String {
+	PDF_STORE_LOCATION.to_string()
+}
+
+/// The bucket and the credentials of an S3 compatible service.
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3Config {
+	/// Url of the service, like `https://s3.eu-central-1.amazonaws.com` or `http://localhost:9000`.
+	pub endpoint: String,
+	pub bucket: String,
+	#[serde(default = "default_region")]
+	pub region: String,
+	pub access_key: String,
+	pub secret_key: String,
+	/// Whether the bucket is addressed in the path of the endpoint instead of as its subdomain, which MinIO needs.
+	#[serde(default)]
+	pub path_style: bool,
+	/// Path the keys are stored under in the bucket.
+	#[serde(default)]
+	pub prefix: String,
+}
+
+fn default_region() -> String {
+	"us-east-1".to_string()
+}
+
+/// How `/{schoolday}/preview.png` renders the pdfs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PreviewConfig {
+	pub renderer: Renderer,
+	/// Resolution of the preview in pixels per inch.
+	pub dpi: u32,
+}
+
+/// The program the previews are rendered with, which has to be installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Renderer {
+	Ghostscript,
+	Pdftoppm,
+}
+
+/// The algorithm the pdfs are hashed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+	Sha512,
+	/// Much faster than SHA512, which matters on small machines fetching every few seconds.
+	Blake3,
+}
+
+/// What requests resolving to a Saturday or Sunday are answered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeekendFallback {
+	/// The schedule of the following Monday, marked with `X-Requested-Day` and `X-Effective-Day`.
+	NextMonday,
+	/// 404 with a `Location` pointing at the following Monday.
+	NotFound,
+}
+
+/// Where and how the pdfs are downloaded.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SourceConfig {
+	/// The urls of the pdfs from Monday to Friday.
+	pub urls: Vec<String>,
+	/// Headers sent with every download, in addition to the `Authorization` header the school server needs.
+	pub headers: HashMap<String, String>,
+	/// Seconds a whole download may take.
+	pub timeout_secs: u64,
+	/// Seconds connecting to the school server may take.
+	pub connect_timeout_secs: u64,
+	/// Directory the pdfs are read from instead of the urls, one file per day named like `Monday.pdf` or `Montag.pdf`.
+	/// The files are converted whenever they change instead of on a schedule.
+	pub directory: Option<String>,
+}
+
+/// How often the pdfs are fetched.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ScheduleConfig {
+	/// Seconds between two fetches of a day.
+	pub interval_secs: u64,
+	/// Up to this many seconds are randomly added to every interval.
+	pub jitter_secs: u64,
+	/// Intervals of single days that differ from `interval_secs`.
+	pub day_interval_secs: HashMap<Schoolday, u64>,
+	/// Cron expressions (with seconds) of the times all days are fetched at, replacing `interval_secs` if set.
+	/// A day is fetched at the earliest upcoming time of any of them.
+	pub cron: Vec<String>,
+	/// Cron expressions of single days, taking precedence over everything else.
+	pub day_cron: HashMap<Schoolday, Vec<String>>,
+	/// Cron expressions of the times the schedule of the next school day is sent as a digest to the notifiers.
+	/// Only sent while the pdfs are fetched from the urls.
+	pub digest_cron: Vec<String>,
+}
+
+/// The connection pools to Postgres. The url of the primary is read from `DATABASE_URL`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+	/// Url of a read replica the read pool connects to instead of the primary.
+	pub read_url: Option<String>,
+	/// The pool storing schedules and jobs.
+	pub write_pool: PoolConfig,
+	/// The pool serving endpoints that only read, so heavy reads can't starve the writer.
+	pub read_pool: PoolConfig,
+}
+
+/// Serving the schedules from the database instead of the memory of the instance,
+/// so any instance behind a load balancer serves the same schedules.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatelessConfig {
+	pub enabled: bool,
+	/// Seconds a schedule read from the database is served before it is read again.
+	pub cache_secs: u64,
+}
+
+/// Tuning of the http server, the defaults fit a small machine with a single core.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+	/// Number of worker threads handling requests.
+	pub workers: usize,
+	/// Seconds an idle connection is kept open for further requests, 0 closes it after every response.
+	pub keep_alive_secs: u64,
+	/// Seconds a client has to send the headers of a request in.
+	pub client_request_timeout_secs: u64,
+	/// Seconds a client has to acknowledge the close of a connection in.
+	pub client_disconnect_timeout_secs: u64,
+	/// Connections a single worker handles at once, further ones wait in the backlog.
+	pub max_connections: usize,
+	/// Connections waiting to be accepted before new ones are refused.
+	pub backlog: u32,
+}
+
+/// Which tier a client belongs to and how many requests each tier may send.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+	/// Requests aren't limited while this is off.
+	pub enabled: bool,
+	/// Take the client address from `Forwarded` or `X-Forwarded-For`, only safe behind a proxy that sets them.
+	pub trust_forwarded: bool,
+	/// The tiers by name. Clients without a known key or address belong to `anonymous`, and aren't limited without it.
+	pub tiers: HashMap<String, QuotaTier>,
+	/// Tier names by the key clients send in `X-Api-Key`. Clients sharing a key share its budget.
+	pub keys: HashMap<String, String>,
+	/// Tier names by client network, like `10.0.0.0/8` for the displays in the school building.
+	pub networks: HashMap<String, String>,
+	/// IPv6 clients share a budget with the addresses in the network of this prefix length, as a single client usually has a whole /64.
+	/// Applies to `/convert` as well.
+	pub ipv6_prefix_len: u8,
+}
+
+/// The budget of every client of a tier, refilled continuously.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuotaTier {
+	/// 0 doesn't limit the tier at all.
+	pub requests_per_minute: u32,
+	/// Requests a client can send at once after being idle.
+	pub burst: u32,
+}
+
+/// Limits of `POST /convert`, which converts uploaded pdfs without storing them.
+/// Every conversion runs a JVM, so the limits keep it from being used to overload the server.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConvertConfig {
+	/// The endpoint answers with 404 while this is off.
+	pub enabled: bool,
+	/// Largest accepted upload in bytes.
+	pub max_size_bytes: usize,
+	/// Pdfs with more pages are rejected before they are converted.
+	pub max_pages: usize,
+	/// Conversions a single address can request per hour, 0 doesn't limit them.
+	pub conversions_per_hour: u32,
+	/// Conversions an address can request at once after being idle.
+	pub burst: u32,
+}
+
+/// The sizing of a single connection pool.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+	pub min_connections: u32,
+	pub max_connections: u32,
+}
+
+/// Where and when to alert the operator about days that stopped updating.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+	/// Url the alert gets POSTed to. Alerting is disabled if this is not set.
+	pub webhook_url: Option<String>,
+	/// Seconds a day has to fail continuously before the alert is sent.
+	pub after_secs: u64,
+}
+
+impl Config {
+	/// Loads the config file at `path`, or the defaults if there is none.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if the file exists but can't be read or parsed.
+	pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+		match std::fs::read_to_string(path) {
+			Ok(content) => {
+				info!("Loading config from {}", path.display());
+				// Everything beyond the syntax is checked by the startup report, see `validation`.
+				Ok(toml::from_str(&content)?)
+			}
+			Err(why) if why.kind() == std::io::ErrorKind::NotFound => {
+				info!("No config found at {}, using defaults", path.display());
+				Ok(Self::default())
+			}
+			Err(why) => Err(Box::new(why)),
+		}
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			failure_threshold: 5,
+			max_conversions: 2,
+			hash_algorithm: HashAlgorithm::Sha512,
+			alert: AlertConfig::default(),
+			source: SourceConfig::default(),
+			schedule: ScheduleConfig::default(),
+			database: DatabaseConfig::default(),
+			stateless: StatelessConfig::default(),
+			http: HttpConfig::default(),
+			quotas: QuotaConfig::default(),
+			convert: ConvertConfig::default(),
+			class_names: NormalizationConfig::default(),
+			class_groups: HashMap::new(),
+			signing_key: None,
+			cors_origins: Vec::new(),
+			admin_token: None,
+			auth: None,
+			weekend_fallback: WeekendFallback::NextMonday,
+			preview: PreviewConfig::default(),
+			blobs: BlobStoreConfig::default(),
+			notify: NotifyConfig::default(),
+			matrix: None,
+			mastodon: None,
+			mqtt: None,
+			events: None,
+		}
+	}
+}
+
+impl Default for SourceConfig {
+	fn default() -> Self {
+		Self {
+			urls: SOURCE_URLS.map(String::from).to_vec(),
+			headers: HashMap::new(),
+			timeout_secs: 20,
+			connect_timeout_secs: 20,
+			directory: None,
+		}
+	}
+}
+
+impl Default for ScheduleConfig {
+	fn default() -> Self {
+		Self {
+			interval_secs: 20,
+			jitter_secs: 0,
+			day_interval_secs: HashMap::new(),
+			cron: Vec::new(),
+			day_cron: HashMap::new(),
+			digest_cron: Vec::new(),
+		}
+	}
+}
+
+impl Default for DatabaseConfig {
+	fn default() -> Self {
+		Self {
+			read_url: None,
+			write_pool: PoolConfig {
+				min_connections: 2,
+				max_connections: 5,
+			},
+			read_pool: PoolConfig::default(),
+		}
+	}
+}
+
+impl Default for StatelessConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			cache_secs: 5,
+		}
+	}
+}
+
+impl Default for HttpConfig {
+	fn default() -> Self {
+		Self {
+			workers: 1,
+			keep_alive_secs: 15,
+			client_request_timeout_secs: 10,
+			client_disconnect_timeout_secs: 5,
+			max_connections: 1024,
+			backlog: 256,
+		}
+	}
+}
+
+impl Default for QuotaConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			trust_forwarded: false,
+			tiers: HashMap::from([("anonymous".to_string(), QuotaTier {
+				requests_per_minute: 60,
+				burst: 20,
+			})]),
+			keys: HashMap::new(),
+			networks: HashMap::new(),
+			ipv6_prefix_len: 64,
+		}
+	}
+}
+
+impl Default for ConvertConfig {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			max_size_bytes: 2 * 1024 * 1024,
+			max_pages: 10,
+			conversions_per_hour: 10,
+			burst: 3,
+		}
+	}
+}
+
+impl Default for PoolConfig {
+	fn default() -> Self {
+		Self {
+			min_connections: 1,
+			max_connections: 5,
+		}
+	}
+}
+
+impl Default for BlobStoreConfig {
+	fn default() -> Self {
+		Self::Disk {
+			directory: default_blob_directory(),
+		}
+	}
+}
+
+impl Default for PreviewConfig {
+	fn default() -> Self {
+		Self {
+			renderer: Renderer::Ghostscript,
+			dpi: 100,
+		}
+	}
+}
+
+impl Default for MqttConfig {
+	fn default() -> Self {
+		Self {
+			host: "localhost".to_string(),
+			port: 1883,
+			client_id: "substitution_pdf_server".to_string(),
+			username: None,
+			password: None,
+			topic_prefix: "substitution".to_string(),
+		}
+	}
+}
+
+impl Default for NotifyConfig {
+	fn default() -> Self {
+		Self {
+			webhooks: Vec::new(),
+			retries: 3,
+			templates: HashMap::new(),
+		}
+	}
+}
+
+impl Default for EventsConfig {
+	fn default() -> Self {
+		Self {
+			nats_url: "nats://localhost:4222".to_string(),
+			subject_prefix: "substitution.changes".to_string(),
+			include_payload: false,
+		}
+	}
+}
+
+impl Default for AlertConfig {
+	fn default() -> Self {
+		Self {
+			webhook_url: None,
+			after_secs: 30 * 60,
+		}
+	}
+}
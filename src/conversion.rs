@@ -0,0 +1,31 @@
+use std::path::Path;
+use lazy_static::lazy_static;
+use substitution_pdf_to_json::{ConversionTimings, SubstitutionSchedule};
+use tokio::sync::Semaphore;
+use tracing::debug;
+use crate::CONFIG;
+
+lazy_static! {
+	/// Bounds the conversions running at once, every one of them starts a JVM for tabula.
+	static ref CONVERSIONS: Semaphore = Semaphore::new(CONFIG.max_conversions.max(1));
+}
+
+/// Converts the pdf like [`SubstitutionSchedule::from_pdf_bytes_timed`],
+/// waiting while `max_conversions` other conversions are running.
+///
+/// # Errors
+///
+/// Returns `Err` if the pdf couldn't be converted.
+pub async fn convert_pdf(pdf: &[u8], temp_dir: &Path) -> Result<(Vec<SubstitutionSchedule>, ConversionTimings), Box<dyn std::error::Error + Send + Sync>> {
+	if CONVERSIONS.available_permits() == 0 {
+		debug!("Waiting for a running conversion to finish");
+	}
+	let _permit = CONVERSIONS.acquire().await?;
+
+	SubstitutionSchedule::from_pdf_bytes_timed(pdf, temp_dir).await
+}
+
+/// The number of conversions running right now.
+pub fn running() -> usize {
+	CONFIG.max_conversions.max(1) - CONVERSIONS.available_permits()
+}
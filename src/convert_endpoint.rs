@@ -0,0 +1,111 @@
+use actix_web::{HttpRequest, HttpResponse, post, Responder, web};
+use actix_web::http::header;
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use tracing::{debug, error};
+use crate::{CONFIG, conversion, quotas};
+use crate::util::TEMP_DIR;
+use crate::classes::NORMALIZER;
+
+/// How far into the upload the pdf header may start, like pdf readers allow.
+const MAGIC_SEARCH_RANGE: usize = 1024;
+const PDF_MAGIC: &[u8] = b"%PDF-";
+
+/// Converts an uploaded pdf and answers with the schedules of the days it covers as json, without storing anything.
+/// Rejects uploads that are too large, aren't pdfs or have too many pages before they reach the converter,
+/// and limits the conversions per address, see [`ConvertConfig`](crate::config::ConvertConfig).
+#[post("/convert")]
+pub async fn post_convert(req: HttpRequest, payload: web::Payload) -> impl Responder {
+	let config = &CONFIG.convert;
+	if !config.enabled {
+		return HttpResponse::NotFound().finish();
+	}
+
+	let declared_length = req.headers()
+		.get(header::CONTENT_LENGTH)
+		.and_then(|length| length.to_str().ok())
+		.and_then(|length| length.parse::<usize>().ok());
+	if declared_length.is_some_and(|length| length > config.max_size_bytes) {
+		return HttpResponse::PayloadTooLarge().finish();
+	}
+
+	// Counted before the upload is read, so invalid uploads use up the budget as well.
+	let quota = quotas::check_conversion(&req);
+	let mut response = match &quota {
+		Some(quota) if quota.exceeded() => quotas::too_many_requests(),
+		_ => convert_upload(payload).await,
+	};
+	if let Some(quota) = quota {
+		quota.add_headers(response.headers_mut());
+	}
+
+	response
+}
+
+async fn convert_upload(payload: web::Payload) -> HttpResponse {
+	let config = &CONFIG.convert;
+
+	let pdf = match read_limited(payload, config.max_size_bytes).await {
+		Ok(Some(pdf)) => pdf,
+		Ok(None) => return HttpResponse::PayloadTooLarge().finish(),
+		Err(why) => return HttpResponse::BadRequest().body(why.to_string()),
+	};
+
+	// The content type is chosen by the client, only the content tells whether it is a pdf.
+	if !is_pdf(&pdf) {
+		return HttpResponse::UnsupportedMediaType().body("The upload is not a pdf");
+	}
+
+	let counted = pdf.clone();
+	let pages = match tokio::task::spawn_blocking(move || substitution_pdf_to_json::page_count(&counted)).await {
+		Ok(Ok(pages)) => pages,
+		Ok(Err(why)) => return HttpResponse::UnprocessableEntity().body(why.to_string()),
+		Err(why) => {
+			error!("Counting the pages of an upload panicked: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+	if pages > config.max_pages {
+		return HttpResponse::UnprocessableEntity().body(format!("The pdf has {pages} pages, at most {} are converted", config.max_pages));
+	}
+
+	let temp_dir = match tempfile::Builder::new().tempdir_in(&*TEMP_DIR) {
+		Ok(temp_dir) => temp_dir,
+		Err(why) => {
+			error!("Couldn't create a temp dir for an upload: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	debug!("Converting an uploaded pdf with {pages} pages");
+	match conversion::convert_pdf(&pdf, temp_dir.path()).await {
+		Ok((mut schedules, _)) => {
+			for schedule in &mut schedules {
+				schedule.normalize_class_names(&NORMALIZER);
+			}
+			HttpResponse::Ok().json(schedules)
+		}
+		Err(why) => HttpResponse::UnprocessableEntity().body(why.to_string()),
+	}
+}
+
+/// Reads the whole payload, `None` if it is larger than `max_size`.
+async fn read_limited(mut payload: web::Payload, max_size: usize) -> Result<Option<Bytes>, actix_web::error::PayloadError> {
+	let mut body = BytesMut::new();
+	while let Some(chunk) = payload.next().await {
+		let chunk = chunk?;
+		if body.len() + chunk.len() > max_size {
+			return Ok(None);
+		}
+		body.extend_from_slice(&chunk);
+	}
+
+	Ok(Some(body.freeze()))
+}
+
+/// Whether the bytes start with a pdf header, allowing for some garbage in front of it.
+fn is_pdf(upload: &[u8]) -> bool {
+	upload[..upload.len().min(MAGIC_SEARCH_RANGE)]
+		.windows(PDF_MAGIC.len())
+		.any(|window| window == PDF_MAGIC)
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+use actix_web::{get, HttpRequest, HttpResponse, Responder, web};
+use actix_web::http::header;
+use chrono::{Datelike, Duration, Local, NaiveDate, TimeZone};
+use sqlx::PgPool;
+use substitution_pdf_to_json::SubstitutionSchedule;
+use tracing::error;
+use crate::{CONFIG, Schoolday, storage, weekend};
+use crate::config::WeekendFallback;
+use crate::formats::Format;
+use crate::json_endpoint::{DayQuery, filter_schedule};
+use crate::stateless::served_schedule;
+use crate::storage::Pools;
+use crate::util::local_midnight;
+
+/// Serves the schedule issued for a calendar date, in the format negotiated from the `Accept` header.
+/// The served schedule of the weekday is used if it was issued for the date, past dates are looked up in the database.
+/// Dates on weekends are handled like configured in [`WeekendFallback`].
+#[get("/{date:\\d{4}-\\d{2}-\\d{2}}")]
+pub async fn get_date_json(req: HttpRequest, date: web::Path<String>, query: web::Query<DayQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let date = match NaiveDate::parse_from_str(&date, "%F") {
+		Ok(date) => date,
+		Err(why) => return HttpResponse::BadRequest().body(why.to_string()),
+	};
+
+	match Schoolday::from_weekday(date.weekday()) {
+		Some(day) => date_response(&req, day, date, &query, &pools).await,
+		None => {
+			let monday = date + Duration::days(i64::from(7 - date.weekday().num_days_from_monday()));
+			match CONFIG.weekend_fallback {
+				WeekendFallback::NextMonday => weekend::mark_fallback(date_response(&req, Schoolday::Monday, monday, &query, &pools).await, date.weekday(), Schoolday::Monday),
+				WeekendFallback::NotFound => weekend::not_found(date.weekday(), &format!("/{monday}")),
+			}
+		}
+	}
+}
+
+async fn date_response(req: &HttpRequest, day: Schoolday, date: NaiveDate, query: &DayQuery, pools: &Pools) -> HttpResponse {
+	let format = match Format::select(req, query.format.as_deref()) {
+		Some(format) => format,
+		None => return HttpResponse::NotAcceptable().finish(),
+	};
+
+	let schedule = match schedule_for_date(day, date, pools).await {
+		Ok(Some(schedule)) => schedule,
+		Ok(None) => return HttpResponse::NotFound().body(format!("There is no schedule for {date}")),
+		Err(why) => {
+			error!("{why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	let body = match &query.classes {
+		Some(classes) => format.render(day, &filter_schedule(&schedule, classes), query.timestamps),
+		None => format.render(day, &schedule, query.timestamps),
+	};
+
+	match body {
+		Ok(body) => HttpResponse::Ok()
+			.content_type(format.content_type())
+			.insert_header((header::VARY, "Accept"))
+			.body(body),
+		Err(why) => {
+			error!("{why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+async fn schedule_for_date(day: Schoolday, date: NaiveDate, pools: &Pools) -> Result<Option<Arc<SubstitutionSchedule>>, Box<dyn std::error::Error + Send + Sync>> {
+	if let Some(schedule) = served_schedule(day, &pools.read).await? {
+		if issue_date(&schedule) == Some(date) {
+			return Ok(Some(schedule));
+		}
+	}
+
+	// Schedules for today or later that aren't served haven't been published yet.
+	if date >= Local::now().date_naive() {
+		return Ok(None);
+	}
+
+	Ok(stored_schedule(day, date, &pools.read).await?.map(Arc::new))
+}
+
+fn issue_date(schedule: &SubstitutionSchedule) -> Option<NaiveDate> {
+	Local.timestamp_millis_opt(schedule.pdf_issue_date)
+		.single()
+		.map(|issued| issued.date_naive())
+}
+
+/// The last schedule that was stored for `date`.
+async fn stored_schedule(day: Schoolday, date: NaiveDate, pool: &PgPool) -> Result<Option<SubstitutionSchedule>, Box<dyn std::error::Error + Send + Sync>> {
+	let start = local_midnight(date).and_then(|start| Local.timestamp_millis_opt(start).single());
+	let end = local_midnight(date + Duration::days(1)).and_then(|end| Local.timestamp_millis_opt(end).single());
+	let (start, end) = match (start, end) {
+		(Some(start), Some(end)) => (start.naive_utc(), end.naive_utc()),
+		_ => return Ok(None),
+	};
+
+	let hash = sqlx::query_scalar!(
+		r#"
+		SELECT hash
+		FROM substitution_json
+		WHERE pdf_date >= $1 AND pdf_date < $2
+		ORDER BY insertion_time DESC
+		LIMIT 1
+		"#,
+		start,
+		end
+	)
+		.fetch_optional(pool)
+		.await?
+		.flatten();
+
+	let json = match hash {
+		Some(hash) => storage::load_json(pool, &hash).await?,
+		None => None,
+	};
+	let mut schedule: SubstitutionSchedule = match json {
+		Some(json) => serde_json::from_value(json)?,
+		None => return Ok(None),
+	};
+	if schedule.effective_date().is_none() {
+		schedule.assign_day(day);
+	}
+
+	Ok(Some(schedule))
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use serde_json::{Map, Value};
+use substitution_pdf_to_json::{ClassName, SubstitutionColumn, SubstitutionSchedule};
+use sqlx::PgPool;
+use crate::Schoolday;
+use crate::stateless::{served_previous, served_schedule};
+
+/// A block of a class whose substitution changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change<'a> {
+	pub class: &'a ClassName,
+	pub block: usize,
+	pub before: Option<&'a str>,
+	pub after: Option<&'a str>,
+}
+
+/// Lists the blocks that differ between `old` and `new`, ordered by class and block.
+pub fn changes<'a>(old: &'a SubstitutionSchedule, new: &'a SubstitutionSchedule) -> Vec<Change<'a>> {
+	let classes: BTreeSet<&ClassName> = old.entries().keys().chain(new.entries().keys()).collect();
+
+	let mut changes = Vec::new();
+	for class in classes {
+		let before = old.entries().get(class);
+		let after = new.entries().get(class);
+
+		for block in 0..6 {
+			let block_text = |column: Option<&'a SubstitutionColumn>| {
+				column.and_then(|column| column.blocks().find(|(idx, _)| *idx == block).map(|(_, text)| text))
+			};
+			let (before, after) = (block_text(before), block_text(after));
+
+			if before != after {
+				changes.push(Change {
+					class,
+					block,
+					before,
+					after,
+				});
+			}
+		}
+	}
+
+	changes
+}
+
+/// Writes the changes as a markdown list, one line per block, like `- **10A**: Block 3 now Raum 204 Herr X`.
+pub fn render_text(day: Schoolday, changes: &[Change]) -> String {
+	if changes.is_empty() {
+		return format!("No changes on {day}.\n");
+	}
+
+	let mut out = format!("Changes on {day}:\n\n");
+	for change in changes {
+		let _ = match (change.before, change.after) {
+			(_, Some(after)) => writeln!(out, "- **{}**: Block {} now {}", change.class, change.block, one_line(after)),
+			(Some(before), None) => writeln!(out, "- **{}**: Block {} no longer {}", change.class, change.block, one_line(before)),
+			(None, None) => Ok(()),
+		};
+	}
+
+	out
+}
+
+/// The text describing what changed on `day` since the substitutions last changed,
+/// or `None` if they haven't changed since the server started.
+///
+/// # Errors
+///
+/// Returns `Err` if the schedules couldn't be read from the database in stateless mode.
+pub async fn text_diff(day: Schoolday, pool: &PgPool) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+	let (old, new) = match (served_previous(day, pool).await?, served_schedule(day, pool).await?) {
+		(Some(old), Some(new)) => (old, new),
+		_ => return Ok(None),
+	};
+
+	Ok(Some(render_text(day, &changes(&old, &new))))
+}
+
+/// The merge patch turning the schedule of `day` before the substitutions last changed into the current one,
+/// or `None` if they haven't changed since the server started.
+///
+/// # Errors
+///
+/// Returns `Err` if the schedules couldn't be read from the database in stateless mode.
+pub async fn merge_patch_diff(day: Schoolday, pool: &PgPool) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
+	let (old, new) = match (served_previous(day, pool).await?, served_schedule(day, pool).await?) {
+		(Some(old), Some(new)) => (serde_json::to_value(&*old)?, serde_json::to_value(&*new)?),
+		_ => return Ok(None),
+	};
+
+	Ok(Some(merge_patch(&old, &new)))
+}
+
+/// Creates a JSON Merge Patch (RFC 7386) turning `old` into `new`.
+/// Removed members are set to `null`, arrays and other values that changed are replaced as a whole.
+pub fn merge_patch(old: &Value, new: &Value) -> Value {
+	let (old, new) = match (old, new) {
+		(Value::Object(old), Value::Object(new)) => (old, new),
+		_ => return new.clone(),
+	};
+
+	let mut patch = Map::new();
+	for key in old.keys().filter(|key| !new.contains_key(*key)) {
+		patch.insert(key.clone(), Value::Null);
+	}
+	for (key, value) in new {
+		match old.get(key) {
+			Some(previous) if previous == value => {}
+			Some(previous) => {
+				patch.insert(key.clone(), merge_patch(previous, value));
+			}
+			None => {
+				patch.insert(key.clone(), value.clone());
+			}
+		}
+	}
+
+	Value::Object(patch)
+}
+
+/// Joins the lines of a block, which the PDF splits over several rows.
+pub fn one_line(text: &str) -> String {
+	text.lines()
+		.map(str::trim)
+		.filter(|line| !line.is_empty())
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use super::*;
+
+	fn schedule(entries: Value) -> SubstitutionSchedule {
+		serde_json::from_value(json!({
+			"pdf_issue_date": 1_652_054_400_000_i64,
+			"struct_time": 0,
+			"entries": entries,
+		})).unwrap()
+	}
+
+	#[test]
+	fn changed_blocks_are_listed_by_class_and_block() {
+		let old = schedule(json!({"10A": {"1": "Mathe", "3": "Raum 104"}, "5A": {"0": "Sport entfällt"}}));
+		let new = schedule(json!({"10A": {"1": "Mathe", "3": "Raum 204\nHerr X"}, "9B": {"2": "Englisch"}}));
+
+		let changes = changes(&old, &new);
+
+		assert_eq!(
+			changes.iter().map(|change| (change.class.as_str(), change.block, change.before, change.after)).collect::<Vec<_>>(),
+			[
+				("5A", 0, Some("Sport entfällt"), None),
+				("9B", 2, None, Some("Englisch")),
+				("10A", 3, Some("Raum 104"), Some("Raum 204\nHerr X")),
+			],
+		);
+	}
+
+	#[test]
+	fn changes_are_rendered_one_per_line() {
+		let old = schedule(json!({"10A": {"3": "Raum 104"}, "5A": {"0": "Sport entfällt"}}));
+		let new = schedule(json!({"10A": {"3": "Raum 204\n Herr X "}}));
+
+		assert_eq!(
+			render_text(Schoolday::Monday, &changes(&old, &new)),
+			"Changes on Monday:\n\n- **5A**: Block 0 no longer Sport entfällt\n- **10A**: Block 3 now Raum 204, Herr X\n",
+		);
+	}
+
+	#[test]
+	fn unchanged_days_say_so() {
+		let old = schedule(json!({"10A": {"3": "Raum 104"}}));
+
+		assert_eq!(render_text(Schoolday::Friday, &changes(&old, &old)), "No changes on Friday.\n");
+	}
+
+	#[test]
+	fn merge_patches_turn_the_old_version_into_the_new_one() {
+		let old = serde_json::to_value(schedule(json!({"10A": {"1": "Mathe", "3": "Raum 104"}, "5A": {"0": "Sport entfällt"}}))).unwrap();
+		let new = serde_json::to_value(schedule(json!({"10A": {"1": "Mathe", "3": "Raum 204"}, "9B": {"2": "Englisch"}}))).unwrap();
+
+		let patch = merge_patch(&old, &new);
+		assert_eq!(patch, json!({"entries": {"10A": {"3": "Raum 204"}, "5A": null, "9B": {"2": "Englisch"}}}));
+
+		let mut patched = old;
+		json_patch::merge(&mut patched, &patch);
+		assert_eq!(patched, new);
+	}
+
+	#[test]
+	fn values_that_are_no_objects_are_replaced() {
+		assert_eq!(merge_patch(&json!([1, 2]), &json!([2])), json!([2]));
+		assert_eq!(merge_patch(&json!({"a": [1, 2]}), &json!({"a": [2]})), json!({"a": [2]}));
+		assert_eq!(merge_patch(&json!({"a": 1}), &json!({"a": 1})), json!({}));
+	}
+}
+
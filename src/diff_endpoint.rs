@@ -0,0 +1,38 @@
+use actix_web::{get, HttpResponse, Responder, web};
+use tracing::error;
+use crate::{diff, Schoolday};
+use crate::storage::Pools;
+
+/// Serves what changed in the last update of a day as a markdown list.
+#[get("/diff/{schoolday}/text")]
+pub async fn get_text_diff(day: web::Path<Schoolday>, pools: web::Data<Pools>) -> impl Responder {
+	let day = day.into_inner();
+
+	match diff::text_diff(day, &pools.read).await {
+		Ok(Some(text)) => HttpResponse::Ok()
+			.content_type("text/markdown; charset=utf-8")
+			.body(text),
+		Ok(None) => HttpResponse::NotFound().body(format!("{day} didn't change since the server started")),
+		Err(why) => {
+			error!("Couldn't diff the schedules of {day}: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// Serves what changed in the last update of a day as a JSON Merge Patch (RFC 7386) of the previous schedule.
+#[get("/diff/{schoolday}/merge-patch")]
+pub async fn get_merge_patch_diff(day: web::Path<Schoolday>, pools: web::Data<Pools>) -> impl Responder {
+	let day = day.into_inner();
+
+	match diff::merge_patch_diff(day, &pools.read).await {
+		Ok(Some(patch)) => HttpResponse::Ok()
+			.content_type("application/merge-patch+json")
+			.json(patch),
+		Ok(None) => HttpResponse::NotFound().body(format!("{day} didn't change since the server started")),
+		Err(why) => {
+			error!("Couldn't diff the schedules of {day}: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use bytes::Bytes;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use sqlx::PgPool;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+use tracing::{debug, error, info};
+use crate::{pipeline, Schoolday, systemd};
+use crate::fetcher::PdfFetcher;
+
+/// How long a file has to stay unchanged before it is read, so files that are still being copied aren't converted.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Reads the pdfs from a directory, one file per day named after it, e.g. `Monday.pdf` or `Montag.pdf`.
+#[derive(Debug)]
+pub struct DirectoryFetcher {
+	dir: PathBuf,
+}
+
+impl DirectoryFetcher {
+	pub fn new(dir: PathBuf) -> Self {
+		Self {
+			dir,
+		}
+	}
+
+	/// Finds the file of `day`, as the name may be in either language and any case.
+	async fn path(&self, day: Schoolday) -> Result<Option<PathBuf>, std::io::Error> {
+		let mut entries = tokio::fs::read_dir(&self.dir).await?;
+
+		while let Some(entry) = entries.next_entry().await? {
+			let path = entry.path();
+			if day_of(&path) == Some(day) {
+				return Ok(Some(path));
+			}
+		}
+
+		Ok(None)
+	}
+}
+
+#[async_trait]
+impl PdfFetcher for DirectoryFetcher {
+	async fn fetch(&self, day: Schoolday) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+		let path = self.path(day).await?.ok_or_else(|| format!("There is no pdf for {day} in {}", self.dir.display()))?;
+		Ok(Bytes::from(tokio::fs::read(path).await?))
+	}
+}
+
+/// The day a file in the watched directory belongs to, named in English or German.
+fn day_of(path: &Path) -> Option<Schoolday> {
+	if !path.extension()?.to_str()?.eq_ignore_ascii_case("pdf") {
+		return None;
+	}
+
+	path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Converts the pdfs in `dir` whenever one is added or changed, until `shutdown` changes.
+/// The files present on startup are converted right away.
+pub async fn watch(dir: PathBuf, pool: PgPool, mut shutdown: watch::Receiver<bool>) {
+	let (sender, mut changes) = mpsc::unbounded_channel();
+	let watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+		match result {
+			Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+				for path in event.paths {
+					let _ = sender.send(path);
+				}
+			}
+			Ok(_) => {}
+			Err(why) => error!("Watching the pdf directory failed: {why}"),
+		}
+	});
+	let mut watcher: RecommendedWatcher = match watcher {
+		Ok(watcher) => watcher,
+		Err(why) => {
+			error!("Couldn't watch {}: {why}", dir.display());
+			return;
+		}
+	};
+	if let Err(why) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+		error!("Couldn't watch {}: {why}", dir.display());
+		return;
+	}
+
+	info!("Watching {} for pdfs", dir.display());
+	let directory = DirectoryFetcher::new(dir.clone());
+	let now = Instant::now();
+	let mut due: HashMap<Schoolday, Instant> = HashMap::new();
+	for day in Schoolday::ALL {
+		if matches!(directory.path(day).await, Ok(Some(_))) {
+			due.insert(day, now);
+		}
+	}
+	let fetcher: Arc<dyn PdfFetcher> = Arc::new(directory);
+
+	loop {
+		systemd::feed_watchdog();
+		let next = due.values().min().copied().into_iter().chain(systemd::watchdog_deadline(Instant::now())).min();
+
+		tokio::select! {
+			Some(path) = changes.recv() => {
+				if let Some(day) = day_of(&path) {
+					debug!("{day}: {} changed", path.display());
+					due.insert(day, Instant::now() + SETTLE_TIME);
+				}
+			}
+			() = tokio::time::sleep_until(next.unwrap_or_else(|| Instant::now() + Duration::from_secs(60 * 60))) => {
+				let now = Instant::now();
+				let settled: Vec<Schoolday> = due.iter().filter(|(_, at)| **at <= now).map(|(day, _)| *day).collect();
+
+				for day in settled {
+					due.remove(&day);
+					if let Err(why) = pipeline::update(day, fetcher.clone(), pool.clone()).await {
+						error!("{why}");
+					}
+				}
+			}
+			_ = shutdown.changed() => break,
+		}
+	}
+
+	info!("Stopped watching {}", dir.display());
+}
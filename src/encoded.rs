@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::io::Write;
+use actix_web::HttpRequest;
+use actix_web::http::header::{AcceptEncoding, ContentEncoding, Encoding, Header};
+use bytes::Bytes;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use tokio::sync::RwLock;
+use tracing::debug;
+use crate::{Schoolday, signing};
+use crate::formats::Format;
+use crate::stateless::{served_json, served_msgpack};
+
+/// Quality and window size of brotli, the highest ones, as a body is only compressed once per update.
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_WINDOW: u32 = 22;
+
+lazy_static! {
+	static ref ENCODED: RwLock<HashMap<(Schoolday, Format), EncodedBodies>> = RwLock::new(HashMap::new());
+}
+
+/// The compressed versions of a prerendered body.
+struct EncodedBodies {
+	/// The hash of the uncompressed body, which changes with every served schedule, even if the hash of the pdf of the day doesn't.
+	version: blake3::Hash,
+	/// The signature of the uncompressed body.
+	signature: Option<String>,
+	bodies: HashMap<ContentEncoding, Bytes>,
+}
+
+/// A prerendered body, compressed as negotiated.
+pub struct Encoded {
+	pub body: Bytes,
+	/// The `X-Signature` of the uncompressed body, as clients verify it after decoding.
+	/// `None` for uncompressed bodies, which are signed like any other response.
+	pub signature: Option<String>,
+}
+
+/// Picks the compression the client prefers from its `Accept-Encoding` header, brotli, gzip or none.
+pub fn negotiate(req: &HttpRequest) -> ContentEncoding {
+	let supported = [Encoding::brotli(), Encoding::gzip(), Encoding::identity()];
+
+	match AcceptEncoding::parse(req).ok().and_then(|accept| accept.negotiate(supported.iter())) {
+		Some(Encoding::Known(ContentEncoding::Brotli)) => ContentEncoding::Brotli,
+		Some(Encoding::Known(ContentEncoding::Gzip)) => ContentEncoding::Gzip,
+		_ => ContentEncoding::Identity,
+	}
+}
+
+/// The prerendered json or msgpack of `day` compressed with `encoding`, `None` if there is no schedule yet.
+/// The bodies are compressed once and dropped when the served body changes.
+///
+/// # Errors
+///
+/// Returns `Err` if the schedule couldn't be read or compressed, or `format` isn't prerendered.
+pub async fn encoded(day: Schoolday, format: Format, encoding: ContentEncoding, pool: &PgPool) -> Result<Option<Encoded>, Box<dyn std::error::Error + Send + Sync>> {
+	let body = match prerendered(day, format, pool).await? {
+		Some(body) => body,
+		None => return Ok(None),
+	};
+
+	// Uncompressed bodies are shared with the store, there is nothing to cache.
+	if encoding == ContentEncoding::Identity {
+		return Ok(Some(Encoded {
+			body,
+			signature: None,
+		}));
+	}
+
+	let version = blake3::hash(&body);
+	{
+		let encoded = ENCODED.read().await;
+		if let Some(bodies) = encoded.get(&(day, format)).filter(|bodies| bodies.version == version) {
+			if let Some(compressed) = bodies.bodies.get(&encoding) {
+				return Ok(Some(Encoded {
+					body: compressed.clone(),
+					signature: bodies.signature.clone(),
+				}));
+			}
+		}
+	}
+
+	let signature = signing::signature(&body);
+	let compressed = Bytes::from(tokio::task::spawn_blocking(move || compress(&body, encoding)).await??);
+
+	// A request that read the body before it was replaced may put it back, the next request compresses the new one again then.
+	let mut encoded = ENCODED.write().await;
+	let bodies = encoded.entry((day, format)).or_insert_with(|| EncodedBodies {
+		version,
+		signature: signature.clone(),
+		bodies: HashMap::new(),
+	});
+	if bodies.version != version {
+		debug!("{day}: Dropping the encoded bodies of the replaced {format:?}");
+		bodies.version = version;
+		bodies.signature.clone_from(&signature);
+		bodies.bodies.clear();
+	}
+	bodies.bodies.insert(encoding, compressed.clone());
+
+	Ok(Some(Encoded {
+		body: compressed,
+		signature,
+	}))
+}
+
+async fn prerendered(day: Schoolday, format: Format, pool: &PgPool) -> Result<Option<Bytes>, Box<dyn std::error::Error + Send + Sync>> {
+	match format {
+		Format::Json => served_json(day, pool).await,
+		Format::MsgPack => served_msgpack(day, pool).await,
+		_ => Err(format!("{format:?} isn't prerendered").into()),
+	}
+}
+
+fn compress(body: &[u8], encoding: ContentEncoding) -> Result<Vec<u8>, std::io::Error> {
+	match encoding {
+		ContentEncoding::Brotli => {
+			let mut compressed = Vec::new();
+			{
+				let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+				writer.write_all(body)?;
+			}
+			Ok(compressed)
+		}
+		ContentEncoding::Gzip => {
+			let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+			encoder.write_all(body)?;
+			encoder.finish()
+		}
+		_ => Ok(body.to_vec()),
+	}
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use async_nats::{Client, ConnectOptions};
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::Serialize;
+use substitution_pdf_to_json::{ClassName, SubstitutionSchedule};
+use tokio::sync::OnceCell;
+use tracing::{error, info};
+use crate::config::EventsConfig;
+use crate::notifier::{Notifier, ScheduleChange};
+use crate::Schoolday;
+
+/// Published to `<subject_prefix>.<day>` whenever the schedule of a day changes.
+#[derive(Debug, Serialize)]
+pub struct ChangeEvent<'a> {
+	pub day: Schoolday,
+	/// The hash of the pdf the schedule was converted from.
+	pub hash: Option<&'a str>,
+	/// The issue date of the pdf, in milliseconds since the unix epoch.
+	pub pdf_issue_date: i64,
+	/// The classes with changed blocks, empty for the first schedule of the day since the server started.
+	pub changed_classes: Vec<&'a ClassName>,
+	/// The changes as a markdown list, see `/diff/{schoolday}/text`.
+	pub summary: Option<String>,
+	/// The whole schedule, if `include_payload` is set.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub schedule: Option<&'a SubstitutionSchedule>,
+}
+
+/// Publishes a [`ChangeEvent`] for every changed schedule to NATS.
+pub struct NatsNotifier {
+	client: Arc<OnceCell<Client>>,
+	config: EventsConfig,
+}
+
+impl NatsNotifier {
+	/// Connects to the server in the background, retrying until it is reachable.
+	pub fn connect(config: EventsConfig) -> Self {
+		let client = Arc::new(OnceCell::new());
+
+		let url = config.nats_url.clone();
+		let cell = client.clone();
+		tokio::spawn(async move {
+			match ConnectOptions::new().retry_on_initial_connect().connect(&url).await {
+				Ok(client) => {
+					info!("Publishing change events to {url}");
+					let _ = cell.set(client);
+				}
+				Err(why) => error!("Couldn't connect to NATS at {url}: {why}"),
+			}
+		});
+
+		Self {
+			client,
+			config,
+		}
+	}
+}
+
+#[async_trait]
+impl Notifier for NatsNotifier {
+	fn name(&self) -> &'static str {
+		"nats"
+	}
+
+	/// Consumers may want to know about every schedule, including the first one after a restart.
+	fn wants(&self, _change: &ScheduleChange) -> bool {
+		true
+	}
+
+	async fn notify(&self, change: &ScheduleChange) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let client = self.client.get().ok_or("Not connected to NATS yet")?;
+
+		let event = ChangeEvent {
+			day: change.day,
+			hash: change.hash.as_deref(),
+			pdf_issue_date: change.schedule.pdf_issue_date,
+			changed_classes: change.changed_classes().into_iter().collect(),
+			summary: change.summary(),
+			schedule: self.config.include_payload.then_some(change.schedule.as_ref()),
+		};
+
+		let subject = format!("{}.{}", self.config.subject_prefix, change.day);
+		client.publish(subject, Bytes::from(serde_json::to_vec(&event)?)).await?;
+
+		Ok(())
+	}
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::info;
+use crate::storage;
+
+/// An exported schedule, listed in the `index.json` of the export.
+#[derive(Debug, Serialize)]
+pub struct ExportedSchedule {
+	/// The file the schedule was written to, relative to the export directory.
+	pub file: String,
+	pub hash: String,
+	/// The date the pdf was issued for, in milliseconds since the epoch.
+	pub pdf_date: i64,
+	/// When the schedule was stored, in milliseconds since the epoch.
+	pub insertion_time: Option<i64>,
+	/// The day it was fetched for, unknown for schedules stored before days were recorded.
+	pub day: Option<i16>,
+}
+
+/// Writes every stored schedule to `dir` as `<pdf date>-<hash prefix>.json`, plus an `index.json` listing them all.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read or a file couldn't be written.
+pub async fn export_dir(dir: &Path, pool: &PgPool) -> Result<Vec<ExportedSchedule>, Box<dyn std::error::Error>> {
+	tokio::fs::create_dir_all(dir).await?;
+
+	// Only the metadata is loaded up front, the jsons are loaded one by one so the archive doesn't have to fit in memory.
+	let rows = sqlx::query!(
+		r#"
+		SELECT hash AS "hash!", pdf_date, insertion_time, day
+		FROM substitution_json
+		WHERE hash IS NOT NULL
+		ORDER BY pdf_date, insertion_time
+		"#
+	)
+		.fetch_all(pool)
+		.await?;
+
+	let mut index = Vec::with_capacity(rows.len());
+	for row in rows {
+		let json = storage::load_json(pool, &row.hash).await?;
+
+		let file = format!("{}-{}.json", row.pdf_date.format("%F"), &row.hash[..16]);
+		tokio::fs::write(dir.join(&file), serde_json::to_vec_pretty(&json)?).await?;
+
+		index.push(ExportedSchedule {
+			file,
+			hash: row.hash,
+			pdf_date: millis(row.pdf_date),
+			insertion_time: row.insertion_time.map(millis),
+			day: row.day,
+		});
+	}
+
+	tokio::fs::write(dir.join("index.json"), serde_json::to_vec_pretty(&index)?).await?;
+	info!("Exported {} schedules to {}", index.len(), dir.display());
+
+	Ok(index)
+}
+
+/// The database stores the times in UTC without a zone.
+fn millis(time: NaiveDateTime) -> i64 {
+	Utc.from_utc_datetime(&time).timestamp_millis()
+}
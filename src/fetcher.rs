@@ -0,0 +1,15 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use crate::Schoolday;
+
+/// A source the pdf of a day can be fetched from.
+/// Implemented by [`crate::pdf_getter::SubstitutionPDFGetter`] for the school server, other sources or mocks can be swapped in.
+#[async_trait]
+pub trait PdfFetcher: Send + Sync {
+	/// Fetches the current pdf of `day`.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if the pdf couldn't be fetched.
+	async fn fetch(&self, day: Schoolday) -> Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+}
@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use actix_web::http::header::{Accept, Header, Quality};
+use actix_web::HttpRequest;
+use chrono::{NaiveDate, TimeZone, Utc};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use substitution_pdf_to_json::{ClassName, SubstitutionColumn, SubstitutionSchedule};
+use crate::{proto, Schoolday};
+use crate::v2::rfc3339;
+
+/// How timestamps are written in json and msgpack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Timestamps {
+	/// Milliseconds since the unix epoch, in the fields `pdf_issue_date` and `struct_time`.
+	#[default]
+	Millis,
+	/// RFC 3339 strings in UTC, in the fields `pdf_issued_at` and `created_at`.
+	Rfc3339,
+}
+
+/// A schedule with its timestamps written as RFC 3339 strings.
+#[derive(Serialize)]
+struct Rfc3339Schedule<'a> {
+	pdf_issued_at: String,
+	created_at: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	effective_date: Option<NaiveDate>,
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	issue_date_mismatch: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	ocr_confidence: Option<f32>,
+	entries: &'a BTreeMap<ClassName, SubstitutionColumn>,
+}
+
+impl<'a> From<&'a SubstitutionSchedule> for Rfc3339Schedule<'a> {
+	#[allow(clippy::cast_possible_wrap)]
+	fn from(schedule: &'a SubstitutionSchedule) -> Self {
+		Self {
+			pdf_issued_at: rfc3339(schedule.pdf_issue_date),
+			created_at: rfc3339(schedule.struct_time() as i64),
+			effective_date: schedule.effective_date(),
+			issue_date_mismatch: schedule.issue_date_mismatch(),
+			ocr_confidence: schedule.ocr_confidence(),
+			entries: schedule.entries(),
+		}
+	}
+}
+
+/// The formats a schedule can be served in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+	Json,
+	MsgPack,
+	Protobuf,
+	Html,
+	Csv,
+	ICal,
+	Markdown,
+}
+
+impl Format {
+	/// Ordered by preference, used when the client accepts a whole range like `text/*`.
+	const ALL: [Format; 7] = [Format::Json, Format::MsgPack, Format::Protobuf, Format::Html, Format::Csv, Format::ICal, Format::Markdown];
+
+	pub fn content_type(self) -> &'static str {
+		match self {
+			Format::Json => "application/json",
+			Format::MsgPack => "application/msgpack",
+			Format::Protobuf => "application/x-protobuf",
+			Format::Html => "text/html; charset=utf-8",
+			Format::Csv => "text/csv; charset=utf-8",
+			Format::ICal => "text/calendar; charset=utf-8",
+			Format::Markdown => "text/markdown; charset=utf-8",
+		}
+	}
+
+	fn mime(self) -> (&'static str, &'static str) {
+		match self {
+			Format::Json => ("application", "json"),
+			Format::MsgPack => ("application", "msgpack"),
+			Format::Protobuf => ("application", "x-protobuf"),
+			Format::Html => ("text", "html"),
+			Format::Csv => ("text", "csv"),
+			Format::ICal => ("text", "calendar"),
+			Format::Markdown => ("text", "markdown"),
+		}
+	}
+
+	/// Parses the name of a format in the `format` query parameter, which takes precedence over the `Accept` header.
+	fn from_name(name: &str) -> Option<Self> {
+		match name.to_lowercase().as_str() {
+			"json" => Some(Format::Json),
+			"msgpack" => Some(Format::MsgPack),
+			"protobuf" | "proto" => Some(Format::Protobuf),
+			"html" => Some(Format::Html),
+			"csv" => Some(Format::Csv),
+			"ical" | "ics" => Some(Format::ICal),
+			"md" | "markdown" => Some(Format::Markdown),
+			_ => None,
+		}
+	}
+
+	/// Picks the format named by the `format` query parameter if there is one, the one negotiated from `Accept` otherwise.
+	/// Returns `None` if the name is unknown or no format is acceptable.
+	pub fn select(req: &HttpRequest, name: Option<&str>) -> Option<Self> {
+		match name {
+			Some(name) => Self::from_name(name),
+			None => Self::negotiate(req),
+		}
+	}
+
+	/// Picks the format the client prefers most from its `Accept` header.
+	/// Falls back to json without an `Accept` header, returns `None` if no format is acceptable.
+	pub fn negotiate(req: &HttpRequest) -> Option<Self> {
+		let accept = match Accept::parse(req) {
+			Ok(accept) if !accept.is_empty() => accept,
+			_ => return Some(Format::Json),
+		};
+
+		let mut ranges: Vec<_> = accept
+			.iter()
+			.filter(|range| range.quality > Quality::ZERO)
+			.collect();
+		// Stable, so ranges with the same quality keep the order the client listed them in.
+		ranges.sort_by_key(|range| std::cmp::Reverse(range.quality));
+
+		ranges.into_iter().find_map(|range| {
+			Format::ALL.into_iter().find(|format| {
+				let (type_, subtype) = format.mime();
+				let range_type = range.item.type_().as_str();
+				let range_subtype = range.item.subtype().as_str();
+
+				// Neither msgpack nor protobuf have a registered type, so both spellings are in use.
+				let range_subtype = match range_subtype {
+					"x-msgpack" => "msgpack",
+					"protobuf" => "x-protobuf",
+					subtype => subtype,
+				};
+
+				(range_type == "*" || range_type == type_) && (range_subtype == "*" || range_subtype == subtype)
+			})
+		})
+	}
+
+	/// Renders the schedule of `day` in this format.
+	/// `timestamps` only applies to json and msgpack, the other formats have a fixed representation.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if the schedule couldn't be serialized.
+	pub fn render(self, day: Schoolday, schedule: &SubstitutionSchedule, timestamps: Timestamps) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+		Ok(match (self, timestamps) {
+			(Format::Json, Timestamps::Rfc3339) => serde_json::to_vec(&Rfc3339Schedule::from(schedule))?,
+			(Format::MsgPack, Timestamps::Rfc3339) => rmp_serde::to_vec_named(&Rfc3339Schedule::from(schedule))?,
+			_ => self.render_default(day, schedule)?,
+		})
+	}
+
+	fn render_default(self, day: Schoolday, schedule: &SubstitutionSchedule) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+		Ok(match self {
+			Format::Json => serde_json::to_vec(schedule)?,
+			Format::MsgPack => rmp_serde::to_vec_named(schedule)?,
+			Format::Protobuf => proto::SubstitutionSchedule::from(schedule).encode_to_vec(),
+			Format::Html => render_html(day, schedule).into_bytes(),
+			Format::Csv => render_csv(schedule).into_bytes(),
+			Format::ICal => render_ical(day, schedule).into_bytes(),
+			Format::Markdown => render_markdown(day, schedule).into_bytes(),
+		})
+	}
+}
+
+/// One row per class and block that has substitutions.
+fn render_csv(schedule: &SubstitutionSchedule) -> String {
+	let mut out = String::from("class,block,substitution\r\n");
+
+	for (class, column) in schedule.entries() {
+		for (block, text) in column.blocks() {
+			let _ = write!(out, "{},{block},{}\r\n", escape_csv(class), escape_csv(text));
+		}
+	}
+
+	out
+}
+
+fn escape_csv(field: &str) -> String {
+	if field.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+/// A standalone page with one table row per class and one column per block.
+fn render_html(day: Schoolday, schedule: &SubstitutionSchedule) -> String {
+	let mut out = String::new();
+	let _ = write!(
+		out,
+		"<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Vertretungsplan {day}</title></head>\n<body>\n<h1>Vertretungsplan {day}</h1>\n<table>\n<tr><th>Klasse</th>"
+	);
+	for block in 0..6 {
+		let _ = write!(out, "<th>{block}</th>");
+	}
+	out.push_str("</tr>\n");
+
+	for (class, column) in schedule.entries() {
+		let _ = write!(out, "<tr><th>{}</th>", escape_html(class));
+		let mut blocks = column.blocks().peekable();
+		for block in 0..6 {
+			match blocks.next_if(|(idx, _)| *idx == block) {
+				Some((_, text)) => {
+					let _ = write!(out, "<td>{}</td>", escape_html(text).replace('\n', "<br>"));
+				}
+				None => out.push_str("<td></td>"),
+			}
+		}
+		out.push_str("</tr>\n");
+	}
+
+	out.push_str("</table>\n</body>\n</html>\n");
+	out
+}
+
+fn escape_html(text: &str) -> String {
+	text
+		.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+/// A heading per class with a list of its blocks, to be pasted into wikis and messengers.
+fn render_markdown(day: Schoolday, schedule: &SubstitutionSchedule) -> String {
+	let mut out = format!("# Vertretungsplan {day}\n");
+
+	for (class, column) in schedule.entries() {
+		let _ = write!(out, "\n## {}\n\n", escape_markdown(class));
+		for (block, text) in column.blocks() {
+			let lines: Vec<String> = text.lines().map(|line| escape_markdown(line.trim())).collect();
+			let _ = writeln!(out, "- Block {block}: {}", lines.join("  \n  "));
+		}
+	}
+
+	out
+}
+
+fn escape_markdown(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '#' | '|' | '<' | '>') {
+			escaped.push('\\');
+		}
+		escaped.push(c);
+	}
+	escaped
+}
+
+/// One all-day event per class and block on the date the schedule applies to, block times are not part of the PDF.
+/// Schedules without an effective date fall back to the date of the PDF.
+fn render_ical(day: Schoolday, schedule: &SubstitutionSchedule) -> String {
+	let date = schedule.effective_date()
+		.or_else(|| Utc.timestamp_millis_opt(schedule.pdf_issue_date).single().map(|issued| issued.date_naive()))
+		.unwrap_or_else(|| Utc::now().date_naive())
+		.format("%Y%m%d");
+	let stamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+
+	let mut out = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//substitution_pdf_server//DE\r\n");
+	for (class, column) in schedule.entries() {
+		for (block, text) in column.blocks() {
+			let _ = write!(
+				out,
+				"BEGIN:VEVENT\r\nUID:{date}-{day}-{}-{block}@substitution_pdf_server\r\nDTSTAMP:{stamp}\r\nDTSTART;VALUE=DATE:{date}\r\nSUMMARY:{} Block {block}\r\nDESCRIPTION:{}\r\nEND:VEVENT\r\n",
+				escape_ical(class).replace(' ', "_"),
+				escape_ical(class),
+				escape_ical(text),
+			);
+		}
+	}
+	out.push_str("END:VCALENDAR\r\n");
+
+	out
+}
+
+fn escape_ical(text: &str) -> String {
+	text
+		.replace('\\', "\\\\")
+		.replace(';', "\\;")
+		.replace(',', "\\,")
+		.replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use actix_web::http::header::ACCEPT;
+	use actix_web::test::TestRequest;
+	use super::*;
+
+	fn negotiate(accept: &str) -> Option<Format> {
+		Format::negotiate(&TestRequest::default().insert_header((ACCEPT, accept)).to_http_request())
+	}
+
+	#[test]
+	fn json_is_served_without_an_accept_header() {
+		assert_eq!(Format::negotiate(&TestRequest::default().to_http_request()), Some(Format::Json));
+	}
+
+	#[test]
+	fn the_most_preferred_format_is_picked() {
+		assert_eq!(negotiate("text/html"), Some(Format::Html));
+		assert_eq!(negotiate("text/csv;q=0.5, text/calendar"), Some(Format::ICal));
+		assert_eq!(negotiate("text/markdown, text/csv"), Some(Format::Markdown));
+		assert_eq!(negotiate("text/*"), Some(Format::Html));
+		assert_eq!(negotiate("*/*"), Some(Format::Json));
+	}
+
+	#[test]
+	fn both_spellings_of_unregistered_types_are_accepted() {
+		assert_eq!(negotiate("application/x-msgpack"), Some(Format::MsgPack));
+		assert_eq!(negotiate("application/protobuf"), Some(Format::Protobuf));
+	}
+
+	#[test]
+	fn unacceptable_formats_are_not_served() {
+		assert_eq!(negotiate("image/png"), None);
+		assert_eq!(negotiate("application/json;q=0"), None);
+	}
+
+	#[test]
+	fn the_format_parameter_takes_precedence() {
+		let req = TestRequest::default().insert_header((ACCEPT, "text/html")).to_http_request();
+		assert_eq!(Format::select(&req, Some("ics")), Some(Format::ICal));
+		assert_eq!(Format::select(&req, Some("pdf")), None);
+		assert_eq!(Format::select(&req, None), Some(Format::Html));
+	}
+
+	#[test]
+	fn markdown_has_a_heading_per_class_and_a_line_per_block() {
+		let schedule: SubstitutionSchedule = serde_json::from_value(serde_json::json!({
+			"pdf_issue_date": 1_652_054_400_000_i64,
+			"struct_time": 0,
+			"entries": {"10A": {"1": "Mathe\n Raum 204 ", "3": "*Sport* entfällt"}, "5_B": {"0": "Englisch"}},
+		})).unwrap();
+
+		assert_eq!(
+			render_markdown(Schoolday::Monday, &schedule),
+			"# Vertretungsplan Monday\n\n## 5\\_B\n\n- Block 0: Englisch\n\n## 10A\n\n- Block 1: Mathe  \n  Raum 204\n- Block 3: \\*Sport\\* entfällt\n",
+		);
+	}
+}
+
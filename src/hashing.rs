@@ -0,0 +1,70 @@
+use sha2::{Digest, Sha512};
+use crate::CONFIG;
+use crate::config::HashAlgorithm;
+
+impl HashAlgorithm {
+	/// Hashes `bytes`, hex encoded.
+	pub fn hash(self, bytes: &[u8]) -> String {
+		match self {
+			HashAlgorithm::Sha512 => hex::encode(Sha512::digest(bytes)),
+			HashAlgorithm::Blake3 => blake3::hash(bytes).to_hex().to_string(),
+		}
+	}
+
+	/// The name the algorithm is stored with next to the hash.
+	pub fn as_str(self) -> &'static str {
+		match self {
+			HashAlgorithm::Sha512 => "sha512",
+			HashAlgorithm::Blake3 => "blake3",
+		}
+	}
+
+	/// The algorithm a hex encoded hash was created with, told apart by its length.
+	/// Further days of a pdf are stored under `<hash>-<day>`, the day is ignored.
+	/// `None` if it isn't a hash at all.
+	pub fn of(hash: &str) -> Option<Self> {
+		let digest = hash.split_once('-').map_or(hash, |(digest, _)| digest);
+		if !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+			return None;
+		}
+
+		match digest.len() {
+			128 => Some(HashAlgorithm::Sha512),
+			64 => Some(HashAlgorithm::Blake3),
+			_ => None,
+		}
+	}
+}
+
+/// Hashes `bytes` with the configured algorithm, to tell whether a pdf changed and to address it in the archive.
+pub fn hash(bytes: &[u8]) -> String {
+	CONFIG.hash_algorithm.hash(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SHA512_ABC: &str = "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f";
+	const BLAKE3_EMPTY: &str = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+
+	#[test]
+	fn hashes_are_hex_encoded_digests() {
+		assert_eq!(HashAlgorithm::Sha512.hash(b"abc"), SHA512_ABC);
+		assert_eq!(HashAlgorithm::Blake3.hash(b""), BLAKE3_EMPTY);
+	}
+
+	#[test]
+	fn the_algorithm_is_told_by_the_length() {
+		assert_eq!(HashAlgorithm::of(SHA512_ABC), Some(HashAlgorithm::Sha512));
+		assert_eq!(HashAlgorithm::of(BLAKE3_EMPTY), Some(HashAlgorithm::Blake3));
+		assert_eq!(HashAlgorithm::of(&format!("{BLAKE3_EMPTY}-Tuesday")), Some(HashAlgorithm::Blake3));
+	}
+
+	#[test]
+	fn other_strings_are_no_hashes() {
+		assert_eq!(HashAlgorithm::of("abc"), None);
+		assert_eq!(HashAlgorithm::of(&BLAKE3_EMPTY.replace('a', "g")), None);
+		assert_eq!(HashAlgorithm::of(&SHA512_ABC[..100]), None);
+	}
+}
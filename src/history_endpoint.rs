@@ -0,0 +1,131 @@
+use actix_web::{get, HttpResponse, Responder, web};
+use actix_web::http::header;
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::error;
+use crate::{diff, Schoolday, storage};
+use crate::config::HashAlgorithm;
+use crate::storage::Pools;
+
+/// A stored schedule and when it was stored.
+#[derive(Debug, Serialize)]
+struct StoredSchedule {
+	hash: String,
+	/// `sha512` or `blake3`.
+	hash_algorithm: String,
+	/// The day the schedule was fetched for, unknown for rows stored before days were recorded.
+	day: Option<Schoolday>,
+	/// The issue date of the pdf, in milliseconds since the unix epoch.
+	pdf_date: i64,
+	/// When the schedule was stored, in milliseconds since the unix epoch.
+	insertion_time: Option<i64>,
+	json: Option<serde_json::Value>,
+	/// Whether the pdf is stored as well, at `/history/hash/{hash}/pdf`.
+	has_pdf: bool,
+	/// The schedule of the same day stored before this one.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	previous_hash: Option<String>,
+	/// The JSON Merge Patch (RFC 7386) turning the previous schedule into this one.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	merge_patch: Option<serde_json::Value>,
+}
+
+/// Serves the schedule stored for the pdf with the hash, e.g. the one a notification or log line names.
+/// Further days of a pdf are stored under `<hash>-<day>`, which is accepted as well.
+#[get("/history/hash/{hash}")]
+pub async fn get_history_by_hash(hash: web::Path<String>, pools: web::Data<Pools>) -> impl Responder {
+	let hash = hash.into_inner().to_ascii_lowercase();
+	if !is_stored_hash(&hash) {
+		return HttpResponse::BadRequest().body("Expected a hex encoded SHA512 or BLAKE3 hash");
+	}
+
+	match stored_schedule(&hash, &pools.read).await {
+		Ok(Some(stored)) => HttpResponse::Ok().json(stored),
+		Ok(None) => HttpResponse::NotFound().body(format!("There is no schedule for {hash}")),
+		Err(why) => {
+			error!("Couldn't look up the schedule of {hash}: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// Serves the pdf stored with the schedule of the hash, byte for byte as the school published it.
+#[get("/history/hash/{hash}/pdf")]
+pub async fn get_history_pdf(hash: web::Path<String>, pools: web::Data<Pools>) -> impl Responder {
+	let hash = hash.into_inner().to_ascii_lowercase();
+	if !is_stored_hash(&hash) {
+		return HttpResponse::BadRequest().body("Expected a hex encoded SHA512 or BLAKE3 hash");
+	}
+
+	match storage::load_pdf(&pools.read, &hash).await {
+		Ok(Some(pdf)) => HttpResponse::Ok()
+			.content_type("application/pdf")
+			.insert_header((header::ETAG, format!("\"{hash}\"")))
+			.insert_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"))
+			.body(pdf),
+		Ok(None) => HttpResponse::NotFound().body(format!("There is no pdf for {hash}")),
+		Err(why) => {
+			error!("Couldn't read the pdf of {hash}: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// Whether `hash` looks like a hash of a pdf, optionally followed by the day of a further section of the pdf.
+fn is_stored_hash(hash: &str) -> bool {
+	let day = hash.split_once('-').map(|(_, day)| day);
+	HashAlgorithm::of(hash).is_some() && day.is_none_or(|day| day.parse::<Schoolday>().is_ok())
+}
+
+async fn stored_schedule(hash: &str, pool: &PgPool) -> Result<Option<StoredSchedule>, sqlx::Error> {
+	let row = sqlx::query!(r#"SELECT hash, hash_algorithm, day, pdf_date, insertion_time, pdf IS NOT NULL AS "has_pdf!" FROM substitution_json WHERE hash = $1"#, hash)
+		.fetch_optional(pool)
+		.await?;
+
+	let row = match row {
+		Some(row) => row,
+		None => return Ok(None),
+	};
+
+	// Rows stored before days were recorded can't tell which schedule they followed.
+	let previous = match (row.day, row.insertion_time) {
+		(Some(day), Some(insertion_time)) => sqlx::query_scalar!(
+			r#"
+			SELECT hash AS "hash!"
+			FROM substitution_json
+			WHERE day = $1 AND (pdf_date, insertion_time) < ($2, $3) AND hash IS NOT NULL
+			ORDER BY pdf_date DESC, insertion_time DESC
+			LIMIT 1
+			"#,
+			day,
+			row.pdf_date,
+			insertion_time
+		)
+			.fetch_optional(pool)
+			.await?,
+		_ => None,
+	};
+
+	// Superseded schedules are stored as patches of the ones that replaced them.
+	let json = storage::load_json(pool, hash).await?;
+	let previous_json = match &previous {
+		Some(previous) => storage::load_json(pool, previous).await?,
+		None => None,
+	};
+	let merge_patch = previous_json.as_ref()
+		.zip(json.as_ref())
+		.map(|(previous, json)| diff::merge_patch(previous, json));
+
+	Ok(Some(StoredSchedule {
+		hash: row.hash.unwrap_or_default(),
+		hash_algorithm: row.hash_algorithm,
+		day: row.day.and_then(|day| usize::try_from(day).ok()).and_then(|day| Schoolday::ALL.get(day).copied()),
+		pdf_date: Utc.from_utc_datetime(&row.pdf_date).timestamp_millis(),
+		insertion_time: row.insertion_time.map(|time| Utc.from_utc_datetime(&time).timestamp_millis()),
+		json,
+		has_pdf: row.has_pdf,
+		previous_hash: previous,
+		merge_patch,
+	}))
+}
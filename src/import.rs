@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+use chrono::{Datelike, Local, TimeZone};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{debug, info, warn};
+use crate::{conversion, hashing, Schoolday, storage};
+use crate::util::TEMP_DIR;
+use crate::classes::NORMALIZER;
+use crate::json_handler::section_hash;
+
+/// The outcome of importing a directory of archived pdfs.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+	/// Pdfs that were converted and stored.
+	pub imported: usize,
+	/// Pdfs that were stored already.
+	pub skipped: usize,
+	pub failed: Vec<FailedImport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedImport {
+	pub path: PathBuf,
+	pub error: String,
+}
+
+/// Converts every pdf in `dir` and its subdirectories and stores the ones that aren't stored yet.
+/// The day of a pdf is taken from the date it was issued for, files that aren't pdfs are ignored.
+///
+/// # Errors
+///
+/// Returns `Err` if the directory couldn't be read, failures of single pdfs are only reported.
+pub async fn import_dir(dir: &Path, pool: &PgPool) -> Result<ImportReport, std::io::Error> {
+	let mut report = ImportReport::default();
+	let mut dirs = vec![dir.to_path_buf()];
+
+	while let Some(dir) = dirs.pop() {
+		let mut entries = tokio::fs::read_dir(&dir).await?;
+
+		while let Some(entry) = entries.next_entry().await? {
+			let path = entry.path();
+			if entry.file_type().await?.is_dir() {
+				dirs.push(path);
+				continue;
+			}
+
+			match import_file(&path, pool).await {
+				Ok(Some(true)) => report.imported += 1,
+				Ok(Some(false)) => report.skipped += 1,
+				Ok(None) => debug!("Ignoring {}, it isn't a pdf", path.display()),
+				Err(why) => {
+					warn!("Couldn't import {}: {why}", path.display());
+					report.failed.push(FailedImport {
+						path,
+						error: why.to_string(),
+					});
+				}
+			}
+		}
+	}
+
+	info!("Imported {} pdfs, skipped {} and failed on {}", report.imported, report.skipped, report.failed.len());
+	Ok(report)
+}
+
+/// Returns whether the pdf was stored, or `None` if the file isn't a pdf.
+async fn import_file(path: &Path, pool: &PgPool) -> Result<Option<bool>, Box<dyn std::error::Error + Send + Sync>> {
+	let pdf = tokio::fs::read(path).await?;
+	if !pdf.starts_with(b"%PDF") {
+		return Ok(None);
+	}
+
+	let hash = hashing::hash(&pdf);
+	let stored = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM substitution_json WHERE hash = $1)", hash)
+		.fetch_one(pool)
+		.await?
+		.unwrap_or(false);
+	if stored {
+		return Ok(Some(false));
+	}
+
+	let temp_dir = tempfile::Builder::new().tempdir_in(&*TEMP_DIR)?;
+	let (schedules, _) = conversion::convert_pdf(&pdf, temp_dir.path()).await?;
+
+	// Every further day a pdf covers is stored under its own hash, like when it is downloaded.
+	let mut inserted = false;
+	for (section, mut schedule) in schedules.into_iter().enumerate() {
+		schedule.normalize_class_names(&NORMALIZER);
+
+		let pdf_date = Local.timestamp_opt(schedule.pdf_issue_date / 1000, 0).unwrap();
+		let day = Schoolday::from(pdf_date.weekday());
+		schedule.assign_day(day);
+		let (hash, pdf) = if section == 0 { (hash.clone(), Some(pdf.as_slice())) } else { (section_hash(&hash, day), None) };
+		inserted |= storage::save_schedule(pool, day, &hash, &pdf_date, serde_json::to_value(&schedule)?, pdf, false).await?;
+	}
+
+	Ok(Some(inserted))
+}
@@ -0,0 +1,121 @@
+use std::time::Duration;
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use substitution_pdf_to_json::SubstitutionSchedule;
+use tracing::{debug, error, info};
+use crate::{JSON_HANDLER, Schoolday, storage};
+
+/// The channel instances announce newly stored schedules on.
+pub const CHANNEL: &str = "schedule_updated";
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+lazy_static! {
+	/// Identifies this instance, so it can ignore its own notifications.
+	static ref INSTANCE_ID: String = format!("{:016x}", rand::thread_rng().gen::<u64>());
+}
+
+/// The payload of a notification on [`CHANNEL`].
+#[derive(Debug, Serialize, Deserialize)]
+struct ScheduleUpdated {
+	instance: String,
+	day: Schoolday,
+	hash: String,
+}
+
+/// The payload announcing that `day` now serves the schedule of the pdf with `hash`.
+pub fn payload(day: Schoolday, hash: &str) -> String {
+	serde_json::to_string(&ScheduleUpdated {
+		instance: INSTANCE_ID.clone(),
+		day,
+		hash: hash.to_string(),
+	}).unwrap()
+}
+
+/// Listens for schedules other instances stored and serves them as well.
+/// Has to use the primary, notifications aren't replicated.
+pub async fn listen_loop(pool: PgPool) {
+	let mut warm = false;
+	loop {
+		if let Err(why) = listen(&pool, &mut warm).await {
+			error!("Listening for schedule updates failed: {why}");
+		}
+		tokio::time::sleep(RECONNECT_DELAY).await;
+	}
+}
+
+/// Serves the stored schedules once the first time it listens, so later updates aren't overwritten by older rows.
+async fn listen(pool: &PgPool, warm: &mut bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+	let mut listener = PgListener::connect_with(pool).await?;
+	listener.listen(CHANNEL).await?;
+	info!("Listening for schedule updates of other instances");
+
+	if !*warm {
+		serve_stored(pool).await;
+		*warm = true;
+	}
+
+	loop {
+		// Reconnects by itself, notifications sent in the meantime are lost.
+		let notification = listener.recv().await?;
+		let update: ScheduleUpdated = match serde_json::from_str(notification.payload()) {
+			Ok(update) => update,
+			Err(why) => {
+				error!("Invalid schedule update {:?}: {why}", notification.payload());
+				continue;
+			}
+		};
+
+		if update.instance == *INSTANCE_ID {
+			continue;
+		}
+
+		debug!("{}: Another instance stored {}", update.day, update.hash);
+		if let Err(why) = refresh(pool, update.day, update.hash).await {
+			error!("Couldn't load a schedule another instance stored: {why}");
+		}
+	}
+}
+
+/// Serves the newest stored schedule of every day, so instances that don't fetch the pdfs start with a warm cache.
+async fn serve_stored(pool: &PgPool) {
+	for day in Schoolday::ALL {
+		let newest = sqlx::query_scalar!(
+			r#"
+			SELECT hash
+			FROM substitution_json
+			WHERE day = $1
+			ORDER BY pdf_date DESC, insertion_time DESC
+			LIMIT 1
+			"#,
+			day as i16
+		)
+			.fetch_optional(pool)
+			.await;
+
+		let loaded = match newest {
+			Ok(Some(Some(hash))) => refresh(pool, day, hash).await,
+			Ok(_) => continue,
+			Err(why) => Err(why.into()),
+		};
+		match loaded {
+			Ok(()) => debug!("{day}: Serving the stored schedule"),
+			Err(why) => error!("{day}: Couldn't load the stored schedule: {why}"),
+		}
+	}
+}
+
+async fn refresh(pool: &PgPool, day: Schoolday, hash: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+	let json = storage::load_json(pool, &hash)
+		.await?
+		.ok_or("The schedule isn't in the database")?;
+
+	let mut schedule: SubstitutionSchedule = serde_json::from_value(json)?;
+	// Rows stored before effective dates existed don't have one.
+	if schedule.effective_date().is_none() {
+		schedule.assign_day(day);
+	}
+	JSON_HANDLER.apply_remote(day, hash, schedule).await
+}
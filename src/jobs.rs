@@ -0,0 +1,114 @@
+use std::time::Duration;
+use bytes::Bytes;
+use chrono::Utc;
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+use crate::{pipeline, Schoolday};
+use crate::storage::{StorageError, with_retry};
+
+/// Attempts after which a job is given up and only kept for inspection.
+const MAX_ATTEMPTS: i32 = 5;
+/// How long a pending job is left alone before the worker picks it up,
+/// long enough for the fetch that created it to finish.
+const RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+const WORKER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Persists a conversion job before it is started.
+/// A job for the same pdf is kept as it is, including its attempts.
+pub async fn enqueue(pool: &PgPool, day: Schoolday, hash: &str, pdf: &[u8]) -> Result<(), StorageError> {
+	let now = Utc::now().naive_utc();
+
+	with_retry(|| sqlx::query!(
+		r#"
+		INSERT INTO conversion_jobs (hash, day, pdf, created_at, updated_at)
+		VALUES ($1, $2, $3, $4, $4)
+		ON CONFLICT (hash) DO NOTHING
+		"#,
+		hash,
+		day as i16,
+		pdf,
+		now
+	)
+		.execute(pool)).await?;
+
+	Ok(())
+}
+
+/// Counts a failed attempt of a job, after `MAX_ATTEMPTS` it is marked as failed and not retried anymore.
+pub async fn fail(pool: &PgPool, hash: &str, error: &str) -> Result<(), StorageError> {
+	let now = Utc::now().naive_utc();
+
+	with_retry(|| sqlx::query!(
+		r#"
+		UPDATE conversion_jobs
+		SET attempts = attempts + 1,
+			state = CASE WHEN attempts + 1 >= $2 THEN 'failed' ELSE 'pending' END,
+			last_error = $3,
+			updated_at = $4
+		WHERE hash = $1
+		"#,
+		hash,
+		MAX_ATTEMPTS,
+		error,
+		now
+	)
+		.execute(pool)).await?;
+
+	Ok(())
+}
+
+/// Gets the pdf of a job, `None` if there is no job for this hash.
+pub async fn load_pdf(pool: &PgPool, hash: &str) -> Result<Option<Vec<u8>>, sqlx::Error> {
+	sqlx::query_scalar!("SELECT pdf FROM conversion_jobs WHERE hash = $1", hash)
+		.fetch_optional(pool)
+		.await
+}
+
+/// Retries pending jobs that weren't touched for `RETRY_AFTER`, e.g. because the server stopped while converting them.
+pub async fn job_worker(pool: PgPool) {
+	loop {
+		if let Err(why) = retry_pending(&pool).await {
+			error!("Couldn't retry the pending conversion jobs: {why}");
+		}
+
+		tokio::time::sleep(WORKER_INTERVAL).await;
+	}
+}
+
+async fn retry_pending(pool: &PgPool) -> Result<(), StorageError> {
+	let now = Utc::now().naive_utc();
+	#[allow(clippy::cast_possible_wrap)]
+	let stale_before = now - chrono::Duration::seconds(RETRY_AFTER.as_secs() as i64);
+
+	// Claim the jobs by touching them, so they aren't picked up again while being retried.
+	let jobs = sqlx::query!(
+		r#"
+		UPDATE conversion_jobs
+		SET updated_at = $1
+		WHERE state = 'pending' AND updated_at < $2
+		RETURNING hash, day, pdf
+		"#,
+		now,
+		stale_before
+	)
+		.fetch_all(pool)
+		.await?;
+
+	for job in jobs {
+		let day = match usize::try_from(job.day).ok().and_then(|day| Schoolday::ALL.get(day)) {
+			Some(day) => *day,
+			None => {
+				warn!("Conversion job {} has the invalid day {}", job.hash, job.day);
+				fail(pool, &job.hash, "Invalid day").await?;
+				continue;
+			}
+		};
+
+		info!("Retrying the conversion job of {day}");
+		if let Err(why) = pipeline::retry(day, job.hash, Bytes::from(job.pdf), pool.clone()).await {
+			error!("{why}");
+		}
+	}
+
+	Ok(())
+}
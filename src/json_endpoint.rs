@@ -1,14 +1,163 @@
-use actix_web::{get, HttpResponse, Responder, web};
-use crate::{JSON_HANDLER, Schoolday};
+use actix_web::{get, HttpRequest, HttpResponse, Responder, web};
+use actix_web::http::header::{self, ContentEncoding};
+use chrono::{Datelike, Local};
+use serde::Deserialize;
+use substitution_pdf_to_json::SubstitutionSchedule;
+use tracing::error;
+use crate::{CONFIG, Schoolday, weekend};
+use crate::classes::ClassSelector;
+use crate::config::WeekendFallback;
+use crate::formats::{Format, Timestamps};
+use crate::encoded;
+use crate::signing::SIGNATURE_HEADER;
+use crate::stateless::served_schedule;
+use crate::storage::Pools;
 
+/// Query parameters of the day endpoint.
+#[derive(Debug, Deserialize)]
+pub struct DayQuery {
+	/// Comma separated classes or class groups to restrict the response to.
+	pub classes: Option<String>,
+	/// `rfc3339` to write timestamps as strings instead of milliseconds.
+	#[serde(default)]
+	pub timestamps: Timestamps,
+	/// Name of the format to respond with, like `md`, overriding the `Accept` header.
+	pub format: Option<String>,
+}
+
+/// Serves the schedule of a day in the format negotiated from the `Accept` header.
 #[get("/{schoolday}")]
-pub async fn get_schoolday_pdf_json(day: web::Path<Schoolday>) -> impl Responder {
-	if let Some(json) = JSON_HANDLER.get_json(*day).await {
-		return HttpResponse::Ok()
-			.content_type("application/json")
-			.body(json);
+pub async fn get_schoolday_pdf_json(req: HttpRequest, day: web::Path<Schoolday>, query: web::Query<DayQuery>, pools: web::Data<Pools>) -> impl Responder {
+	day_response(&req, *day, &query, &pools).await
+}
+
+/// Serves the schedule of the current day, see [`WeekendFallback`] for what is served on weekends.
+#[get("/today")]
+pub async fn get_today(req: HttpRequest, query: web::Query<DayQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let weekday = Local::now().weekday();
+
+	match (Schoolday::from_weekday(weekday), CONFIG.weekend_fallback) {
+		(Some(day), _) => day_response(&req, day, &query, &pools).await,
+		(None, WeekendFallback::NextMonday) => weekend::mark_fallback(day_response(&req, Schoolday::Monday, &query, &pools).await, weekday, Schoolday::Monday),
+		(None, WeekendFallback::NotFound) => weekend::not_found(weekday, &format!("/{}", Schoolday::Monday)),
+	}
+}
+
+async fn day_response(req: &HttpRequest, day: Schoolday, query: &DayQuery, pools: &Pools) -> HttpResponse {
+	let format = match Format::select(req, query.format.as_deref()) {
+		Some(format) => format,
+		None => return HttpResponse::NotAcceptable().finish(),
+	};
+
+	// The prerendered json and msgpack are served as is, compressed ahead of time.
+	if query.classes.is_none() && query.timestamps == Timestamps::Millis && matches!(format, Format::Json | Format::MsgPack) {
+		let encoding = encoded::negotiate(req);
+
+		return match encoded::encoded(day, format, encoding, &pools.read).await {
+			Ok(Some(encoded)) => {
+				let mut response = HttpResponse::Ok();
+				response
+					.content_type(format.content_type())
+					.insert_header((header::VARY, "Accept, Accept-Encoding"));
+				if encoding != ContentEncoding::Identity {
+					response.insert_header(encoding);
+				}
+				if let Some(signature) = encoded.signature {
+					response.insert_header((SIGNATURE_HEADER, signature));
+				}
+				response.body(encoded.body)
+			}
+			Ok(None) => no_schedule_yet(),
+			Err(why) => {
+				error!("{why}");
+				HttpResponse::InternalServerError().finish()
+			}
+		};
+	}
+
+	let schedule = match served_schedule(day, &pools.read).await {
+		Ok(Some(schedule)) => schedule,
+		Ok(None) => return no_schedule_yet(),
+		Err(why) => {
+			error!("{why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	let body = match &query.classes {
+		Some(classes) => format.render(day, &filter_schedule(&schedule, classes), query.timestamps),
+		None => format.render(day, &schedule, query.timestamps),
+	};
+
+	match body {
+		Ok(body) => HttpResponse::Ok()
+			.content_type(format.content_type())
+			.insert_header((header::VARY, "Accept"))
+			.body(body),
+		Err(why) => {
+			error!("{why}");
+			HttpResponse::InternalServerError().finish()
+		}
 	}
+}
+
+/// Serves the schedule of a single class, or of every class in a configured group.
+#[get("/{schoolday}/classes/{class}")]
+pub async fn get_schoolday_class_json(req: HttpRequest, path: web::Path<(Schoolday, String)>, query: web::Query<DayQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let (day, class) = path.into_inner();
+
+	let schedule = match served_schedule(day, &pools.read).await {
+		Ok(schedule) => schedule,
+		Err(why) => {
+			error!("{why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	if let Some(schedule) = schedule {
+		let selector = ClassSelector::new(&class);
+		let schedule = schedule.filter_classes(|class| selector.matches(class));
+
+		if schedule.entries().is_empty() {
+			return HttpResponse::NotFound().finish();
+		}
+
+		// Only an explicitly requested format, this endpoint always served json regardless of `Accept`.
+		let format = match query.format.as_deref() {
+			Some(name) => match Format::select(&req, Some(name)) {
+				Some(format) => format,
+				None => return HttpResponse::NotAcceptable().finish(),
+			},
+			None => Format::Json,
+		};
+
+		return match format.render(day, &schedule, query.timestamps) {
+			Ok(body) => HttpResponse::Ok()
+				.content_type(format.content_type())
+				.body(body),
+			Err(why) => {
+				error!("{why}");
+				HttpResponse::InternalServerError().finish()
+			}
+		};
+	}
+
+	no_schedule_yet()
+}
+
+/// Restricts the schedule to the comma separated `classes`.
+pub fn filter_schedule(schedule: &SubstitutionSchedule, classes: &str) -> SubstitutionSchedule {
+	let selectors: Vec<ClassSelector> = classes
+		.split(',')
+		.map(str::trim)
+		.filter(|class| !class.is_empty())
+		.map(ClassSelector::new)
+		.collect();
+
+	schedule.filter_classes(|class| selectors.iter().any(|selector| selector.matches(class)))
+}
 
+fn no_schedule_yet() -> HttpResponse {
 	HttpResponse::NoContent()
 		.append_header(("Retry-After", "120"))
 		.finish()
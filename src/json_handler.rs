@@ -1,159 +1,463 @@
 use std::collections::HashMap;
-use std::io::Write;
-use std::path::Path;
-use chrono::{DateTime, Local, TimeZone, Utc};
-use sha2::{Sha512, Digest};
+use std::sync::Arc;
+use bytes::Bytes;
+use chrono::{Local, TimeZone, Utc};
+use serde::Serialize;
 use sqlx::PgPool;
 use substitution_pdf_to_json::SubstitutionSchedule;
-use tokio::sync::RwLock;
+use arc_swap::ArcSwap;
+use tokio::sync::{RwLock, watch};
 use tracing::{debug, error, info, trace};
-use crate::{Schoolday, util};
-use tokio::io::AsyncWriteExt;
-use crate::PDF_STORE_LOCATION;
+use crate::{CONFIG, METRICS, Schoolday};
+use crate::classes::NORMALIZER;
+use crate::{conversion, notifier, stateless, storage};
+use crate::metrics::Stage;
+use crate::pipeline::split_days;
+use crate::util::TEMP_DIR;
 
+/// What is served for the days, read without locks so requests never wait for an update.
 pub struct JsonHandler {
-	jsons: RwLock<HashMap<Schoolday, String>>,
-	hashes: RwLock<HashMap<Schoolday, String>>,
+	/// Shared with the responses, so serving a day doesn't copy it.
+	jsons: DayMap<VersionedJson>,
+	msgpacks: DayMap<Bytes>,
+	hashes: DayMap<String>,
+	errors: RwLock<HashMap<Schoolday, UpdateError>>,
+	statuses: RwLock<HashMap<Schoolday, DayStatus>>,
+	schedules: DayMap<Arc<SubstitutionSchedule>>,
+	pdfs: DayMap<ServedPdf>,
+	/// The schedules the served ones replaced, to tell what changed.
+	previous: DayMap<Arc<SubstitutionSchedule>>,
+	/// Counts the changes of the served schedules, so waiting requests wake up.
+	changes: watch::Sender<u64>,
+}
+
+/// A value per day that readers load as a snapshot, writers replace the whole map.
+/// Writes are rare and the map has five entries at most, so copying it is cheap.
+struct DayMap<T> {
+	days: ArcSwap<HashMap<Schoolday, T>>,
+}
+
+impl<T: Clone> DayMap<T> {
+	fn new() -> Self {
+		Self {
+			days: ArcSwap::from_pointee(HashMap::new()),
+		}
+	}
+
+	fn get(&self, day: Schoolday) -> Option<T> {
+		self.days.load().get(&day).cloned()
+	}
+
+	/// Sets the value of `day` and returns the one it replaced.
+	fn insert(&self, day: Schoolday, value: T) -> Option<T> {
+		let mut old = None;
+		self.days.rcu(|days| {
+			let mut days = HashMap::clone(days);
+			old = days.insert(day, value.clone());
+			days
+		});
+		old
+	}
+
+	fn remove(&self, day: Schoolday) -> Option<T> {
+		let mut old = None;
+		self.days.rcu(|days| {
+			let mut days = HashMap::clone(days);
+			old = days.remove(&day);
+			days
+		});
+		old
+	}
+}
+
+/// A served json and the hash of the stored schedule it is, which tells clients the version they have.
+/// Both are replaced at once, so they always belong together.
+#[derive(Debug, Clone)]
+pub struct VersionedJson {
+	/// The hash of the pdf of the day, or the section hash of the pdf of the day covering it.
+	pub hash: String,
+	pub json: Bytes,
+}
+
+/// The pdf a day is served from.
+#[derive(Debug, Clone)]
+pub struct ServedPdf {
+	pub hash: String,
+	pub bytes: Bytes,
+}
+
+/// Freshness information about a day, served by `/status`.
+/// All times are in milliseconds since the epoch.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DayStatus {
+	/// When the pdf of the day was last downloaded successfully.
+	pub last_fetch: Option<i64>,
+	/// When the pdf of the day last changed.
+	pub last_change: Option<i64>,
+	/// The issue date of the pdf the current json was created from.
+	pub pdf_issue_date: Option<i64>,
+	/// How many updates of the day failed in a row.
+	pub consecutive_failures: u32,
+	/// When the current streak of failures started.
+	pub failing_since: Option<i64>,
+	/// The day whose pdf also covered this day and is served instead of the pdf of this day, which is older.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub covered_by: Option<Schoolday>,
+	/// Whether the operator was alerted about the current or a just recovered streak of failures.
+	#[serde(skip)]
+	pub alerted: bool,
+}
+
+/// The most recent error that happened while updating a day.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateError {
+	/// When the error happened, in milliseconds since the epoch.
+	pub time: i64,
+	/// The pipeline stage the error happened in.
+	pub stage: Stage,
+	pub message: String,
 }
 
 impl JsonHandler {
 	pub fn new() -> Self {
-		let jsons = RwLock::new(HashMap::new());
-		let hashes = RwLock::new(HashMap::new());
+		let jsons = DayMap::new();
+		let msgpacks = DayMap::new();
+		let hashes = DayMap::new();
+		let errors = RwLock::new(HashMap::new());
+		let statuses = RwLock::new(HashMap::new());
+		let schedules = DayMap::new();
+		let pdfs = DayMap::new();
+		let previous = DayMap::new();
+		let (changes, _) = watch::channel(0);
 
 		Self {
 			jsons,
+			msgpacks,
 			hashes,
+			errors,
+			statuses,
+			schedules,
+			pdfs,
+			previous,
+			changes,
 		}
 	}
 
-	/// Updates the internal json store.
-	/// Also saves the json in the database.
-	#[allow(clippy::similar_names)]
-	pub async fn update(&self, day: Schoolday, pdf: Vec<u8>, pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
-		let mut hasher = Sha512::new();
-		Digest::update(&mut hasher, &pdf);
-		let hash_bytes = hasher.finalize();
-		let hash = hex::encode(hash_bytes);
-
-		let hashes = self.hashes.read().await;
-		if let Some(old_hash) = hashes.get(&day) {
-			if hash == *old_hash {
-				debug!("{day}: New hash matched old hash");
-				return Ok(());
-			}
+	/// Records that the pdf of `day` was just downloaded.
+	pub async fn fetched(&self, day: Schoolday) {
+		let mut statuses = self.statuses.write().await;
+		statuses.entry(day).or_default().last_fetch = Some(Utc::now().timestamp_millis());
+	}
+
+	/// Serves the schedule converted from the pdf of `day`, unless the day serves a newer pdf of another day.
+	/// The hash is stored either way, so the pdf isn't converted again.
+	pub async fn serve(&self, day: Schoolday, hash: String, json: String, msgpack: Vec<u8>, schedule: SubstitutionSchedule, pdf: ServedPdf) {
+		let pdf_issue_date = schedule.pdf_issue_date;
+
+		if self.covered_by_newer(day, pdf_issue_date).await {
+			info!("{day}: Keeping the newer schedule of another day's pdf");
+		} else {
+			stateless::forget(day).await;
+			self.store(day, hash.clone(), json, msgpack, schedule);
+			self.store_pdf(day, Some(pdf));
+			tokio::spawn(notifier::dispatch(day));
 		}
 
-		// Drop the read lock as it is not needed anymore.
-		// We would also deadlock as we request a read lock next.
-		std::mem::drop(hashes);
+		// The hash is only stored once the json is, so a failed conversion is retried on the next fetch.
+		trace!("Putting new hash into hash store.");
+		let _ = self.hashes.insert(day, hash);
+		self.announce_change();
 
 		{
-			trace!("Putting new hash into hash store.");
-			let mut hashes = self.hashes.write().await;
-			let _ = hashes.insert(day, hash.clone());
+			let mut statuses = self.statuses.write().await;
+			let status = statuses.entry(day).or_default();
+			if status.covered_by.is_none() || status.pdf_issue_date.is_none_or(|served| served <= pdf_issue_date) {
+				status.last_change = Some(Utc::now().timestamp_millis());
+				status.pdf_issue_date = Some(pdf_issue_date);
+				status.covered_by = None;
+			}
 		}
+		self.reset_failures(day).await;
+		METRICS.update_succeeded();
+	}
+
+	/// Serves `day` from the pdf of `source_day`, which covers both.
+	/// The day is only replaced if the pdf is newer than the one it serves, so it doesn't go back to an older pdf.
+	pub async fn serve_covered(&self, day: Schoolday, source_day: Schoolday, json: String, msgpack: Vec<u8>, schedule: SubstitutionSchedule, pdf: &ServedPdf) {
+		let pdf_issue_date = schedule.pdf_issue_date;
 
-		debug!("Creating temp dir to store pdf for tabula...");
-		let temp_dir_path = util::make_temp_dir();
-		let temp_file_name = util::get_random_name();
-		debug!("Created temp dir for the pdf!");
-
-		debug!("Writing pdf to temp file...");
-		let temp_file_path = format!("{}/{}", temp_dir_path, temp_file_name);
-		let temp_file_path = Path::new(&temp_file_path);
-		let mut temp_file = std::fs::File::create(temp_file_path).expect("Couldn't create temp pdf file");
-		temp_file.write_all(&pdf).expect("Couldn't write pdf");
-		debug!("Wrote pdf!");
-
-		debug!("Creating json with tabula...");
-		let new_schedule = SubstitutionSchedule::from_pdf(temp_file_path)?;
-		let json = serde_json::to_string(&new_schedule)?;
-		debug!("Created json!");
-
-		debug!("Spawning database update and pdf save task.");
-		tokio::spawn(async move {
-			let pdf_date_time = Local.timestamp(&new_schedule.pdf_issue_date / 1000, 0);
-
-			if let Err(why) = save_pdf_to_disk(day, &pdf, &pdf_date_time).await {
-				error!("{why}");
+		{
+			let mut statuses = self.statuses.write().await;
+			let status = statuses.entry(day).or_default();
+			if status.pdf_issue_date.is_some_and(|served| served >= pdf_issue_date) {
+				debug!("{day}: Already serving a pdf at least as new as the one of {source_day}");
+				return;
 			}
+			status.last_change = Some(Utc::now().timestamp_millis());
+			status.pdf_issue_date = Some(pdf_issue_date);
+			status.covered_by = Some(source_day);
+		}
 
-			let json_value = serde_json::to_value(new_schedule).unwrap();
+		// The hash of the day stays the one of its own pdf, which is only converted again once it changes.
+		stateless::forget(day).await;
+		self.store(day, section_hash(&pdf.hash, day), json, msgpack, schedule);
+		self.store_pdf(day, Some(pdf.clone()));
+		self.announce_change();
+		tokio::spawn(notifier::dispatch(day));
+	}
 
-			update_db(&hash, &pdf_date_time, json_value, pool).await;
+	/// Whether `day` serves the pdf of another day that is newer than `pdf_issue_date`.
+	async fn covered_by_newer(&self, day: Schoolday, pdf_issue_date: i64) -> bool {
+		let statuses = self.statuses.read().await;
+		statuses.get(&day).is_some_and(|status| {
+			status.covered_by.is_some() && status.pdf_issue_date.is_some_and(|served| served > pdf_issue_date)
+		})
+	}
 
-		});
+	/// Converts an archived pdf again with the current parser and replaces its row in the database.
+	/// Days that currently serve this pdf are updated as well and returned.
+	pub async fn reprocess(&self, hash: &str, pdf: &[u8], pool: &PgPool) -> Result<(Vec<Schoolday>, Vec<String>), Box<dyn std::error::Error + Send + Sync>> {
+		let temp_dir = tempfile::Builder::new().tempdir_in(&*TEMP_DIR)?;
+		let (mut schedules, _) = conversion::convert_pdf(pdf, temp_dir.path()).await?;
 
-		{
-			let mut json_store = self.jsons.write().await;
+		let mut serving = Vec::new();
+		for day in Schoolday::ALL {
+			if self.get_hash(day).as_deref() == Some(hash) {
+				serving.push(day);
+			}
+		}
+
+		// Only the day the pdf was downloaded for is stored under its hash, the other days it covers aren't converted again.
+		let mut schedule = match serving.first() {
+			Some(day) => split_days(*day, schedules).0,
+			None => schedules.swap_remove(0),
+		};
+		schedule.normalize_class_names(&NORMALIZER);
 
-			info!("Adding new json for {day} to the json map.");
-			let old = json_store.insert(day, json);
+		let pdf_date = Local.timestamp_opt(schedule.pdf_issue_date / 1000, 0).unwrap();
+		storage::replace_schedule(pool, hash, &pdf_date, serde_json::to_value(&schedule)?, pdf).await?;
 
-			if old.is_some() {
-				trace!("An old json was replaced");
+		// The pdf can be served for several days, each with its own effective date.
+		let mut days = Vec::new();
+		for day in serving {
+			if self.covered_by_newer(day, schedule.pdf_issue_date).await {
+				continue;
 			}
+			let mut schedule = schedule.clone();
+			schedule.assign_day(day);
+			stateless::forget(day).await;
+			self.store(day, hash.to_string(), serde_json::to_string(&schedule)?, rmp_serde::to_vec_named(&schedule)?, schedule);
+			days.push(day);
+		}
+		self.announce_change();
+
+		Ok((days, schedule.warnings().to_vec()))
+	}
+
+	/// Serves a schedule another instance converted and stored in the database.
+	/// Nothing happens if the hash is already served.
+	pub async fn apply_remote(&self, day: Schoolday, hash: String, schedule: SubstitutionSchedule) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		if self.get_hash(day).as_deref() == Some(hash.as_str()) {
+			return Ok(());
 		}
 
-		info!("Removing temp pdf file and accompanying temp directory.");
-		std::fs::remove_file(temp_file_path)?;
-		std::fs::remove_dir(temp_dir_path)?;
+		let json = serde_json::to_string(&schedule)?;
+		let msgpack = rmp_serde::to_vec_named(&schedule)?;
+		let pdf_issue_date = schedule.pdf_issue_date;
+		stateless::forget(day).await;
+		self.store(day, hash.clone(), json, msgpack, schedule);
+		// Only the instance that downloaded the pdf has it.
+		self.store_pdf(day, None);
+		let _ = self.hashes.insert(day, hash);
+		self.announce_change();
+
+		{
+			let mut statuses = self.statuses.write().await;
+			let status = statuses.entry(day).or_default();
+			status.last_change = Some(Utc::now().timestamp_millis());
+			status.pdf_issue_date = Some(pdf_issue_date);
+			status.covered_by = None;
+		}
 
 		Ok(())
 	}
 
+	/// Wakes the requests waiting for a change, once the schedule and the hash of a day are both replaced.
+	fn announce_change(&self) {
+		self.changes.send_modify(|changes| *changes += 1);
+	}
+
+	/// Notifies about every following change of a served schedule, without telling which day changed.
+	pub fn subscribe(&self) -> watch::Receiver<u64> {
+		self.changes.subscribe()
+	}
+
+	/// Replaces the served pdf of `day`, or forgets it if `None`.
+	fn store_pdf(&self, day: Schoolday, pdf: Option<ServedPdf>) {
+		let _ = match pdf {
+			Some(pdf) => self.pdfs.insert(day, pdf),
+			None => self.pdfs.remove(day),
+		};
+	}
+
+	/// Replaces the served json, msgpack and schedule of `day`, `hash` is the one the schedule is stored under.
+	fn store(&self, day: Schoolday, hash: String, json: String, msgpack: Vec<u8>, schedule: SubstitutionSchedule) {
+		info!("Adding new json for {day} to the json map.");
+		if self.jsons.insert(day, VersionedJson { hash, json: Bytes::from(json) }).is_some() {
+			trace!("An old json was replaced");
+		}
+		let _ = self.msgpacks.insert(day, Bytes::from(msgpack));
+
+		METRICS.set_issue_date_mismatch(day, schedule.issue_date_mismatch());
+
+		let schedule = Arc::new(schedule);
+		let old = self.schedules.insert(day, schedule.clone());
+
+		// A reprocessed pdf with the same substitutions isn't a change, the diff keeps showing the last one.
+		if let Some(old) = old.filter(|old| old.entries() != schedule.entries()) {
+			let _ = self.previous.insert(day, old);
+		}
+	}
+
 	/// Gets a json from the internal json store.
-	pub async fn get_json(&self, day: Schoolday) -> Option<String> {
-		let jsons = self.jsons.read().await;
-		jsons.get(&day).map(std::clone::Clone::clone)
+	pub fn get_json(&self, day: Schoolday) -> Option<Bytes> {
+		self.jsons.get(day).map(|versioned| versioned.json)
 	}
-}
 
-/// Inserts the json into the db.
-async fn update_db(hash: &str, pdf_date: &DateTime<Local>, json: serde_json::Value, pool: PgPool) {
-	let insertion_time = Utc::now();
-	let insertion_time = insertion_time.naive_utc();
-	let pdf_date = pdf_date.naive_utc();
-
-	let query_result = sqlx::query!(
-		r#"
-		INSERT INTO substitution_json
-		VALUES($1, $2, $3, $4)
-		"#,
-		hash,
-		pdf_date,
-		insertion_time,
-		json
-	)
-		.execute(&pool)
-		.await;
-
-	if let Err(why) = query_result {
-		error!("{why}");
+	/// Gets the json of `day` together with the hash of the stored schedule it is.
+	pub fn get_versioned_json(&self, day: Schoolday) -> Option<VersionedJson> {
+		self.jsons.get(day)
+	}
+
+	/// Gets the msgpack encoding of the json of `day`.
+	pub fn get_msgpack(&self, day: Schoolday) -> Option<Bytes> {
+		self.msgpacks.get(day)
+	}
+
+	/// Gets the parsed schedule behind the json of `day`.
+	pub fn get_schedule(&self, day: Schoolday) -> Option<Arc<SubstitutionSchedule>> {
+		self.schedules.get(day)
+	}
+
+	/// Gets the pdf the current json of `day` was created from, if this instance downloaded it.
+	pub fn get_pdf(&self, day: Schoolday) -> Option<ServedPdf> {
+		self.pdfs.get(day)
 	}
-}
 
-/// Saves the PDF to disk
-async fn save_pdf_to_disk(day: Schoolday, pdf: &[u8], time: &DateTime<Local>) -> Result<(), Box<dyn std::error::Error>>{
-	let date = time.format("%F");
-	let location = format!("{PDF_STORE_LOCATION}/{date}-{day}");
-	tokio::fs::create_dir(&location).await?;
+	/// Gets the schedule `day` served before its substitutions last changed.
+	pub fn get_previous(&self, day: Schoolday) -> Option<Arc<SubstitutionSchedule>> {
+		self.previous.get(day)
+	}
 
-	let time = Local::now();
-	let time = time.format("%F-%R");
-	let file_location = format!("{location}/{time}");
-	let path = Path::new(&file_location);
+	/// Gets the hash of the last pdf of `day` that was converted.
+	/// It doesn't tell the served json apart while another day's pdf covers `day`, see [`Self::get_versioned_json`] for that.
+	pub fn get_hash(&self, day: Schoolday) -> Option<String> {
+		self.hashes.get(day)
+	}
 
-	if !path.exists() {
-		let mut file = tokio::fs::OpenOptions::new()
-			.write(true)
-			.create(true)
-			.open(path)
-			.await?;
+	/// Gets the freshness information of `day`, `None` if it was never fetched.
+	pub async fn get_status(&self, day: Schoolday) -> Option<DayStatus> {
+		let statuses = self.statuses.read().await;
+		statuses.get(&day).cloned()
+	}
 
-		file.write(pdf).await?;
+	/// Gets the freshness information of every day that was fetched at least once.
+	pub async fn get_statuses(&self) -> HashMap<Schoolday, DayStatus> {
+		let statuses = self.statuses.read().await;
+		statuses.clone()
 	}
 
-	Ok(())
+	/// Remembers `message` as the latest error of `day` and counts it as a failure of `stage`.
+	/// Escalates once the day failed `failure_threshold` times in a row.
+	pub async fn record_error(&self, day: Schoolday, stage: Stage, message: String) {
+		METRICS.fail(stage);
+
+		let consecutive_failures = {
+			let mut statuses = self.statuses.write().await;
+			let status = statuses.entry(day).or_default();
+			status.consecutive_failures += 1;
+			status.failing_since.get_or_insert_with(|| Utc::now().timestamp_millis());
+			status.consecutive_failures
+		};
+		METRICS.set_consecutive_failures(day, consecutive_failures);
+
+		if consecutive_failures == CONFIG.failure_threshold {
+			error!("{day} failed to update {consecutive_failures} times in a row, last error in {stage:?}: {message}");
+			METRICS.escalated();
+		}
+
+		let error = UpdateError {
+			time: Utc::now().timestamp_millis(),
+			stage,
+			message,
+		};
+
+		let mut errors = self.errors.write().await;
+		let _ = errors.insert(day, error);
+	}
+
+	/// Resets the consecutive failures of `day` after a successful update.
+	pub async fn reset_failures(&self, day: Schoolday) {
+		let mut statuses = self.statuses.write().await;
+		let status = statuses.entry(day).or_default();
+
+		if status.consecutive_failures >= CONFIG.failure_threshold {
+			info!("{day} recovered after {} failed updates", status.consecutive_failures);
+		}
+		status.consecutive_failures = 0;
+		status.failing_since = None;
+		METRICS.set_consecutive_failures(day, 0);
+	}
+
+	/// Collects the days that failed longer than `after_millis` without an alert being sent,
+	/// and the days that recovered after an alert was sent. Both get marked as handled.
+	pub async fn take_alerts(&self, after_millis: i64) -> (Vec<(Schoolday, DayStatus)>, Vec<Schoolday>) {
+		let now = Utc::now().timestamp_millis();
+		let mut failing = Vec::new();
+		let mut recovered = Vec::new();
+
+		let mut statuses = self.statuses.write().await;
+		for (day, status) in statuses.iter_mut() {
+			match status.failing_since {
+				Some(since) if !status.alerted && now - since >= after_millis => {
+					status.alerted = true;
+					failing.push((*day, status.clone()));
+				}
+				None if status.alerted => {
+					status.alerted = false;
+					recovered.push(*day);
+				}
+				_ => {}
+			}
+		}
+
+		(failing, recovered)
+	}
+
+	/// Checks if no day failed more than `failure_threshold` times in a row.
+	pub async fn is_healthy(&self) -> bool {
+		let statuses = self.statuses.read().await;
+		statuses
+			.values()
+			.all(|status| status.consecutive_failures < CONFIG.failure_threshold)
+	}
+
+	/// Gets the latest error of every day that had one.
+	pub async fn get_errors(&self) -> HashMap<Schoolday, UpdateError> {
+		let errors = self.errors.read().await;
+		errors.clone()
+	}
+}
+
+/// The hash a day is stored under if the pdf with `hash` covers it next to the day it was downloaded for.
+pub fn section_hash(hash: &str, day: Schoolday) -> String {
+	format!("{hash}-{}", day.to_string().to_lowercase())
+}
+
+/// The hash of the pdf a schedule stored under `hash` was converted from, the inverse of [`section_hash`].
+pub fn source_hash(hash: &str) -> &str {
+	match hash.rsplit_once('-') {
+		Some((source, day)) if day.parse::<Schoolday>().is_ok() => source,
+		_ => hash,
+	}
 }
@@ -0,0 +1,107 @@
+use std::time::Duration;
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+use crate::systemd;
+use crate::scheduler::Scheduler;
+use crate::storage::is_transient;
+
+/// Key of the advisory lock the leading instance holds, "SUBSPDFS" in ascii.
+const LOCK_KEY: i64 = 0x5355_4253_5044_4653;
+/// How often an instance that isn't leading tries to take over.
+const CANDIDATE_INTERVAL: Duration = Duration::from_secs(15);
+/// How often the leader checks that it still holds the lock.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Runs the scheduler while this instance holds the leader lock, so only one of many instances fetches the pdfs.
+/// The lock is a session level advisory lock and is released by Postgres when the connection of a dead leader drops,
+/// another instance takes over on its next attempt.
+pub async fn lead(pool: PgPool, scheduler: Scheduler, mut shutdown: watch::Receiver<bool>) {
+	loop {
+		match try_lead(&pool, &scheduler, &mut shutdown).await {
+			Ok(true) => return,
+			Ok(false) => {}
+			Err(why) => error!("Couldn't lead: {why}"),
+		}
+
+		// Instances waiting for the lock feed the watchdog here, as their scheduler isn't running.
+		let retry_at = Instant::now() + CANDIDATE_INTERVAL;
+		loop {
+			systemd::feed_watchdog();
+			let wake_at = systemd::watchdog_deadline(Instant::now()).map_or(retry_at, |deadline| deadline.min(retry_at));
+
+			tokio::select! {
+				() = tokio::time::sleep_until(wake_at) => {}
+				_ = shutdown.changed() => return,
+			}
+			if Instant::now() >= retry_at {
+				break;
+			}
+		}
+	}
+}
+
+/// Runs the scheduler if the lock could be taken, until `shutdown` changes or the lock is lost.
+/// Returns whether it ran until shutdown.
+async fn try_lead(pool: &PgPool, scheduler: &Scheduler, shutdown: &mut watch::Receiver<bool>) -> Result<bool, sqlx::Error> {
+	let mut connection = match take_lock(pool).await? {
+		Some(connection) => Some(connection),
+		None => return Ok(false),
+	};
+
+	info!("Took the leader lock, this instance fetches the pdfs now");
+	let (stop, stop_receiver) = watch::channel(false);
+	let running = tokio::spawn(scheduler.clone().run(stop_receiver));
+
+	let result = loop {
+		tokio::select! {
+			() = tokio::time::sleep(HEARTBEAT_INTERVAL) => {}
+			_ = shutdown.changed() => break Ok(true),
+		}
+
+		if let Some(held) = &mut connection {
+			if held.ping().await.is_ok() {
+				continue;
+			}
+		}
+
+		// While the database is unreachable no other instance can take over either,
+		// so this one keeps fetching and serving from memory until it can take the lock again.
+		connection = None;
+		match take_lock(pool).await {
+			Ok(Some(reconnected)) => connection = Some(reconnected),
+			Ok(None) => {
+				info!("Another instance took the leader lock");
+				break Ok(false);
+			}
+			Err(why) if is_transient(&why) => warn!("Couldn't reach the database to renew the leader lock: {why}"),
+			Err(why) => break Err(why),
+		}
+	};
+
+	let _ = stop.send(true);
+	if let Err(why) = running.await {
+		error!("The scheduler panicked: {why}");
+	}
+
+	// Closing the session releases the lock for the other instances.
+	if let (Ok(true), Some(connection)) = (&result, connection) {
+		connection.close().await?;
+	}
+
+	result
+}
+
+/// Returns the connection holding the lock, or `None` if another instance holds it.
+async fn take_lock(pool: &PgPool) -> Result<Option<PgConnection>, sqlx::Error> {
+	// The lock belongs to the session, so the connection must not go back to the pool.
+	let mut connection = pool.acquire().await?.detach();
+
+	let locked = sqlx::query_scalar!("SELECT pg_try_advisory_lock($1)", LOCK_KEY)
+		.fetch_one(&mut connection)
+		.await?
+		.unwrap_or(false);
+
+	Ok(locked.then_some(connection))
+}
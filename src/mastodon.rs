@@ -0,0 +1,82 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use reqwest::Client;
+use sqlx::PgPool;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+use crate::config::MastodonConfig;
+use crate::notifier::{current_change, Notifier, ScheduleChange};
+use crate::{Schoolday, templates};
+
+/// Posts a status saying that a day changed and how many classes are affected.
+/// At most one status per day is posted every `min_interval_secs`, further changes within that time
+/// are posted as a single status when it ends.
+pub struct MastodonNotifier {
+	client: Client,
+	config: MastodonConfig,
+	/// When the last status about a day was posted.
+	last_posts: Mutex<HashMap<Schoolday, Instant>>,
+	/// Days with a status waiting for the end of their rate limit.
+	pending: Mutex<HashSet<Schoolday>>,
+	pool: PgPool,
+}
+
+impl MastodonNotifier {
+	pub fn new(config: MastodonConfig, pool: PgPool) -> Self {
+		Self {
+			client: Client::new(),
+			config,
+			last_posts: Mutex::new(HashMap::new()),
+			pending: Mutex::new(HashSet::new()),
+			pool,
+		}
+	}
+}
+
+#[async_trait]
+impl Notifier for MastodonNotifier {
+	fn name(&self) -> &'static str {
+		"mastodon"
+	}
+
+	async fn notify(&self, change: &ScheduleChange) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let day = change.day;
+		let min_interval = Duration::from_secs(self.config.min_interval_secs);
+
+		if !self.pending.lock().await.insert(day) {
+			debug!("{day}: A status is already waiting to be posted");
+			return Ok(());
+		}
+
+		let last_post = self.last_posts.lock().await.get(&day).copied();
+		if let Some(wait) = last_post.and_then(|last_post| min_interval.checked_sub(last_post.elapsed())) {
+			info!("{day}: Waiting {}s before posting to Mastodon again", wait.as_secs());
+			tokio::time::sleep(wait).await;
+		}
+		let _ = self.pending.lock().await.remove(&day);
+
+		// The status describes the state at the time it is posted, including changes made while waiting.
+		let current = match current_change(day, &self.pool).await {
+			Some(current) if current.previous.is_some() => current,
+			_ => return Ok(()),
+		};
+		let changes = current.changes();
+		if changes.is_empty() {
+			return Ok(());
+		}
+
+		let status = templates::render(self.name(), &current, &changes)?;
+
+		self.client
+			.post(format!("{}/api/v1/statuses", self.config.instance.trim_end_matches('/')))
+			.bearer_auth(&self.config.access_token)
+			.form(&[("status", status.as_str()), ("visibility", self.config.visibility.as_str())])
+			.send()
+			.await?
+			.error_for_status()?;
+
+		let _ = self.last_posts.lock().await.insert(day, Instant::now());
+		Ok(())
+	}
+}
@@ -0,0 +1,116 @@
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde_json::json;
+use tracing::debug;
+use crate::classes::ClassSelector;
+use crate::config::MatrixConfig;
+use crate::diff::Change;
+use crate::notifier::{Notifier, ScheduleChange, ScheduleDigest};
+use crate::templates;
+
+/// Posts the changes to the configured Matrix rooms.
+/// Class rooms only get the changes of their class and only if there are any.
+pub struct MatrixNotifier {
+	client: Client,
+	config: MatrixConfig,
+}
+
+impl MatrixNotifier {
+	pub fn new(config: MatrixConfig) -> Self {
+		Self {
+			client: Client::new(),
+			config,
+		}
+	}
+
+	/// Sends a text message to the room.
+	/// The transaction id is derived from the change, so Matrix drops the message if a retry sends it again.
+	async fn send(&self, room: &str, transaction: &str, message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let mut url = Url::parse(&self.config.homeserver)?;
+		// Room ids and aliases contain `!`, `#` and `:`, which the segments are escaped for.
+		url.path_segments_mut()
+			.map_err(|_| format!("Invalid Matrix homeserver {}", self.config.homeserver))?
+			.pop_if_empty()
+			.extend(["_matrix", "client", "v3", "rooms", room, "send", "m.room.message", transaction]);
+
+		self.client
+			.put(url)
+			.bearer_auth(&self.config.access_token)
+			.json(&json!({
+				"msgtype": "m.text",
+				"body": message,
+			}))
+			.send()
+			.await?
+			.error_for_status()?;
+
+		debug!("Posted the changes to {room}");
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Notifier for MatrixNotifier {
+	fn name(&self) -> &'static str {
+		"matrix"
+	}
+
+	async fn notify(&self, change: &ScheduleChange) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let day = change.day;
+		let changes = change.changes();
+		let transaction = |room: &str| format!("{}-{day}-{room}", change.hash.as_deref().unwrap_or_default());
+
+		let message = templates::render(self.name(), change, &changes)?;
+		for room in &self.config.rooms {
+			self.send(room, &transaction(room), &message).await?;
+		}
+
+		for (class, rooms) in &self.config.class_rooms {
+			let selector = ClassSelector::new(class);
+			let class_changes: Vec<Change> = changes.iter()
+				.filter(|change| selector.matches(change.class))
+				.cloned()
+				.collect();
+			if class_changes.is_empty() {
+				continue;
+			}
+
+			let message = templates::render(self.name(), change, &class_changes)?;
+			for room in rooms {
+				self.send(room, &transaction(&format!("{room}-{class}")), &message).await?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn wants_digest(&self) -> bool {
+		true
+	}
+
+	/// The rooms get the whole schedule, class rooms only the substitutions of their class.
+	async fn digest(&self, digest: &ScheduleDigest) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let day = digest.day;
+		let transaction = |room: &str| format!("digest-{}-{day}-{room}", digest.schedule.pdf_issue_date);
+
+		let message = templates::render_digest("matrix_digest", day, &digest.schedule)?;
+		for room in &self.config.rooms {
+			self.send(room, &transaction(room), &message).await?;
+		}
+
+		for (class, rooms) in &self.config.class_rooms {
+			let selector = ClassSelector::new(class);
+			let class_schedule = digest.schedule.filter_classes(|other| selector.matches(other));
+			if class_schedule.entries().is_empty() {
+				continue;
+			}
+
+			let message = templates::render_digest("matrix_digest", day, &class_schedule)?;
+			for room in rooms {
+				self.send(room, &transaction(&format!("{room}-{class}")), &message).await?;
+			}
+		}
+
+		Ok(())
+	}
+}
\ No newline at end of file
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use actix_web::HttpRequest;
+use actix_web::http::StatusCode;
+use serde::Serialize;
+use crate::{conversion, Schoolday};
+
+/// The stages a pdf passes through on its way from the school server into the json store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+	Download = 0,
+	Hash = 1,
+	Tabula = 2,
+	Parse = 3,
+	Serialize = 4,
+	DbInsert = 5,
+}
+
+impl Stage {
+	const ALL: [Stage; 6] = [
+		Stage::Download,
+		Stage::Hash,
+		Stage::Tabula,
+		Stage::Parse,
+		Stage::Serialize,
+		Stage::DbInsert,
+	];
+
+	fn label(self) -> &'static str {
+		match self {
+			Stage::Download => "download",
+			Stage::Hash => "hash",
+			Stage::Tabula => "tabula",
+			Stage::Parse => "parse",
+			Stage::Serialize => "serialize",
+			Stage::DbInsert => "db_insert",
+		}
+	}
+}
+
+/// Timings and failure counts of a single stage.
+#[derive(Default)]
+struct StageMetrics {
+	count: AtomicU64,
+	total_micros: AtomicU64,
+	last_micros: AtomicU64,
+	failures: AtomicU64,
+}
+
+/// Upper bounds of the buckets of the request latency histogram, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+/// Requests to a single route and day.
+#[derive(Default)]
+struct RouteMetrics {
+	/// Responses by the first digit of the status, from 1xx to 5xx.
+	statuses: [u64; 5],
+	/// Requests that took at most the bound of the bucket with the same index, not cumulative.
+	buckets: [u64; LATENCY_BUCKETS.len()],
+	total_micros: u64,
+	count: u64,
+}
+
+/// Counters for the conversion pipeline, rendered in the prometheus text format by the metrics endpoint.
+#[derive(Default)]
+pub struct Metrics {
+	stages: [StageMetrics; 6],
+	updates_succeeded: AtomicU64,
+	updates_unchanged: AtomicU64,
+	updates_failed: AtomicU64,
+	consecutive_failures: [AtomicU64; 5],
+	escalations: AtomicU64,
+	issue_date_mismatches: [AtomicU64; 5],
+	duplicate_inserts: AtomicU64,
+	/// By route pattern and the day in the path, if there is one.
+	routes: Mutex<HashMap<(String, Option<Schoolday>), RouteMetrics>>,
+}
+
+impl Metrics {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Records a successful run of `stage` that took `duration`.
+	#[allow(clippy::cast_possible_truncation)]
+	pub fn observe(&self, stage: Stage, duration: Duration) {
+		let micros = duration.as_micros() as u64;
+		let stage = &self.stages[stage as usize];
+
+		stage.count.fetch_add(1, Ordering::Relaxed);
+		stage.total_micros.fetch_add(micros, Ordering::Relaxed);
+		stage.last_micros.store(micros, Ordering::Relaxed);
+	}
+
+	/// Records a failed run of `stage`.
+	pub fn fail(&self, stage: Stage) {
+		self.stages[stage as usize].failures.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Records that an update stored a new json.
+	pub fn update_succeeded(&self) {
+		self.updates_succeeded.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Records that an update was skipped because the pdf didn't change.
+	pub fn update_unchanged(&self) {
+		self.updates_unchanged.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Records that an update failed in any stage.
+	pub fn update_failed(&self) {
+		self.updates_failed.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Sets how many updates of `day` failed in a row.
+	pub fn set_consecutive_failures(&self, day: Schoolday, failures: u32) {
+		self.consecutive_failures[day as usize].store(u64::from(failures), Ordering::Relaxed);
+	}
+
+	/// Sets whether the served pdf of `day` is dated on another weekday.
+	pub fn set_issue_date_mismatch(&self, day: Schoolday, mismatch: bool) {
+		self.issue_date_mismatches[day as usize].store(u64::from(mismatch), Ordering::Relaxed);
+	}
+
+	/// Records that a schedule wasn't inserted because a row with its hash exists already.
+	pub fn duplicate_insert(&self) {
+		self.duplicate_inserts.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Records that a day crossed the failure threshold.
+	pub fn escalated(&self) {
+		self.escalations.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Records a response to `request`, labelled by the pattern of the route so the number of series stays bounded.
+	#[allow(clippy::cast_possible_truncation)]
+	pub fn observe_request(&self, request: &HttpRequest, status: StatusCode, duration: Duration) {
+		let route = request.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+		let day = request.match_info().get("schoolday").and_then(|day| day.parse().ok());
+		let seconds = duration.as_secs_f64();
+
+		let mut routes = self.routes.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+		let metrics = routes.entry((route, day)).or_default();
+		metrics.statuses[(status.as_u16() / 100).clamp(1, 5) as usize - 1] += 1;
+		if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+			metrics.buckets[bucket] += 1;
+		}
+		metrics.total_micros += duration.as_micros() as u64;
+		metrics.count += 1;
+	}
+
+	/// Renders all metrics in the prometheus text exposition format.
+	#[allow(clippy::cast_precision_loss)]
+	pub fn render(&self) -> String {
+		let mut out = String::new();
+
+		let _ = writeln!(out, "# TYPE substitution_stage_duration_seconds summary");
+		for stage in Stage::ALL {
+			let metrics = &self.stages[stage as usize];
+			let total = metrics.total_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+			let _ = writeln!(out, "substitution_stage_duration_seconds_sum{{stage=\"{}\"}} {total}", stage.label());
+			let _ = writeln!(out, "substitution_stage_duration_seconds_count{{stage=\"{}\"}} {}", stage.label(), metrics.count.load(Ordering::Relaxed));
+		}
+
+		let _ = writeln!(out, "# TYPE substitution_stage_last_duration_seconds gauge");
+		for stage in Stage::ALL {
+			let last = self.stages[stage as usize].last_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+			let _ = writeln!(out, "substitution_stage_last_duration_seconds{{stage=\"{}\"}} {last}", stage.label());
+		}
+
+		let _ = writeln!(out, "# TYPE substitution_stage_failures_total counter");
+		for stage in Stage::ALL {
+			let failures = self.stages[stage as usize].failures.load(Ordering::Relaxed);
+			let _ = writeln!(out, "substitution_stage_failures_total{{stage=\"{}\"}} {failures}", stage.label());
+		}
+
+		let _ = writeln!(out, "# TYPE substitution_updates_total counter");
+		let _ = writeln!(out, "substitution_updates_total{{result=\"success\"}} {}", self.updates_succeeded.load(Ordering::Relaxed));
+		let _ = writeln!(out, "substitution_updates_total{{result=\"unchanged\"}} {}", self.updates_unchanged.load(Ordering::Relaxed));
+		let _ = writeln!(out, "substitution_updates_total{{result=\"failure\"}} {}", self.updates_failed.load(Ordering::Relaxed));
+
+		let _ = writeln!(out, "# TYPE substitution_consecutive_failures gauge");
+		for day in Schoolday::ALL {
+			let failures = self.consecutive_failures[day as usize].load(Ordering::Relaxed);
+			let _ = writeln!(out, "substitution_consecutive_failures{{day=\"{day}\"}} {failures}");
+		}
+
+		let _ = writeln!(out, "# TYPE substitution_failure_escalations_total counter");
+		let _ = writeln!(out, "substitution_failure_escalations_total {}", self.escalations.load(Ordering::Relaxed));
+
+		let _ = writeln!(out, "# TYPE substitution_issue_date_mismatch gauge");
+		for day in Schoolday::ALL {
+			let mismatch = self.issue_date_mismatches[day as usize].load(Ordering::Relaxed);
+			let _ = writeln!(out, "substitution_issue_date_mismatch{{day=\"{day}\"}} {mismatch}");
+		}
+
+		let _ = writeln!(out, "# TYPE substitution_duplicate_inserts_total counter");
+		let _ = writeln!(out, "substitution_duplicate_inserts_total {}", self.duplicate_inserts.load(Ordering::Relaxed));
+
+		let _ = writeln!(out, "# TYPE substitution_conversions_running gauge");
+		let _ = writeln!(out, "substitution_conversions_running {}", conversion::running());
+
+		self.render_routes(&mut out);
+
+		out
+	}
+
+	#[allow(clippy::cast_precision_loss)]
+	fn render_routes(&self, out: &mut String) {
+		let routes = self.routes.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+		let mut keys: Vec<&(String, Option<Schoolday>)> = routes.keys().collect();
+		keys.sort_by_key(|(route, day)| (route.as_str(), day.map(|day| day as usize)));
+
+		let labels = |(route, day): &(String, Option<Schoolday>)| {
+			let day = day.map(|day| day.to_string()).unwrap_or_default();
+			format!("route=\"{}\",day=\"{day}\"", route.replace('\\', "\\\\").replace('"', "\\\""))
+		};
+
+		let _ = writeln!(out, "# TYPE substitution_http_requests_total counter");
+		for key in &keys {
+			let labels = labels(key);
+			for (class, count) in routes[*key].statuses.iter().enumerate() {
+				if *count > 0 {
+					let _ = writeln!(out, "substitution_http_requests_total{{{labels},status=\"{}xx\"}} {count}", class + 1);
+				}
+			}
+		}
+
+		let _ = writeln!(out, "# TYPE substitution_http_request_duration_seconds histogram");
+		for key in &keys {
+			let labels = labels(key);
+			let metrics = &routes[*key];
+			let mut cumulative = 0;
+			for (bound, count) in LATENCY_BUCKETS.iter().zip(metrics.buckets) {
+				cumulative += count;
+				let _ = writeln!(out, "substitution_http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+			}
+			let _ = writeln!(out, "substitution_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}", metrics.count);
+			let _ = writeln!(out, "substitution_http_request_duration_seconds_sum{{{labels}}} {}", metrics.total_micros as f64 / 1_000_000.0);
+			let _ = writeln!(out, "substitution_http_request_duration_seconds_count{{{labels}}} {}", metrics.count);
+		}
+	}
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use tracing::{error, info};
+use crate::config::MqttConfig;
+use crate::notifier::{Notifier, ScheduleChange};
+
+/// Messages that can wait in the client until the event loop sent them.
+const CHANNEL_CAPACITY: usize = 64;
+/// How long to wait before reconnecting after the connection to the broker failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Publishes the schedule of a day to `<prefix>/<day>` and the schedule of every class to `<prefix>/<day>/classes/<class>`.
+/// The messages are retained, so subscribers get the current schedules right away.
+pub struct MqttNotifier {
+	client: AsyncClient,
+	topic_prefix: String,
+}
+
+impl MqttNotifier {
+	/// Connects to the broker and keeps the connection up in the background.
+	pub fn connect(config: MqttConfig) -> Self {
+		let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+		options.set_keep_alive(Duration::from_secs(30));
+		if let (Some(username), Some(password)) = (&config.username, &config.password) {
+			options.set_credentials(username, password);
+		}
+
+		let (client, mut event_loop) = AsyncClient::new(options, CHANNEL_CAPACITY);
+
+		info!("Publishing the schedules to the MQTT broker at {}:{}", config.host, config.port);
+		tokio::spawn(async move {
+			loop {
+				// Polling drives the connection, it reconnects on the next poll after an error.
+				if let Err(why) = event_loop.poll().await {
+					error!("The connection to the MQTT broker failed: {why}");
+					tokio::time::sleep(RECONNECT_DELAY).await;
+				}
+			}
+		});
+
+		Self {
+			client,
+			topic_prefix: config.topic_prefix,
+		}
+	}
+}
+
+#[async_trait]
+impl Notifier for MqttNotifier {
+	fn name(&self) -> &'static str {
+		"mqtt"
+	}
+
+	/// The retained messages have to be current, including the first schedule after a restart.
+	fn wants(&self, _change: &ScheduleChange) -> bool {
+		true
+	}
+
+	async fn notify(&self, change: &ScheduleChange) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let day_topic = format!("{}/{}", self.topic_prefix, change.day);
+		self.client.publish(day_topic.as_str(), QoS::AtLeastOnce, true, serde_json::to_vec(change.schedule.as_ref())?).await?;
+
+		for class in change.schedule.entries().keys() {
+			let class_schedule = change.schedule.filter_classes(|other| other == class);
+			let topic = format!("{day_topic}/classes/{}", topic_level(class));
+			self.client.publish(topic, QoS::AtLeastOnce, true, serde_json::to_vec(&class_schedule)?).await?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Replaces the characters that separate or match topic levels, class names like `BGT 11/1` contain them.
+fn topic_level(class: &str) -> String {
+	class.replace(['/', '+', '#'], "-")
+}
@@ -0,0 +1,331 @@
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::sync::Arc;
+use arc_swap::ArcSwapOption;
+use std::time::Duration;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use reqwest::Client;
+use serde_json::json;
+use sqlx::PgPool;
+use substitution_pdf_to_json::{ClassName, SubstitutionSchedule};
+use tracing::{debug, error, warn};
+use crate::Schoolday;
+use crate::config::Config;
+use crate::diff::{self, Change};
+use crate::events::NatsNotifier;
+use crate::mastodon::MastodonNotifier;
+use crate::matrix::MatrixNotifier;
+use crate::mqtt::MqttNotifier;
+use crate::preferences::UserNotifier;
+use crate::stateless::{served_pdf, served_previous, served_schedule};
+use crate::templates;
+
+/// The delay before the first retry of a failed notification, doubled for every further one.
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+lazy_static! {
+	/// Every notifier enabled in the config, set up by [`init`] and replaced by [`replace`].
+	static ref NOTIFIERS: ArcSwapOption<Registry> = ArcSwapOption::empty();
+}
+
+/// Creates the notifiers, has to be called inside the runtime.
+pub fn init(config: &Config, pool: PgPool) {
+	replace(Registry::from_config(config, pool));
+}
+
+/// Sends the following changes to the notifiers of `registry`.
+/// Changes held back by the previous notifiers are still sent by them.
+pub fn replace(registry: Registry) {
+	NOTIFIERS.store(Some(Arc::new(registry)));
+}
+
+/// Sends the current schedule of `day` to the notifiers, see [`Registry::dispatch`].
+pub async fn dispatch(day: Schoolday) {
+	if let Some(registry) = NOTIFIERS.load_full() {
+		registry.dispatch(day).await;
+	}
+}
+
+/// Sends the digest of `day` to the notifiers, see [`Registry::dispatch_digest`].
+pub async fn dispatch_digest(day: Schoolday) {
+	if let Some(registry) = NOTIFIERS.load_full() {
+		registry.dispatch_digest(day).await;
+	}
+}
+
+/// A changed schedule, passed to every notifier.
+#[derive(Debug, Clone)]
+pub struct ScheduleChange {
+	pub day: Schoolday,
+	/// The hash of the pdf the schedule was converted from.
+	pub hash: Option<String>,
+	pub schedule: Arc<SubstitutionSchedule>,
+	/// The schedule that was served before, `None` for the first schedule of the day since the server started.
+	pub previous: Option<Arc<SubstitutionSchedule>>,
+}
+
+impl ScheduleChange {
+	/// The blocks that changed, empty if there is no previous schedule.
+	pub fn changes(&self) -> Vec<Change<'_>> {
+		match &self.previous {
+			Some(previous) => diff::changes(previous, &self.schedule),
+			None => Vec::new(),
+		}
+	}
+
+	/// The classes with changed blocks.
+	pub fn changed_classes(&self) -> BTreeSet<&ClassName> {
+		match &self.previous {
+			Some(previous) => diff::changes(previous, &self.schedule).into_iter().map(|change| change.class).collect(),
+			None => BTreeSet::new(),
+		}
+	}
+
+	/// The changes as a markdown list, `None` if there is no previous schedule.
+	pub fn summary(&self) -> Option<String> {
+		self.previous.as_ref().map(|_| diff::render_text(self.day, &self.changes()))
+	}
+}
+
+/// The schedule of the next school day, sent as a digest at the times in `schedule.digest_cron`.
+#[derive(Debug, Clone)]
+pub struct ScheduleDigest {
+	pub day: Schoolday,
+	pub schedule: Arc<SubstitutionSchedule>,
+}
+
+/// A backend the changes of the schedules are sent to.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+	/// Name of the backend used in logs.
+	fn name(&self) -> &'static str;
+
+	/// Whether the notifier is interested in `change`.
+	/// By default only changes with a previous schedule and at least one changed block are sent,
+	/// so a restart doesn't send every schedule again.
+	fn wants(&self, change: &ScheduleChange) -> bool {
+		!change.changes().is_empty()
+	}
+
+	/// Sends the change, failed attempts are retried by the [`Registry`].
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if the change couldn't be sent.
+	async fn notify(&self, change: &ScheduleChange) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+	/// Whether the notifier sends digests.
+	fn wants_digest(&self) -> bool {
+		false
+	}
+
+	/// Sends the digest, only called if [`Notifier::wants_digest`] returns `true`.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if the digest couldn't be sent.
+	async fn digest(&self, _digest: &ScheduleDigest) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		Ok(())
+	}
+}
+
+/// The notifiers changes are dispatched to.
+pub struct Registry {
+	notifiers: Vec<Arc<dyn Notifier>>,
+	retries: u32,
+	pool: PgPool,
+}
+
+impl Registry {
+	/// Creates every notifier that is configured.
+	/// Has to be called inside the runtime, as some of them connect in the background.
+	pub fn from_config(config: &Config, pool: PgPool) -> Self {
+		let mut notifiers: Vec<Arc<dyn Notifier>> = Vec::new();
+
+		let client = Client::new();
+		for url in &config.notify.webhooks {
+			notifiers.push(Arc::new(WebhookNotifier::new(client.clone(), url.clone())));
+		}
+		if let Some(matrix) = &config.matrix {
+			notifiers.push(Arc::new(MatrixNotifier::new(matrix.clone())));
+		}
+		if let Some(mastodon) = &config.mastodon {
+			notifiers.push(Arc::new(MastodonNotifier::new(mastodon.clone(), pool.clone())));
+		}
+		if let Some(mqtt) = &config.mqtt {
+			notifiers.push(Arc::new(MqttNotifier::connect(mqtt.clone())));
+		}
+		if let Some(events) = &config.events {
+			notifiers.push(Arc::new(NatsNotifier::connect(events.clone())));
+		}
+		if config.auth.is_some() {
+			notifiers.push(Arc::new(UserNotifier::new(pool.clone())));
+		}
+
+		Self {
+			notifiers,
+			retries: config.notify.retries,
+			pool,
+		}
+	}
+
+	/// Sends the current schedule of `day` to every notifier that wants it, each in its own task.
+	pub async fn dispatch(&self, day: Schoolday) {
+		if self.notifiers.is_empty() {
+			return;
+		}
+
+		let change = match current_change(day, &self.pool).await {
+			Some(change) => Arc::new(change),
+			None => return,
+		};
+
+		for notifier in &self.notifiers {
+			if !notifier.wants(&change) {
+				continue;
+			}
+
+			let notifier = notifier.clone();
+			let change = change.clone();
+			let retries = self.retries;
+			tokio::spawn(async move {
+				with_retry(notifier.name(), change.day, retries, || notifier.notify(&change)).await;
+			});
+		}
+	}
+
+	/// Sends the current schedule of `day` as a digest to every notifier that sends digests.
+	pub async fn dispatch_digest(&self, day: Schoolday) {
+		let schedule = match served_schedule(day, &self.pool).await {
+			Ok(Some(schedule)) => schedule,
+			Ok(None) => {
+				warn!("{day}: There is no schedule to send a digest of");
+				return;
+			}
+			Err(why) => {
+				error!("{day}: Couldn't read the schedule to send a digest of: {why}");
+				return;
+			}
+		};
+		let digest = Arc::new(ScheduleDigest {
+			day,
+			schedule,
+		});
+
+		for notifier in self.notifiers.iter().filter(|notifier| notifier.wants_digest()) {
+			let notifier = notifier.clone();
+			let digest = digest.clone();
+			let retries = self.retries;
+			tokio::spawn(async move {
+				with_retry(notifier.name(), digest.day, retries, || notifier.digest(&digest)).await;
+			});
+		}
+	}
+}
+
+/// The current schedule of `day` and the one served before it, `None` if there is none or it couldn't be read.
+pub async fn current_change(day: Schoolday, pool: &PgPool) -> Option<ScheduleChange> {
+	let read = async {
+		let schedule = match served_schedule(day, pool).await? {
+			Some(schedule) => schedule,
+			None => return Ok(None),
+		};
+
+		Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Some(ScheduleChange {
+			day,
+			hash: served_pdf(day, pool).await?.map(|pdf| pdf.hash),
+			schedule,
+			previous: served_previous(day, pool).await?,
+		}))
+	};
+
+	match read.await {
+		Ok(change) => change,
+		Err(why) => {
+			error!("{day}: Couldn't read the changed schedule: {why}");
+			None
+		}
+	}
+}
+
+/// Runs `send`, retrying up to `retries` times with exponential backoff.
+async fn with_retry<F, Fut>(name: &str, day: Schoolday, retries: u32, mut send: F)
+	where
+		F: FnMut() -> Fut,
+		Fut: Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>>,
+{
+	for attempt in 0..=retries {
+		match send().await {
+			Ok(()) => {
+				debug!("{day}: Notified {name}");
+				return;
+			}
+			Err(why) if attempt < retries => {
+				let delay = BASE_RETRY_DELAY * 2_u32.pow(attempt);
+				warn!("{day}: Notifying {name} failed, retrying in {}s: {why}", delay.as_secs());
+				tokio::time::sleep(delay).await;
+			}
+			Err(why) => error!("{day}: Notifying {name} failed {} times, giving up: {why}", retries + 1),
+		}
+	}
+}
+
+/// POSTs the changes to a webhook, with the `text` and `content` fields Slack and Discord show.
+/// Every url has a notifier of its own, so a failing one is retried without posting to the others again.
+pub struct WebhookNotifier {
+	client: Client,
+	url: String,
+}
+
+impl WebhookNotifier {
+	pub fn new(client: Client, url: String) -> Self {
+		Self {
+			client,
+			url,
+		}
+	}
+
+	async fn post(&self, payload: &serde_json::Value) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		self.client
+			.post(&self.url)
+			.json(payload)
+			.send()
+			.await?
+			.error_for_status()?;
+
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+	fn name(&self) -> &'static str {
+		"webhook"
+	}
+
+	async fn notify(&self, change: &ScheduleChange) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let message = templates::render(self.name(), change, &change.changes())?;
+		self.post(&json!({
+			"text": message,
+			"content": message,
+			"day": change.day,
+			"hash": change.hash,
+			"changed_classes": change.changed_classes(),
+		})).await
+	}
+
+	fn wants_digest(&self) -> bool {
+		true
+	}
+
+	async fn digest(&self, digest: &ScheduleDigest) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let message = templates::render_digest("webhook_digest", digest.day, &digest.schedule)?;
+		self.post(&json!({
+			"text": message,
+			"content": message,
+			"day": digest.day,
+			"digest": true,
+		})).await
+	}
+}
@@ -0,0 +1,207 @@
+use std::time::Duration;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::sync::OnceCell;
+use crate::auth::Role;
+use crate::config::OidcConfig;
+
+/// How long a login may take between the redirect to the provider and the callback.
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+/// The cookie binding a login to the browser that started it, see [`sign_state`].
+pub const STATE_COOKIE: &str = "oidc_state";
+
+lazy_static! {
+	static ref CLIENT: Client = Client::new();
+}
+
+/// The endpoints of the provider, see `/.well-known/openid-configuration`.
+#[derive(Debug, Deserialize)]
+struct Discovery {
+	authorization_endpoint: String,
+	token_endpoint: String,
+	userinfo_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+	access_token: String,
+}
+
+/// The user the provider authenticated.
+#[derive(Debug)]
+pub struct OidcUser {
+	pub subject: String,
+	pub username: String,
+	pub role: Role,
+}
+
+static DISCOVERY: OnceCell<Discovery> = OnceCell::const_new();
+
+async fn discovery(config: &OidcConfig) -> Result<&'static Discovery, reqwest::Error> {
+	DISCOVERY.get_or_try_init(|| async {
+		let url = format!("{}/.well-known/openid-configuration", config.issuer.trim_end_matches('/'));
+		CLIENT.get(url).send().await?.error_for_status()?.json().await
+	}).await
+}
+
+/// A login that was started at the provider.
+pub struct Login {
+	/// Where the user is redirected to.
+	pub url: Url,
+	/// The value of [`STATE_COOKIE`], which the callback has to come with.
+	pub cookie: String,
+}
+
+/// The url the user is redirected to for logging in at the provider, and the cookie the callback is checked against.
+/// The `state` is only kept in the cookie, so the callback can reach any instance.
+///
+/// # Errors
+///
+/// Returns `Err` if the configuration of the provider couldn't be fetched.
+pub async fn start_login(config: &OidcConfig, secret: &str) -> Result<Login, Box<dyn std::error::Error>> {
+	let discovery = discovery(config).await?;
+
+	let state: String = rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect();
+	#[allow(clippy::cast_possible_wrap)]
+		let expiry = Utc::now().timestamp() + LOGIN_TIMEOUT.as_secs() as i64;
+
+	let url = Url::parse_with_params(&discovery.authorization_endpoint, [
+		("response_type", "code"),
+		("client_id", config.client_id.as_str()),
+		("redirect_uri", config.redirect_uri.as_str()),
+		("scope", config.scopes.as_str()),
+		("state", state.as_str()),
+	])?;
+
+	Ok(Login {
+		url,
+		cookie: sign_state(secret, &state, expiry),
+	})
+}
+
+/// `<state>.<expiry>.<mac>`, the expiry in seconds since the unix epoch and the mac an HMAC over both.
+fn sign_state(secret: &str, state: &str, expiry: i64) -> String {
+	let payload = format!("{state}.{expiry}");
+	let mac = hex::encode(state_mac(secret, &payload).finalize().into_bytes());
+	format!("{payload}.{mac}")
+}
+
+fn state_mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+	// Keeps the mac from being mistaken for any other one made with the secret.
+	mac.update(b"oidc-state:");
+	mac.update(payload.as_bytes());
+	mac
+}
+
+/// Checks that `cookie` was signed for `state` and hasn't expired.
+fn verify_state(secret: &str, cookie: &str, state: &str) -> Result<(), Box<dyn std::error::Error>> {
+	let (payload, mac) = cookie.rsplit_once('.').ok_or("Malformed login cookie")?;
+	state_mac(secret, payload)
+		.verify_slice(&hex::decode(mac)?)
+		.map_err(|_| "The login cookie wasn't issued by this server")?;
+
+	let (signed_state, expiry) = payload.split_once('.').ok_or("Malformed login cookie")?;
+	if Utc::now().timestamp() >= expiry.parse::<i64>()? {
+		return Err("Expired login".into());
+	}
+	if signed_state != state {
+		return Err("The login was started in another browser".into());
+	}
+
+	Ok(())
+}
+
+/// Exchanges the code of the callback for the user it belongs to.
+/// `cookie` is the value of [`STATE_COOKIE`] the callback came with.
+///
+/// # Errors
+///
+/// Returns `Err` if the `state` doesn't match the cookie, the login expired or the provider rejected the code.
+pub async fn finish_login(config: &OidcConfig, secret: &str, code: &str, state: &str, cookie: Option<&str>) -> Result<OidcUser, Box<dyn std::error::Error>> {
+	verify_state(secret, cookie.ok_or("The login cookie is missing")?, state)?;
+
+	let discovery = discovery(config).await?;
+	let token: TokenResponse = CLIENT.post(&discovery.token_endpoint)
+		.basic_auth(&config.client_id, Some(&config.client_secret))
+		.form(&[
+			("grant_type", "authorization_code"),
+			("code", code),
+			("redirect_uri", config.redirect_uri.as_str()),
+		])
+		.send()
+		.await?
+		.error_for_status()?
+		.json()
+		.await?;
+
+	// The userinfo comes straight from the provider, so unlike the id token it doesn't have to be verified.
+	let userinfo: Value = CLIENT.get(&discovery.userinfo_endpoint)
+		.bearer_auth(&token.access_token)
+		.send()
+		.await?
+		.error_for_status()?
+		.json()
+		.await?;
+
+	let subject = userinfo["sub"].as_str().ok_or("The userinfo has no subject")?.to_string();
+	let username = userinfo["preferred_username"].as_str().unwrap_or(&subject).to_string();
+	let groups: Vec<&str> = userinfo[config.groups_claim.as_str()]
+		.as_array()
+		.map(|groups| groups.iter().filter_map(Value::as_str).collect())
+		.unwrap_or_default();
+
+	Ok(OidcUser {
+		subject,
+		username,
+		role: role_for(config, &groups),
+	})
+}
+
+/// The role with the most rights any of the groups is mapped to, users without such a group are students.
+fn role_for(config: &OidcConfig, groups: &[&str]) -> Role {
+	groups.iter()
+		.filter_map(|group| config.role_groups.get(*group))
+		.copied()
+		.max()
+		.unwrap_or(Role::Student)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SECRET: &str = "secret";
+
+	fn in_a_minute() -> i64 {
+		Utc::now().timestamp() + 60
+	}
+
+	#[test]
+	fn the_signed_state_is_accepted() {
+		assert!(verify_state(SECRET, &sign_state(SECRET, "abc", in_a_minute()), "abc").is_ok());
+	}
+
+	#[test]
+	fn states_of_other_logins_are_rejected() {
+		let cookie = sign_state(SECRET, "abc", in_a_minute());
+
+		assert!(verify_state(SECRET, &cookie, "abd").is_err());
+		assert!(verify_state("other secret", &cookie, "abc").is_err());
+		assert!(verify_state(SECRET, &cookie.replacen("abc", "abd", 1), "abd").is_err());
+	}
+
+	#[test]
+	fn expired_states_are_rejected() {
+		let cookie = sign_state(SECRET, "abc", Utc::now().timestamp() - 1);
+
+		assert!(verify_state(SECRET, &cookie, "abc").is_err());
+	}
+}
@@ -0,0 +1,115 @@
+use actix_web::{get, HttpResponse, Responder, web};
+use actix_web::http::header;
+use serde::Deserialize;
+use sqlx::PgPool;
+use substitution_pdf_to_json::SubstitutionSchedule;
+use tracing::error;
+use crate::{Schoolday, storage};
+use crate::json_handler::VersionedJson;
+use crate::stateless::served_versioned_json;
+use crate::storage::Pools;
+
+#[derive(Debug, Deserialize)]
+pub struct PatchQuery {
+	/// The hash of the schedule the client has.
+	from: String,
+}
+
+/// Answers with a JSON Patch (RFC 6902) turning the schedule of the day with the hash `from` into the served one.
+/// The hash the served schedule is stored under is sent as ETag, to be passed as `from` the next time.
+/// Answers with 404 if there is no schedule stored under `from`, the client has to load the whole day then.
+#[get("/{schoolday}/patch")]
+pub async fn get_patch(day: web::Path<Schoolday>, query: web::Query<PatchQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let day = day.into_inner();
+	let from = query.into_inner().from.to_ascii_lowercase();
+
+	let VersionedJson { hash, json } = match served_versioned_json(day, &pools.read).await {
+		Ok(Some(versioned)) => versioned,
+		Ok(None) => {
+			return HttpResponse::NoContent()
+				.append_header(("Retry-After", "120"))
+				.finish();
+		}
+		Err(why) => {
+			error!("{why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	let current: serde_json::Value = match serde_json::from_slice(&json) {
+		Ok(current) => current,
+		Err(why) => {
+			error!("{day}: The served json isn't valid: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	let patch = if from == hash {
+		json_patch::Patch(Vec::new())
+	} else {
+		match served_version(day, &from, &pools.read).await {
+			Ok(Some(previous)) => json_patch::diff(&previous, &current),
+			Ok(None) => return HttpResponse::NotFound().body(format!("There is no schedule for {from}")),
+			Err(why) => {
+				error!("{day}: Couldn't load the schedule of {from}: {why}");
+				return HttpResponse::InternalServerError().finish();
+			}
+		}
+	};
+
+	HttpResponse::Ok()
+		.content_type("application/json-patch+json")
+		.insert_header((header::ETAG, format!("\"{hash}\"")))
+		.json(patch)
+}
+
+/// The stored schedule of `hash` the way it was served for `day`.
+/// Rows stored before effective dates existed don't have one, it is added like when they are served.
+async fn served_version(day: Schoolday, hash: &str, pool: &PgPool) -> Result<Option<serde_json::Value>, Box<dyn std::error::Error>> {
+	let json = match storage::load_json(pool, hash).await? {
+		Some(json) => json,
+		None => return Ok(None),
+	};
+
+	let mut schedule: SubstitutionSchedule = serde_json::from_value(json)?;
+	if schedule.effective_date().is_none() {
+		schedule.assign_day(day);
+	}
+
+	Ok(Some(serde_json::to_value(schedule)?))
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::{json, Value};
+
+	fn schedule(entries: Value) -> Value {
+		json!({
+			"pdf_issue_date": 1_652_054_400_000_i64,
+			"struct_time": 0,
+			"effective_date": "2022-05-09",
+			"entries": entries,
+		})
+	}
+
+	#[test]
+	fn the_patch_turns_the_cached_schedule_into_the_served_one() {
+		let cached = schedule(json!({"10A": {"1": "Mathe", "3": "Raum 104"}, "5A": {"0": "Sport entfällt"}}));
+		let served = schedule(json!({"10A": {"1": "Mathe", "3": "Raum 204"}, "9B": {"2": "Englisch"}}));
+
+		// The way a client receives the patch.
+		let sent = serde_json::to_string(&json_patch::diff(&cached, &served)).unwrap();
+		let patch: json_patch::Patch = serde_json::from_str(&sent).unwrap();
+
+		let mut patched = cached;
+		json_patch::patch(&mut patched, &patch).unwrap();
+		assert_eq!(patched, served);
+	}
+
+	#[test]
+	fn the_patch_of_an_unchanged_schedule_is_empty() {
+		let served = schedule(json!({"10A": {"3": "Raum 204"}}));
+
+		assert_eq!(serde_json::to_value(json_patch::diff(&served, &served)).unwrap(), json!([]));
+	}
+}
@@ -0,0 +1,53 @@
+use actix_web::{get, HttpRequest, HttpResponse, Responder, web};
+use actix_web::http::header;
+use chrono::{TimeZone, Utc};
+use tracing::error;
+use crate::{JSON_HANDLER, Schoolday};
+use crate::stateless::served_pdf;
+use crate::storage::Pools;
+
+/// Serves the pdf the schedule of a day was converted from, so clients don't have to download it from the school server.
+/// The ETag is the hash of the pdf, clients revalidate with `If-None-Match` on every request.
+#[get("/{schoolday}/pdf")]
+pub async fn get_pdf(req: HttpRequest, day: web::Path<Schoolday>, pools: web::Data<Pools>) -> impl Responder {
+	let day = day.into_inner();
+	let pdf = match served_pdf(day, &pools.read).await {
+		Ok(Some(pdf)) => pdf,
+		Ok(None) => return HttpResponse::NotFound().body(format!("There is no pdf of {day} yet")),
+		Err(why) => {
+			error!("Couldn't read the pdf of {day} from the database: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	let etag = format!("\"{}\"", pdf.hash);
+	let matches = req.headers()
+		.get(header::IF_NONE_MATCH)
+		.and_then(|value| value.to_str().ok())
+		.is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+
+	let mut response = if matches {
+		HttpResponse::NotModified()
+	} else {
+		HttpResponse::Ok()
+	};
+	response
+		.insert_header((header::ETAG, etag))
+		.insert_header((header::CACHE_CONTROL, "no-cache"));
+
+	let last_change = JSON_HANDLER.get_status(day).await
+		.and_then(|status| status.last_change)
+		.and_then(|millis| Utc.timestamp_millis_opt(millis).single());
+	if let Some(last_change) = last_change {
+		response.insert_header((header::LAST_MODIFIED, last_change.format("%a, %d %b %Y %H:%M:%S GMT").to_string()));
+	}
+
+	if matches {
+		return response.finish();
+	}
+
+	response
+		.content_type("application/pdf")
+		.insert_header((header::CONTENT_DISPOSITION, format!("inline; filename=\"{day}.pdf\"")))
+		.body(pdf.bytes)
+}
@@ -0,0 +1,163 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::Client;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue};
+use crate::{Schoolday, SOURCE_URLS};
+use crate::config::SourceConfig;
+use crate::fetcher::PdfFetcher;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug)]
+pub struct SubstitutionPDFGetter {
+	urls: [String; 5],
+	headers: HeaderMap,
+	timeout: Option<Duration>,
+	client: Client,
+}
+
+impl SubstitutionPDFGetter {
+	/// Starts configuring a getter, every option that isn't set defaults to what the school server needs.
+	#[must_use]
+	pub fn builder() -> SubstitutionPDFGetterBuilder {
+		SubstitutionPDFGetterBuilder::default()
+	}
+
+	/// Builds a getter from the `[source]` section of the config.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if a configured header is invalid or the client couldn't be built.
+	pub fn from_config(config: &SourceConfig) -> Result<Self, Box<dyn std::error::Error>> {
+		let urls: [String; 5] = config.urls
+			.clone()
+			.try_into()
+			.map_err(|_| "Expected one source url per school day")?;
+
+		let mut builder = Self::builder()
+			.urls(urls)
+			.timeout(Duration::from_secs(config.timeout_secs))
+			.connect_timeout(Duration::from_secs(config.connect_timeout_secs));
+
+		for (name, value) in &config.headers {
+			builder = builder.header(HeaderName::try_from(name.as_str())?, HeaderValue::try_from(value.as_str())?);
+		}
+
+		Ok(builder.build()?)
+	}
+
+	/// Returns result with an Err or a Vector with the binary data of the request-response
+	/// Does not check if the response is valid, this is the responsibility of the caller.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if there was a problem while fetching the PDF for the requested day.
+	pub async fn get_weekday_pdf(&self, day: Schoolday) -> Result<Bytes, reqwest::Error> {
+		let url = &self.urls[day as usize];
+		let mut request = self.client
+			.get(url)
+			.headers(self.headers.clone());
+
+		if let Some(timeout) = self.timeout {
+			request = request.timeout(timeout);
+		}
+
+		let response = self.client.execute(request.build()?).await?;
+		response.bytes().await
+	}
+}
+
+#[async_trait]
+impl PdfFetcher for SubstitutionPDFGetter {
+	async fn fetch(&self, day: Schoolday) -> Result<Bytes, Box<dyn std::error::Error + Send + Sync>> {
+		Ok(self.get_weekday_pdf(day).await?)
+	}
+}
+
+/// Configures a [`SubstitutionPDFGetter`].
+#[derive(Debug)]
+pub struct SubstitutionPDFGetterBuilder {
+	urls: [String; 5],
+	headers: HeaderMap,
+	timeout: Duration,
+	connect_timeout: Duration,
+	client: Option<Client>,
+}
+
+impl SubstitutionPDFGetterBuilder {
+	/// Sets the urls of the pdfs, indexed by [`Schoolday`].
+	#[must_use]
+	pub fn urls(mut self, urls: [String; 5]) -> Self {
+		self.urls = urls;
+		self
+	}
+
+	/// Adds a header that is sent with every request, replacing a previous value of the same header.
+	#[must_use]
+	pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+		self.headers.insert(name, value);
+		self
+	}
+
+	/// Sets how long a whole request may take.
+	#[must_use]
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Sets how long connecting may take. Ignored if a client is injected, as it is part of the client.
+	#[must_use]
+	pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+		self.connect_timeout = connect_timeout;
+		self
+	}
+
+	/// Uses `client` instead of building a new one, so connections can be shared with the rest of the application.
+	#[must_use]
+	#[allow(dead_code)]
+	pub fn client(mut self, client: Client) -> Self {
+		self.client = Some(client);
+		self
+	}
+
+	/// # Errors
+	///
+	/// Returns `Err` if no client was injected and building one failed.
+	pub fn build(self) -> Result<SubstitutionPDFGetter, reqwest::Error> {
+		// An injected client comes with its own timeouts, so the request timeout is set on every request instead.
+		let (client, timeout) = match self.client {
+			Some(client) => (client, Some(self.timeout)),
+			None => {
+				let client = Client::builder()
+					.connect_timeout(self.connect_timeout)
+					.timeout(self.timeout)
+					.build()?;
+				(client, None)
+			}
+		};
+
+		Ok(SubstitutionPDFGetter {
+			urls: self.urls,
+			headers: self.headers,
+			timeout,
+			client,
+		})
+	}
+}
+
+impl Default for SubstitutionPDFGetterBuilder {
+	fn default() -> Self {
+		let mut headers = HeaderMap::new();
+		headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic aGJzdXNlcjpoYnNwYXNz"));
+
+		Self {
+			urls: SOURCE_URLS.map(String::from),
+			headers,
+			timeout: DEFAULT_TIMEOUT,
+			connect_timeout: DEFAULT_TIMEOUT,
+			client: None,
+		}
+	}
+}
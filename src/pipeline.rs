@@ -0,0 +1,568 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::future::Future;
+use std::sync::{Arc, PoisonError};
+use std::time::Instant;
+use bytes::Bytes;
+use chrono::{DateTime, Local, TimeZone};
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use substitution_pdf_to_json::{PDFJsonError, SubstitutionSchedule};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::{debug, error, info, trace, warn};
+use crate::{CONFIG, JSON_HANDLER, METRICS, Schoolday};
+use crate::util::TEMP_DIR;
+use crate::{blob_store, conversion, hashing, jobs, quarantine, storage};
+use crate::classes::NORMALIZER;
+use crate::fetcher::PdfFetcher;
+use crate::json_handler::{section_hash, ServedPdf};
+use crate::metrics::Stage;
+use crate::storage::StorageError;
+use crate::write_queue::{PendingWrite, WRITE_QUEUE};
+
+/// How many pdfs wait in front of a stage before the one before it waits as well.
+const CHANNEL_CAPACITY: usize = 4;
+/// Downloads running at once, enough for the two days that are fetched.
+const DOWNLOAD_WORKERS: usize = 2;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+lazy_static! {
+	static ref PIPELINE: Pipeline = Pipeline::start();
+	/// The pdfs that are between the hash and the publish stage, with the day they were fetched for.
+	static ref IN_FLIGHT: std::sync::Mutex<HashSet<(Schoolday, String)>> = std::sync::Mutex::new(HashSet::new());
+}
+
+/// Marks the pdf with `hash` as converted for `day` until it is dropped after its last stage,
+/// so fetches of the same pdf that overlap don't convert and store it twice.
+struct InFlight {
+	day: Schoolday,
+	hash: String,
+}
+
+impl InFlight {
+	/// `None` if the pdf is converted for `day` already.
+	fn start(day: Schoolday, hash: &str) -> Option<Self> {
+		let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(PoisonError::into_inner);
+		in_flight.insert((day, hash.to_string())).then(|| Self {
+			day,
+			hash: hash.to_string(),
+		})
+	}
+}
+
+impl Drop for InFlight {
+	fn drop(&mut self) {
+		let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(PoisonError::into_inner);
+		in_flight.remove(&(self.day, std::mem::take(&mut self.hash)));
+	}
+}
+
+/// Tells whoever submitted a pdf how its update ended.
+type Done = oneshot::Sender<Result<(), BoxError>>;
+
+struct Download {
+	day: Schoolday,
+	fetcher: Arc<dyn PdfFetcher>,
+	pool: PgPool,
+	done: Done,
+}
+
+struct Hash {
+	day: Schoolday,
+	pdf: Bytes,
+	pool: PgPool,
+	done: Done,
+}
+
+struct Convert {
+	day: Schoolday,
+	hash: String,
+	pdf: Bytes,
+	pool: PgPool,
+	/// Whether the schedule replaces the served one, or only goes into the database.
+	publish: bool,
+	in_flight: InFlight,
+	done: Done,
+}
+
+/// A schedule converted from the pdf, serialized the way it is served.
+struct Converted {
+	day: Schoolday,
+	schedule: SubstitutionSchedule,
+	json: String,
+	msgpack: Vec<u8>,
+}
+
+struct Persist {
+	hash: String,
+	pdf: Bytes,
+	pool: PgPool,
+	publish: bool,
+	own: Converted,
+	/// The other school days the pdf covers as well.
+	covered: Vec<Converted>,
+	in_flight: InFlight,
+	done: Done,
+}
+
+/// The publish stage gets the schedules as they were persisted.
+type Publish = Persist;
+
+/// The entrances of the stages pdfs can be submitted to, the others are only fed by the stage before them.
+struct Pipeline {
+	downloads: mpsc::Sender<Download>,
+	conversions: mpsc::Sender<Convert>,
+}
+
+impl Pipeline {
+	/// Spawns the workers of every stage: download → hash → convert → persist → publish.
+	/// Every stage hands its result to the next one through a bounded channel, so a slow stage holds up the ones before it.
+	fn start() -> Self {
+		let (downloads, download_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+		let (hashes, hash_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+		let (conversions, conversion_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+		let (persists, persist_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+		let (publishes, publish_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+
+		let next = hashes;
+		spawn_workers(DOWNLOAD_WORKERS, download_receiver, move |item| download(item, next.clone()));
+		let next = conversions.clone();
+		spawn_workers(1, hash_receiver, move |item| hash(item, next.clone()));
+		let next = persists;
+		spawn_workers(CONFIG.max_conversions.max(1), conversion_receiver, move |item| convert(item, next.clone()));
+		let next = publishes;
+		spawn_workers(1, persist_receiver, move |item| persist(item, next.clone()));
+		// A single worker, so the schedules of a day are served in the order they were converted.
+		spawn_workers(1, publish_receiver, publish);
+
+		Self {
+			downloads,
+			conversions,
+		}
+	}
+}
+
+/// Runs `workers` tasks taking items from `receiver` until every sender is gone.
+fn spawn_workers<T, F, Fut>(workers: usize, receiver: mpsc::Receiver<T>, work: F)
+where
+	T: Send + 'static,
+	F: Fn(T) -> Fut + Clone + Send + 'static,
+	Fut: Future<Output = ()> + Send,
+{
+	let receiver = Arc::new(Mutex::new(receiver));
+
+	for _ in 0..workers {
+		let receiver = receiver.clone();
+		let work = work.clone();
+		tokio::spawn(async move {
+			loop {
+				let item = receiver.lock().await.recv().await;
+				match item {
+					Some(item) => work(item).await,
+					None => break,
+				}
+			}
+		});
+	}
+}
+
+/// Downloads the pdf of `day`, converts it and serves the schedule, returning once it passed every stage.
+/// Waits while the pipeline is busy.
+///
+/// # Errors
+///
+/// Returns `Err` with the error of the stage the update failed in.
+pub async fn update(day: Schoolday, fetcher: Arc<dyn PdfFetcher>, pool: PgPool) -> Result<(), BoxError> {
+	debug!("Getting pdf for {day}");
+	let (done, result) = oneshot::channel();
+	PIPELINE.downloads.send(Download {
+		day,
+		fetcher,
+		pool,
+		done,
+	}).await.map_err(|_| "The pipeline stopped")?;
+
+	result.await.map_err(|_| "The pipeline dropped the update")?
+}
+
+/// Converts the pdf of a persisted conversion job again.
+/// The result only replaces the served json if the day doesn't serve anything yet,
+/// otherwise it only goes into the database.
+///
+/// # Errors
+///
+/// Returns `Err` with the error of the stage the conversion failed in.
+pub async fn retry(day: Schoolday, hash: String, pdf: Bytes, pool: PgPool) -> Result<(), BoxError> {
+	let in_flight = match InFlight::start(day, &hash) {
+		Some(in_flight) => in_flight,
+		None => {
+			debug!("{day}: The pdf {hash} is converted already");
+			return Ok(());
+		}
+	};
+
+	let (done, result) = oneshot::channel();
+	PIPELINE.conversions.send(Convert {
+		day,
+		hash,
+		pdf,
+		pool,
+		publish: JSON_HANDLER.get_hash(day).is_none(),
+		in_flight,
+		done,
+	}).await.map_err(|_| "The pipeline stopped")?;
+
+	result.await.map_err(|_| "The pipeline dropped the update")?
+}
+
+/// Ends the update of a pdf that failed in one of the stages.
+fn fail(done: Done, why: BoxError) {
+	METRICS.update_failed();
+	let _ = done.send(Err(why));
+}
+
+/// Hands `item` to the next stage, waiting while it is busy.
+async fn forward<T>(next: &mpsc::Sender<T>, item: T) {
+	if next.send(item).await.is_err() {
+		error!("A stage of the pipeline stopped, dropping the update");
+	}
+}
+
+async fn download(item: Download, next: mpsc::Sender<Hash>) {
+	let Download { day, fetcher, pool, done } = item;
+
+	let download_start = Instant::now();
+	match fetcher.fetch(day).await {
+		Ok(pdf) => {
+			METRICS.observe(Stage::Download, download_start.elapsed());
+			forward(&next, Hash {
+				day,
+				pdf,
+				pool,
+				done,
+			}).await;
+		}
+		Err(why) => {
+			JSON_HANDLER.record_error(day, Stage::Download, why.to_string()).await;
+			fail(done, why);
+		}
+	}
+}
+
+/// Skips pdfs that didn't change since the served schedule was converted.
+async fn hash(item: Hash, next: mpsc::Sender<Convert>) {
+	let Hash { day, pdf, pool, done } = item;
+
+	let hash_start = Instant::now();
+	let hash = hashing::hash(&pdf);
+	METRICS.observe(Stage::Hash, hash_start.elapsed());
+	JSON_HANDLER.fetched(day).await;
+
+	if JSON_HANDLER.get_hash(day).is_some_and(|old_hash| old_hash == hash) {
+		debug!("{day}: New hash matched old hash");
+		METRICS.update_unchanged();
+		JSON_HANDLER.reset_failures(day).await;
+		let _ = done.send(Ok(()));
+		return;
+	}
+
+	let in_flight = match InFlight::start(day, &hash) {
+		Some(in_flight) => in_flight,
+		None => {
+			debug!("{day}: The pdf is converted already");
+			let _ = done.send(Ok(()));
+			return;
+		}
+	};
+
+	// Persist the job first, so the update isn't lost if the server stops before it reaches the database.
+	if let Err(why) = jobs::enqueue(&pool, day, &hash, &pdf).await {
+		error!("Couldn't persist the conversion job of {day}: {why}");
+	}
+
+	forward(&next, Convert {
+		day,
+		hash,
+		pdf,
+		pool,
+		publish: true,
+		in_flight,
+		done,
+	}).await;
+}
+
+/// Converts the pdf to the schedules of the days it covers, failing the conversion job if it can't.
+async fn convert(item: Convert, next: mpsc::Sender<Persist>) {
+	let Convert { day, hash, pdf, pool, publish, in_flight, done } = item;
+
+	match convert_pdf(day, &hash, &pdf).await {
+		Ok((own, covered)) => forward(&next, Persist {
+			hash,
+			pdf,
+			pool,
+			publish,
+			own,
+			covered,
+			in_flight,
+			done,
+		}).await,
+		Err(why) => {
+			if let Err(why) = jobs::fail(&pool, &hash, &why.to_string()).await {
+				error!("Couldn't update the conversion job of {day}: {why}");
+			}
+			fail(done, why);
+		}
+	}
+}
+
+#[allow(clippy::similar_names)]
+async fn convert_pdf(day: Schoolday, hash: &str, pdf: &Bytes) -> Result<(Converted, Vec<Converted>), BoxError> {
+	// The temp dir is removed when the guard drops, including on every early return below.
+	let temp_dir = match tempfile::Builder::new().tempdir_in(&*TEMP_DIR) {
+		Ok(temp_dir) => temp_dir,
+		Err(why) => {
+			JSON_HANDLER.record_error(day, Stage::Tabula, why.to_string()).await;
+			return Err(Box::new(why));
+		}
+	};
+	trace!("Staging pdf in {}", temp_dir.path().display());
+
+	debug!("Creating json with tabula...");
+	let (schedules, timings) = match conversion::convert_pdf(pdf, temp_dir.path()).await {
+		Ok(converted) => converted,
+		Err(why) => {
+			JSON_HANDLER.record_error(day, failed_conversion_stage(why.as_ref()), why.to_string()).await;
+			if let Err(quarantine_error) = quarantine::store(day, hash, pdf, &why.to_string()).await {
+				error!("Couldn't quarantine the pdf of {day}: {quarantine_error}");
+			}
+			return Err(why);
+		}
+	};
+	METRICS.observe(Stage::Tabula, timings.tabula);
+	METRICS.observe(Stage::Parse, timings.parse);
+
+	let serialize_start = Instant::now();
+	let (own, covered) = split_days(day, schedules);
+	let own = match serialize(day, own) {
+		Ok(own) => own,
+		Err(why) => {
+			JSON_HANDLER.record_error(day, Stage::Serialize, why.to_string()).await;
+			return Err(why);
+		}
+	};
+	METRICS.observe(Stage::Serialize, serialize_start.elapsed());
+	debug!("Created json and msgpack!");
+
+	#[cfg(debug_assertions)]
+	crate::schema::validate_schedule(&own.json);
+
+	let covered = covered
+		.into_iter()
+		.filter_map(|(covered_day, schedule)| match serialize(covered_day, schedule) {
+			Ok(converted) => Some(converted),
+			Err(why) => {
+				error!("Couldn't serialize {covered_day} covered by the pdf of {day}: {why}");
+				None
+			}
+		})
+		.collect();
+
+	Ok((own, covered))
+}
+
+/// Normalizes the schedule of `day` and serializes it the way it is served.
+fn serialize(day: Schoolday, mut schedule: SubstitutionSchedule) -> Result<Converted, BoxError> {
+	schedule.normalize_class_names(&NORMALIZER);
+	schedule.assign_day(day);
+	for warning in schedule.warnings() {
+		warn!("{day}: {warning}");
+	}
+
+	Ok(Converted {
+		day,
+		json: serde_json::to_string(&schedule)?,
+		msgpack: rmp_serde::to_vec_named(&schedule)?,
+		schedule,
+	})
+}
+
+/// Archives the pdf and saves the schedules in the database, queueing them while it is unreachable.
+async fn persist(item: Persist, next: mpsc::Sender<Publish>) {
+	let day = item.own.day;
+	if let Err(why) = blob_store::archive(item.pdf.clone(), "").await {
+		error!("{day}: Couldn't archive the pdf: {why}");
+	}
+
+	save(day, item.hash.clone(), &item.own.schedule, Some(item.pdf.clone()), item.publish, &item.pool).await;
+	for covered in &item.covered {
+		save(covered.day, section_hash(&item.hash, covered.day), &covered.schedule, None, item.publish, &item.pool).await;
+	}
+
+	forward(&next, item).await;
+}
+
+/// Serves the schedules, the ones of other days only if the pdf is newer than the one they serve.
+async fn publish(item: Publish) {
+	let day = item.own.day;
+	if !item.publish {
+		debug!("{day}: Not replacing the served json with the one of an older job");
+		let _ = item.done.send(Ok(()));
+		return;
+	}
+
+	let pdf = ServedPdf {
+		hash: item.hash.clone(),
+		bytes: item.pdf,
+	};
+	for covered in item.covered {
+		info!("{day}: The pdf covers {} as well", covered.day);
+		JSON_HANDLER.serve_covered(covered.day, day, covered.json, covered.msgpack, covered.schedule, &pdf).await;
+	}
+	JSON_HANDLER.serve(day, item.hash, item.own.json, item.own.msgpack, item.own.schedule, pdf).await;
+
+	// Once it is served, the served hash keeps the pdf from being converted again.
+	drop(item.in_flight);
+	let _ = item.done.send(Ok(()));
+}
+
+/// Saves a converted schedule and the pdf it came from, queueing them while the database is unreachable.
+async fn save(day: Schoolday, hash: String, schedule: &SubstitutionSchedule, pdf: Option<Bytes>, publish: bool, pool: &PgPool) {
+	let pdf_date = Local.timestamp_opt(schedule.pdf_issue_date / 1000, 0).unwrap();
+	let json = match serde_json::to_value(schedule) {
+		Ok(json) => json,
+		Err(why) => return error!("Couldn't serialize {day}: {why}"),
+	};
+
+	match update_db(day, &hash, &pdf_date, json.clone(), pdf.as_deref(), publish, pool).await {
+		Ok(false) => debug!("{day}: The database already has a row for this pdf"),
+		Ok(true) => {}
+		// The job can't be updated either, the queue writes the schedule once the database is back.
+		Err(why) if why.is_unavailable() => {
+			WRITE_QUEUE.push(PendingWrite {
+				day,
+				hash,
+				pdf_date,
+				json,
+				pdf,
+				notify: publish,
+			}).await;
+		}
+		Err(why) => {
+			if let Err(why) = jobs::fail(pool, &hash, &why.to_string()).await {
+				error!("Couldn't update the conversion job of {day}: {why}");
+			}
+		}
+	}
+}
+
+/// Inserts the json into the db and completes the conversion job of the pdf.
+/// If `publish` is set, the other instances are notified to serve it as well.
+/// Returns whether a new row was written.
+async fn update_db(day: Schoolday, hash: &str, pdf_date: &DateTime<Local>, json: serde_json::Value, pdf: Option<&[u8]>, publish: bool, pool: &PgPool) -> Result<bool, StorageError> {
+	let insert_start = Instant::now();
+
+	match storage::save_schedule(pool, day, hash, pdf_date, json, pdf, publish).await {
+		Ok(inserted) => {
+			METRICS.observe(Stage::DbInsert, insert_start.elapsed());
+			Ok(inserted)
+		}
+		Err(why) => {
+			JSON_HANDLER.record_error(day, Stage::DbInsert, why.to_string()).await;
+			error!("{why}");
+			Err(why)
+		}
+	}
+}
+
+/// Splits the days a pdf covers into the one of `day` and the other school days.
+/// The first day of the pdf stands in for `day` if none is dated on it, like a pdf that wasn't updated yet.
+pub fn split_days(day: Schoolday, mut schedules: Vec<SubstitutionSchedule>) -> (SubstitutionSchedule, Vec<(Schoolday, SubstitutionSchedule)>) {
+	let own = schedules.iter()
+		.position(|schedule| schedule.issue_day() == Some(day))
+		.unwrap_or(0);
+	let schedule = schedules.remove(own);
+
+	let mut covered: Vec<(Schoolday, SubstitutionSchedule)> = Vec::new();
+	for other in schedules {
+		match other.issue_day() {
+			Some(other_day) if other_day != day && covered.iter().all(|(covered_day, _)| *covered_day != other_day) => {
+				covered.push((other_day, other));
+			}
+			_ => warn!("{day}: Ignoring a section of the pdf that doesn't cover another school day"),
+		}
+	}
+
+	(schedule, covered)
+}
+
+/// Attributes a conversion error to the stage it most likely came from.
+/// Errors from tabula itself or from staging its input count as tabula failures, everything else as a parse failure.
+fn failed_conversion_stage(why: &(dyn Error + Send + Sync + 'static)) -> Stage {
+	if matches!(why.downcast_ref::<PDFJsonError>(), Some(PDFJsonError::TabulaError { .. })) || why.is::<std::io::Error>() {
+		Stage::Tabula
+	} else {
+		Stage::Parse
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// 2022-05-09 was a Monday.
+	const MONDAY: i64 = 1_652_054_400_000;
+	const DAY: i64 = 24 * 60 * 60 * 1000;
+
+	fn schedule(pdf_issue_date: i64) -> SubstitutionSchedule {
+		SubstitutionSchedule::from_table(&Vec::new(), pdf_issue_date)
+	}
+
+	#[test]
+	fn the_section_of_the_day_is_its_own() {
+		let (own, covered) = split_days(Schoolday::Tuesday, vec![schedule(MONDAY), schedule(MONDAY + DAY)]);
+
+		assert_eq!(own.issue_day(), Some(Schoolday::Tuesday));
+		assert_eq!(covered.len(), 1);
+		assert_eq!(covered[0].0, Schoolday::Monday);
+		assert_eq!(covered[0].1.issue_day(), Some(Schoolday::Monday));
+	}
+
+	#[test]
+	fn the_first_section_stands_in_for_a_missing_day() {
+		let (own, covered) = split_days(Schoolday::Friday, vec![schedule(MONDAY), schedule(MONDAY + DAY)]);
+
+		assert_eq!(own.issue_day(), Some(Schoolday::Monday));
+		assert_eq!(covered.iter().map(|(day, _)| *day).collect::<Vec<_>>(), [Schoolday::Tuesday]);
+	}
+
+	#[test]
+	fn sections_not_covering_another_school_day_are_ignored() {
+		let saturday = MONDAY - 2 * DAY;
+		let sections = vec![schedule(MONDAY), schedule(MONDAY), schedule(saturday), schedule(MONDAY + DAY), schedule(MONDAY + DAY)];
+
+		let (own, covered) = split_days(Schoolday::Monday, sections);
+
+		assert_eq!(own.issue_day(), Some(Schoolday::Monday));
+		assert_eq!(covered.iter().map(|(day, _)| *day).collect::<Vec<_>>(), [Schoolday::Tuesday]);
+	}
+
+	#[test]
+	fn conversion_errors_are_attributed_to_their_stage() {
+		let io: BoxError = Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "java not found"));
+		let parse: BoxError = "date not found".into();
+
+		assert_eq!(failed_conversion_stage(io.as_ref()), Stage::Tabula);
+		assert_eq!(failed_conversion_stage(parse.as_ref()), Stage::Parse);
+	}
+
+	#[test]
+	fn a_pdf_is_only_in_flight_once_per_day() {
+		let first = InFlight::start(Schoolday::Monday, "in-flight-test").unwrap();
+		assert!(InFlight::start(Schoolday::Monday, "in-flight-test").is_none());
+		assert!(InFlight::start(Schoolday::Tuesday, "in-flight-test").is_some());
+
+		drop(first);
+		assert!(InFlight::start(Schoolday::Monday, "in-flight-test").is_some());
+	}
+}
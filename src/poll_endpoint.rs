@@ -0,0 +1,71 @@
+use std::time::Duration;
+use actix_web::{get, HttpResponse, Responder, web};
+use actix_web::http::header;
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::error;
+use crate::{CONFIG, JSON_HANDLER, Schoolday};
+use crate::stateless::served_versioned_json;
+use crate::storage::Pools;
+
+/// How long a request waits for a change if it doesn't ask for less.
+const DEFAULT_POLL_SECS: u64 = 30;
+/// The longest a request waits, proxies tend to give up on idle requests after a minute or two.
+const MAX_POLL_SECS: u64 = 90;
+
+#[derive(Debug, Deserialize)]
+pub struct PollQuery {
+	/// The hash of the schedule the client has.
+	hash: Option<String>,
+	/// Seconds to wait for a change at most.
+	timeout: Option<u64>,
+}
+
+/// Answers with the schedule of the day once its hash differs from the one the client has,
+/// or with 304 once the timeout passed without a change.
+/// The hash the served schedule is stored under is sent as ETag, to be passed as `hash` to the next request.
+#[get("/poll/{schoolday}")]
+pub async fn get_poll(day: web::Path<Schoolday>, query: web::Query<PollQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let day = day.into_inner();
+	let timeout = Duration::from_secs(query.timeout.unwrap_or(DEFAULT_POLL_SECS).min(MAX_POLL_SECS));
+	let deadline = Instant::now() + timeout;
+	// Changes are announced by this instance, the schedules read from the database in stateless mode only catch up once their cache expires.
+	let recheck = if CONFIG.stateless.enabled {
+		Duration::from_secs(CONFIG.stateless.cache_secs.max(1))
+	} else {
+		timeout
+	};
+
+	// Subscribed before the first look at the hash, so a change in between isn't missed.
+	let mut changes = JSON_HANDLER.subscribe();
+	loop {
+		changes.borrow_and_update();
+		let served = match served_versioned_json(day, &pools.read).await {
+			Ok(served) => served,
+			Err(why) => {
+				error!("{why}");
+				return HttpResponse::InternalServerError().finish();
+			}
+		};
+
+		if let Some(served) = served.as_ref().filter(|served| query.hash.as_ref() != Some(&served.hash)) {
+			return HttpResponse::Ok()
+				.content_type("application/json")
+				.insert_header((header::ETAG, format!("\"{}\"", served.hash)))
+				.insert_header((header::CACHE_CONTROL, "no-store"))
+				.body(served.json.clone());
+		}
+
+		let now = Instant::now();
+		if now >= deadline {
+			let mut response = HttpResponse::NotModified();
+			if let Some(served) = served {
+				response.insert_header((header::ETAG, format!("\"{}\"", served.hash)));
+			}
+			return response.finish();
+		}
+
+		// Neither a change nor the recheck interval passing is a reason to answer, the hash is compared again either way.
+		let _ = tokio::time::timeout(recheck.min(deadline - now), changes.changed()).await;
+	}
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use substitution_pdf_to_json::{ClassName, SubstitutionSchedule};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+use crate::classes::ClassSelector;
+use crate::diff::Change;
+use crate::notifier::{self, Notifier, ScheduleChange};
+use crate::{Schoolday, templates};
+
+/// What a user stored about themselves.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Preferences {
+	/// Classes, courses and class groups the user wants to see, like the `classes` query parameter.
+	pub classes: Vec<String>,
+	/// Url the changes of these classes are POSTed to, like the webhooks in `notify.webhooks`.
+	pub webhook: Option<String>,
+	/// Times of the day no changes are POSTed to the webhook at, the changes within them are sent as one when they end.
+	#[serde(default)]
+	pub quiet_hours: Option<QuietHours>,
+}
+
+/// A daily window, from `start` until `end` on the next day if `end` is earlier.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct QuietHours {
+	pub start: NaiveTime,
+	pub end: NaiveTime,
+}
+
+impl QuietHours {
+	/// The quiet hours stored in the columns `quiet_start` and `quiet_end`.
+	fn from_columns(start: Option<NaiveTime>, end: Option<NaiveTime>) -> Option<Self> {
+		Some(Self {
+			start: start?,
+			end: end?,
+		})
+	}
+
+	/// Whether `time` is within the quiet hours, which may span midnight.
+	pub fn contains(&self, time: NaiveTime) -> bool {
+		if self.start <= self.end {
+			self.start <= time && time < self.end
+		} else {
+			time >= self.start || time < self.end
+		}
+	}
+
+	/// When the quiet hours that `now` is in end.
+	pub fn end_after(&self, now: DateTime<Local>) -> DateTime<Local> {
+		let mut date = now.date_naive();
+		if self.end <= now.time() {
+			date = date.succ_opt().unwrap_or(date);
+		}
+
+		Local.from_local_datetime(&date.and_time(self.end)).earliest().unwrap_or(now)
+	}
+}
+
+impl Preferences {
+	fn selectors(&self) -> Vec<ClassSelector> {
+		self.classes.iter().map(|class| ClassSelector::new(class)).collect()
+	}
+
+	/// Whether any of the classes of the user is `class`.
+	pub fn matches(&self, class: &ClassName) -> bool {
+		self.selectors().iter().any(|selector| selector.matches(class))
+	}
+
+	/// The schedule with only the classes of the user.
+	pub fn filter(&self, schedule: &SubstitutionSchedule) -> SubstitutionSchedule {
+		let selectors = self.selectors();
+		schedule.filter_classes(|class| selectors.iter().any(|selector| selector.matches(class)))
+	}
+}
+
+/// The preferences of the user, the default ones if there are none stored.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be queried.
+pub async fn load(pool: &PgPool, user_id: i64) -> Result<Preferences, sqlx::Error> {
+	let row = sqlx::query!("SELECT classes, webhook, quiet_start, quiet_end FROM user_preferences WHERE user_id = $1", user_id)
+		.fetch_optional(pool)
+		.await?;
+
+	Ok(row.map(|row| Preferences {
+		classes: row.classes,
+		webhook: row.webhook,
+		quiet_hours: QuietHours::from_columns(row.quiet_start, row.quiet_end),
+	}).unwrap_or_default())
+}
+
+/// Replaces the preferences of the user.
+///
+/// # Errors
+///
+/// Returns `Err` if the preferences couldn't be stored.
+pub async fn save(pool: &PgPool, user_id: i64, preferences: &Preferences) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+		INSERT INTO user_preferences (user_id, classes, webhook, quiet_start, quiet_end)
+		VALUES ($1, $2, $3, $4, $5)
+		ON CONFLICT (user_id) DO UPDATE
+			SET classes = EXCLUDED.classes, webhook = EXCLUDED.webhook, quiet_start = EXCLUDED.quiet_start, quiet_end = EXCLUDED.quiet_end
+		"#,
+		user_id,
+		&preferences.classes,
+		preferences.webhook,
+		preferences.quiet_hours.map(|quiet_hours| quiet_hours.start),
+		preferences.quiet_hours.map(|quiet_hours| quiet_hours.end)
+	)
+		.execute(pool)
+		.await?;
+
+	Ok(())
+}
+
+/// The id of a user and a day whose changes are held back during the quiet hours of the user.
+type HeldKey = (i64, Schoolday);
+
+/// POSTs the changes of their classes to the webhooks of the users.
+/// Changes during the quiet hours of a user are held back and sent to them as one change when they end.
+#[derive(Clone)]
+pub struct UserNotifier {
+	client: Client,
+	pool: PgPool,
+	/// Days whose changes are held back during the quiet hours of a user,
+	/// with the schedule that was served before the first held change.
+	held: Arc<Mutex<HashMap<HeldKey, Option<Arc<SubstitutionSchedule>>>>>,
+}
+
+impl UserNotifier {
+	pub fn new(pool: PgPool) -> Self {
+		Self {
+			client: Client::new(),
+			pool,
+			held: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// POSTs the changes of the classes of the user to their webhook, if there are any.
+	/// A failed webhook is only logged, retrying would notify every other user again.
+	async fn send(&self, preferences: &Preferences, change: &ScheduleChange) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let webhook = match &preferences.webhook {
+			Some(webhook) => webhook,
+			None => return Ok(()),
+		};
+		let user_changes: Vec<Change> = change.changes()
+			.into_iter()
+			.filter(|change| preferences.matches(change.class))
+			.collect();
+		if user_changes.is_empty() {
+			return Ok(());
+		}
+
+		let message = templates::render(self.name(), change, &user_changes)?;
+		let mut classes: Vec<&ClassName> = user_changes.iter().map(|change| change.class).collect();
+		classes.dedup();
+		let sent = self.client
+			.post(webhook)
+			.json(&json!({
+				"text": message,
+				"content": message,
+				"day": change.day,
+				"changed_classes": classes,
+			}))
+			.send()
+			.await
+			.and_then(reqwest::Response::error_for_status);
+		if let Err(why) = sent {
+			warn!("{}: Couldn't notify the webhook {webhook}: {why}", change.day);
+		}
+
+		Ok(())
+	}
+
+	/// Holds the change back from the user until `until`, then sends everything that changed since the first held change.
+	async fn hold(&self, user_id: i64, change: &ScheduleChange, until: DateTime<Local>) {
+		let day = change.day;
+		let key = (user_id, day);
+
+		// A later change is covered by the one that is already held.
+		let mut held = self.held.lock().await;
+		if held.contains_key(&key) {
+			return;
+		}
+		let _ = held.insert(key, change.previous.clone());
+		drop(held);
+
+		info!("{day}: Holding the change back from user {user_id} until {}", until.format("%H:%M"));
+		let notifier = self.clone();
+		tokio::spawn(async move {
+			if let Ok(wait) = (until - Local::now()).to_std() {
+				tokio::time::sleep(wait).await;
+			}
+
+			let previous = notifier.held.lock().await.remove(&key).flatten();
+			let change = match notifier::current_change(day, &notifier.pool).await {
+				Some(current) => ScheduleChange {
+					previous,
+					..current
+				},
+				None => return,
+			};
+			// The user may have changed their classes or webhook in the meantime.
+			let sent = match load(&notifier.pool, user_id).await {
+				Ok(preferences) => notifier.send(&preferences, &change).await,
+				Err(why) => Err(why.into()),
+			};
+			if let Err(why) = sent {
+				error!("{day}: Couldn't send the held change to user {user_id}: {why}");
+			}
+		});
+	}
+}
+
+#[async_trait]
+impl Notifier for UserNotifier {
+	fn name(&self) -> &'static str {
+		"users"
+	}
+
+	async fn notify(&self, change: &ScheduleChange) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let subscriptions = sqlx::query!(
+			"SELECT user_id, classes, webhook, quiet_start, quiet_end FROM user_preferences WHERE webhook IS NOT NULL AND classes <> '{}'"
+		)
+			.fetch_all(&self.pool)
+			.await?;
+
+		let now = Local::now();
+		for subscription in subscriptions {
+			let preferences = Preferences {
+				classes: subscription.classes,
+				webhook: subscription.webhook,
+				quiet_hours: QuietHours::from_columns(subscription.quiet_start, subscription.quiet_end),
+			};
+
+			match preferences.quiet_hours.filter(|quiet_hours| quiet_hours.contains(now.time())) {
+				Some(quiet_hours) => {
+					if change.changes().iter().any(|change| preferences.matches(change.class)) {
+						self.hold(subscription.user_id, change, quiet_hours.end_after(now)).await;
+					}
+				}
+				None => self.send(&preferences, change).await?,
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn time(hour: u32, minute: u32) -> NaiveTime {
+		NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+	}
+
+	#[test]
+	fn quiet_hours_may_span_midnight() {
+		let night = QuietHours {
+			start: time(22, 0),
+			end: time(6, 30),
+		};
+
+		assert!(night.contains(time(22, 0)));
+		assert!(night.contains(time(2, 0)));
+		assert!(!night.contains(time(6, 30)));
+		assert!(!night.contains(time(12, 0)));
+	}
+
+	#[test]
+	fn held_changes_are_sent_when_the_quiet_hours_end() {
+		let night = QuietHours {
+			start: time(22, 0),
+			end: time(6, 30),
+		};
+		let evening = Local.with_ymd_and_hms(2022, 5, 12, 22, 30, 0).unwrap();
+		let morning = Local.with_ymd_and_hms(2022, 5, 13, 5, 0, 0).unwrap();
+
+		assert_eq!(night.end_after(evening), Local.with_ymd_and_hms(2022, 5, 13, 6, 30, 0).unwrap());
+		assert_eq!(night.end_after(morning), Local.with_ymd_and_hms(2022, 5, 13, 6, 30, 0).unwrap());
+	}
+}
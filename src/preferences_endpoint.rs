@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use actix_web::{get, HttpResponse, put, Responder, web};
+use reqwest::Url;
+use tracing::error;
+use crate::{preferences, Schoolday};
+use crate::auth::Claims;
+use crate::preferences::Preferences;
+use crate::stateless::served_schedule;
+use crate::storage::Pools;
+
+/// The preferences of the user the token belongs to.
+#[get("/me/preferences")]
+pub async fn get_preferences(claims: Claims, pools: web::Data<Pools>) -> impl Responder {
+	match preferences::load(&pools.read, claims.sub).await {
+		Ok(preferences) => HttpResponse::Ok().json(preferences),
+		Err(why) => {
+			error!("Couldn't load the preferences of {}: {why}", claims.name);
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// Replaces the preferences of the user the token belongs to.
+#[put("/me/preferences")]
+pub async fn put_preferences(claims: Claims, body: web::Json<Preferences>, pools: web::Data<Pools>) -> impl Responder {
+	let mut preferences = body.into_inner();
+	preferences.classes.retain(|class| !class.trim().is_empty());
+
+	if let Some(webhook) = &preferences.webhook {
+		if !Url::parse(webhook).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+			return HttpResponse::BadRequest().body("The webhook has to be a http or https url");
+		}
+	}
+
+	match preferences::save(&pools.write, claims.sub, &preferences).await {
+		Ok(()) => HttpResponse::Ok().json(preferences),
+		Err(why) => {
+			error!("Couldn't store the preferences of {}: {why}", claims.name);
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// The served schedules of every day, with only the classes of the user.
+/// Days without a schedule are left out.
+#[get("/me/schedule")]
+pub async fn get_my_schedule(claims: Claims, pools: web::Data<Pools>) -> impl Responder {
+	let preferences = match preferences::load(&pools.read, claims.sub).await {
+		Ok(preferences) => preferences,
+		Err(why) => {
+			error!("Couldn't load the preferences of {}: {why}", claims.name);
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	let mut schedules = HashMap::new();
+	for day in Schoolday::ALL {
+		match served_schedule(day, &pools.read).await {
+			Ok(Some(schedule)) => {
+				let _ = schedules.insert(day, preferences.filter(&schedule));
+			}
+			Ok(None) => {}
+			Err(why) => {
+				error!("Couldn't read the schedule of {day}: {why}");
+				return HttpResponse::InternalServerError().finish();
+			}
+		}
+	}
+
+	HttpResponse::Ok().json(schedules)
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use async_trait::async_trait;
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use crate::{CONFIG, Schoolday};
+use crate::config::Renderer;
+use crate::json_handler::ServedPdf;
+
+lazy_static! {
+	/// The last rendered preview of every day, with the hash of the pdf it was rendered from.
+	static ref PREVIEWS: Mutex<HashMap<Schoolday, (String, Bytes)>> = Mutex::new(HashMap::new());
+	static ref RENDERER: Box<dyn PageRenderer> = match CONFIG.preview.renderer {
+		Renderer::Ghostscript => Box::new(Ghostscript),
+		Renderer::Pdftoppm => Box::new(Pdftoppm),
+	};
+}
+
+/// Renders pdf pages to images.
+#[async_trait]
+pub trait PageRenderer: Send + Sync {
+	/// Renders the first page of `pdf` to a png with `dpi` pixels per inch.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if the renderer couldn't be run or failed on the pdf.
+	async fn render_first_page(&self, pdf: &[u8], dpi: u32) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Renders with the `gs` binary of Ghostscript.
+pub struct Ghostscript;
+
+/// Renders with the `pdftoppm` binary of poppler.
+pub struct Pdftoppm;
+
+#[async_trait]
+impl PageRenderer for Ghostscript {
+	async fn render_first_page(&self, pdf: &[u8], dpi: u32) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+		let mut command = Command::new("gs");
+		command.args(["-q", "-dSAFER", "-dBATCH", "-dNOPAUSE", "-sDEVICE=png16m", "-dFirstPage=1", "-dLastPage=1", "-sOutputFile=-"])
+			.arg(format!("-r{dpi}"))
+			.arg("-");
+
+		run_piped(command, pdf).await
+	}
+}
+
+#[async_trait]
+impl PageRenderer for Pdftoppm {
+	async fn render_first_page(&self, pdf: &[u8], dpi: u32) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+		let mut command = Command::new("pdftoppm");
+		command.args(["-png", "-singlefile", "-f", "1", "-l", "1", "-r"])
+			.arg(dpi.to_string());
+
+		run_piped(command, pdf).await
+	}
+}
+
+/// Runs `command` with `pdf` on stdin and returns what it wrote to stdout.
+async fn run_piped(mut command: Command, pdf: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+	let mut child = command
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()?;
+
+	// Writing and reading at the same time, the renderer may block on a full stdout before it read the whole pdf.
+	let mut stdin = child.stdin.take().ok_or("The renderer has no stdin")?;
+	let pdf = pdf.to_vec();
+	let writer = tokio::spawn(async move {
+		stdin.write_all(&pdf).await
+	});
+
+	let output = child.wait_with_output().await?;
+	writer.await??;
+
+	if !output.status.success() {
+		return Err(format!("The renderer exited with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()).into());
+	}
+
+	Ok(output.stdout)
+}
+
+/// Gets the preview of `day` rendered from `pdf`, rendering it only if the pdf changed since the last time.
+pub async fn preview(day: Schoolday, pdf: ServedPdf) -> Result<Bytes, Box<dyn std::error::Error + Send + Sync>> {
+	let mut previews = PREVIEWS.lock().await;
+	if let Some((rendered_from, png)) = previews.get(&day) {
+		if *rendered_from == pdf.hash {
+			return Ok(png.clone());
+		}
+	}
+
+	let png = Bytes::from(RENDERER.render_first_page(&pdf.bytes, CONFIG.preview.dpi).await?);
+	let _ = previews.insert(day, (pdf.hash, png.clone()));
+
+	Ok(png)
+}
@@ -0,0 +1,31 @@
+use actix_web::{get, HttpResponse, Responder, web};
+use actix_web::http::header;
+use tracing::error;
+use crate::{preview, Schoolday};
+use crate::stateless::served_pdf;
+use crate::storage::Pools;
+
+/// Serves the first page of the pdf of a day as a png, for displays that can't render the schedule themselves.
+#[get("/{schoolday}/preview.png")]
+pub async fn get_preview(day: web::Path<Schoolday>, pools: web::Data<Pools>) -> impl Responder {
+	let day = day.into_inner();
+	let pdf = match served_pdf(day, &pools.read).await {
+		Ok(Some(pdf)) => pdf,
+		Ok(None) => return HttpResponse::NotFound().body(format!("There is no pdf of {day} yet")),
+		Err(why) => {
+			error!("Couldn't read the pdf of {day} from the database: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	match preview::preview(day, pdf).await {
+		Ok(png) => HttpResponse::Ok()
+			.content_type("image/png")
+			.insert_header((header::CACHE_CONTROL, "no-cache"))
+			.body(png),
+		Err(why) => {
+			error!("Couldn't render the preview of {day}: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
@@ -0,0 +1,42 @@
+use substitution_pdf_to_json as schedule;
+
+include!(concat!(env!("OUT_DIR"), "/substitution.rs"));
+
+impl From<&schedule::SubstitutionColumn> for SubstitutionColumn {
+	fn from(column: &schedule::SubstitutionColumn) -> Self {
+		let mut blocks: [String; 6] = Default::default();
+		for (idx, text) in column.blocks() {
+			blocks[idx] = text.to_string();
+		}
+		let [block_0, block_1, block_2, block_3, block_4, block_5] = blocks;
+
+		Self {
+			block_0,
+			block_1,
+			block_2,
+			block_3,
+			block_4,
+			block_5,
+		}
+	}
+}
+
+impl From<&schedule::SubstitutionSchedule> for SubstitutionSchedule {
+	fn from(schedule: &schedule::SubstitutionSchedule) -> Self {
+		let entries = schedule.entries()
+			.iter()
+			.map(|(class, column)| ClassEntry {
+				class: class.to_string(),
+				substitutions: Some(column.into()),
+			})
+			.collect();
+
+		Self {
+			pdf_issue_date: schedule.pdf_issue_date,
+			entries,
+			struct_time: schedule.struct_time(),
+			effective_date: schedule.effective_date().map(|date| date.to_string()).unwrap_or_default(),
+			issue_date_mismatch: schedule.issue_date_mismatch(),
+		}
+	}
+}
@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+use chrono::Local;
+use tokio::io::AsyncWriteExt;
+use crate::{QUARANTINE_LOCATION, Schoolday};
+
+/// Keeps a pdf that failed to convert, so the failure can be reproduced later.
+/// The pdf is stored as `<hash>.pdf` next to a `<hash>.txt` with the error.
+/// A pdf that is already quarantined isn't written again.
+///
+/// # Errors
+///
+/// Returns `Err` if the files couldn't be written.
+pub async fn store(day: Schoolday, hash: &str, pdf: &[u8], error: &str) -> Result<(), std::io::Error> {
+	let pdf_path = pdf_path(hash);
+	if pdf_path.exists() {
+		return Ok(());
+	}
+
+	let mut file = tokio::fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(&pdf_path)
+		.await?;
+	file.write_all(pdf).await?;
+
+	let report = format!("day: {day}\ntime: {}\nerror: {error}\n", Local::now().to_rfc3339());
+	tokio::fs::write(PathBuf::from(QUARANTINE_LOCATION).join(format!("{hash}.txt")), report).await?;
+
+	Ok(())
+}
+
+/// Reads a quarantined pdf, `None` if there is none with this hash.
+///
+/// # Errors
+///
+/// Returns `Err` if the pdf exists but couldn't be read.
+pub async fn load(hash: &str) -> Result<Option<Vec<u8>>, std::io::Error> {
+	match tokio::fs::read(pdf_path(hash)).await {
+		Ok(pdf) => Ok(Some(pdf)),
+		Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(why) => Err(why),
+	}
+}
+
+fn pdf_path(hash: &str) -> PathBuf {
+	PathBuf::from(QUARANTINE_LOCATION).join(format!("{hash}.pdf"))
+}
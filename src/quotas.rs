@@ -0,0 +1,210 @@
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use ipnet::{IpNet, Ipv6Net};
+use lazy_static::lazy_static;
+use lru::LruCache;
+use tracing::warn;
+use crate::CONFIG;
+use crate::config::QuotaConfig;
+
+/// The tier of clients without a known key or address.
+pub const ANONYMOUS_TIER: &str = "anonymous";
+/// The tier the buckets of `/convert` are kept under, next to the configured ones.
+const CONVERSION_TIER: &str = "/convert";
+const API_KEY_HEADER: &str = "x-api-key";
+/// Buckets kept before the least recently used one is forgotten, it most likely refilled already.
+const MAX_BUCKETS: usize = 10_000;
+
+lazy_static! {
+	static ref QUOTAS: Quotas = Quotas::new(&CONFIG.quotas);
+}
+
+/// The requests a client has left, refilled continuously up to the burst of its tier.
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+struct Quotas {
+	networks: Vec<(IpNet, String)>,
+	/// By tier and client, the key or the address.
+	buckets: Mutex<LruCache<(String, String), Bucket>>,
+}
+
+/// The state of the budget of a client after a request.
+pub struct Quota {
+	limit: u32,
+	remaining: u32,
+	/// Until the budget is full again.
+	reset: Duration,
+	/// Until the next request is allowed, `None` if this one was.
+	retry_after: Option<Duration>,
+}
+
+impl Quotas {
+	fn new(config: &QuotaConfig) -> Self {
+		let networks = config.networks
+			.iter()
+			.filter_map(|(network, tier)| match network.parse() {
+				Ok(network) => Some((network, tier.clone())),
+				Err(why) => {
+					warn!("Ignoring the quota network {network}: {why}");
+					None
+				}
+			})
+			.collect();
+
+		Self {
+			networks,
+			buckets: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_BUCKETS).unwrap())),
+		}
+	}
+
+	/// Takes a request from the bucket of `client` in the tier, which allows `per_second` requests and `burst` at once.
+	#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+	fn take(&self, tier_name: &str, per_second: f64, burst: u32, client: String) -> Quota {
+		let now = Instant::now();
+		let burst = f64::from(burst.max(1));
+		let refill = |bucket: &Bucket| (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second).min(burst);
+
+		let mut buckets = self.buckets.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+		let bucket = buckets.get_or_insert_mut((tier_name.to_string(), client), || Bucket {
+			tokens: burst,
+			updated: now,
+		});
+		bucket.tokens = refill(bucket);
+		bucket.updated = now;
+
+		let retry_after = if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			None
+		} else {
+			Some(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+		};
+
+		Quota {
+			limit: burst as u32,
+			remaining: bucket.tokens.floor() as u32,
+			reset: Duration::from_secs_f64((burst - bucket.tokens) / per_second),
+			retry_after,
+		}
+	}
+}
+
+impl Quota {
+	pub fn exceeded(&self) -> bool {
+		self.retry_after.is_some()
+	}
+
+	/// Adds the `X-RateLimit-*` headers, and `Retry-After` if the request was rejected.
+	#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+	pub fn add_headers(&self, headers: &mut HeaderMap) {
+		let mut insert = |name: HeaderName, value: u64| {
+			headers.insert(name, HeaderValue::from(value));
+		};
+
+		insert(HeaderName::from_static("x-ratelimit-limit"), u64::from(self.limit));
+		insert(HeaderName::from_static("x-ratelimit-remaining"), u64::from(self.remaining));
+		insert(HeaderName::from_static("x-ratelimit-reset"), self.reset.as_secs_f64().ceil() as u64);
+		if let Some(retry_after) = self.retry_after {
+			insert(RETRY_AFTER, retry_after.as_secs_f64().ceil() as u64);
+		}
+	}
+}
+
+/// Takes the request from the budget of the client, `None` if its tier isn't limited.
+pub fn check(req: &HttpRequest) -> Option<Quota> {
+	let config = &CONFIG.quotas;
+	if !config.enabled {
+		return None;
+	}
+
+	let key = req.headers()
+		.get(API_KEY_HEADER)
+		.and_then(|key| key.to_str().ok())
+		.filter(|key| config.keys.contains_key(*key));
+	let address = client_address(req, config.trust_forwarded);
+
+	let (tier_name, client) = match (key, address) {
+		(Some(key), _) => (config.keys[key].as_str(), key.to_string()),
+		(None, Some(address)) => {
+			let tier = QUOTAS.networks
+				.iter()
+				.find(|(network, _)| network.contains(&address))
+				.map_or(ANONYMOUS_TIER, |(_, tier)| tier.as_str());
+			(tier, client_id(address, config.ipv6_prefix_len))
+		}
+		// Requests over unix sockets have no address.
+		(None, None) => (ANONYMOUS_TIER, String::new()),
+	};
+
+	let tier = config.tiers.get(tier_name).filter(|tier| tier.requests_per_minute > 0)?;
+	Some(QUOTAS.take(tier_name, f64::from(tier.requests_per_minute) / 60.0, tier.burst, client))
+}
+
+/// Takes a conversion from the budget of the address of the client, `None` if conversions aren't limited.
+/// Conversions are limited separately from the other requests, even while the quotas are disabled.
+pub fn check_conversion(req: &HttpRequest) -> Option<Quota> {
+	let config = &CONFIG.convert;
+	if config.conversions_per_hour == 0 {
+		return None;
+	}
+
+	let address = client_address(req, CONFIG.quotas.trust_forwarded)
+		.map(|address| client_id(address, CONFIG.quotas.ipv6_prefix_len))
+		.unwrap_or_default();
+	Some(QUOTAS.take(CONVERSION_TIER, f64::from(config.conversions_per_hour) / 3600.0, config.burst, address))
+}
+
+/// The answer to a request that exceeded the quota, the headers are added by [`Quota::add_headers`].
+pub fn too_many_requests() -> HttpResponse {
+	HttpResponse::TooManyRequests().body("Too many requests, see the Retry-After header")
+}
+
+/// The client a budget is kept for: the address, or for IPv6 the network of `ipv6_prefix_len` around it.
+fn client_id(address: IpAddr, ipv6_prefix_len: u8) -> String {
+	match address {
+		IpAddr::V6(address) if address.to_ipv4_mapped().is_none() => Ipv6Net::new(address, ipv6_prefix_len)
+			.map_or_else(|_| address.to_string(), |network| network.trunc().to_string()),
+		address => address.to_canonical().to_string(),
+	}
+}
+
+fn client_address(req: &HttpRequest, trust_forwarded: bool) -> Option<IpAddr> {
+	if !trust_forwarded {
+		return req.peer_addr().map(|peer| peer.ip());
+	}
+
+	let info = req.connection_info();
+	let address = info.realip_remote_addr()?;
+	address.parse::<IpAddr>()
+		.or_else(|_| address.parse::<SocketAddr>().map(|address| address.ip()))
+		.ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn id(address: &str, ipv6_prefix_len: u8) -> String {
+		client_id(address.parse().unwrap(), ipv6_prefix_len)
+	}
+
+	#[test]
+	fn ipv6_clients_are_limited_by_network() {
+		assert_eq!(id("2001:db8:1:2:a:b:c:d", 64), "2001:db8:1:2::/64");
+		assert_eq!(id("2001:db8:1:2:ffff::1", 64), id("2001:db8:1:2:a:b:c:d", 64));
+		assert_ne!(id("2001:db8:1:3::1", 64), id("2001:db8:1:2::1", 64));
+		assert_eq!(id("2001:db8:1:2:a:b:c:d", 48), "2001:db8:1::/48");
+	}
+
+	#[test]
+	fn ipv4_clients_are_limited_by_address() {
+		assert_eq!(id("192.0.2.7", 64), "192.0.2.7");
+		assert_eq!(id("::ffff:192.0.2.7", 64), "192.0.2.7");
+	}
+}
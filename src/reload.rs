@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use actix_web::http::header::HeaderValue;
+use arc_swap::ArcSwap;
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+use crate::{CLI, CONFIG, notifier, templates, validation};
+use crate::config::Config;
+use crate::fetcher::PdfFetcher;
+use crate::notifier::Registry;
+use crate::pdf_getter::SubstitutionPDFGetter;
+use crate::scheduler::{Plan, Scheduler};
+
+lazy_static! {
+	/// The config as of the last reload, for the settings that can change without a restart.
+	/// Everything else keeps using [`CONFIG`].
+	pub static ref LIVE_CONFIG: ArcSwap<Config> = ArcSwap::from_pointee(CONFIG.clone());
+}
+
+/// Whether browsers may call the api from `origin`.
+pub fn allows_origin(origin: &HeaderValue) -> bool {
+	let config = LIVE_CONFIG.load();
+	config.cors_origins.is_empty() || config.cors_origins.iter().any(|allowed| allowed.as_bytes() == origin.as_bytes())
+}
+
+/// Reloads the config file whenever the process gets a SIGHUP.
+/// `scheduler` is `None` when the pdfs are read from a directory, which can't be changed without a restart.
+pub async fn reload_on_hangup(pool: PgPool, scheduler: Option<Scheduler>) {
+	let mut hangups = match signal(SignalKind::hangup()) {
+		Ok(hangups) => hangups,
+		Err(why) => return error!("Couldn't listen for SIGHUP, the config can't be reloaded: {why}"),
+	};
+
+	while hangups.recv().await.is_some() {
+		info!("Got SIGHUP, reloading the config from {}", CLI.config.display());
+		if let Err(why) = reload(&pool, scheduler.as_ref()).await {
+			error!("Keeping the previous config: {why}");
+		}
+	}
+}
+
+/// Loads and validates the config, then applies it.
+/// Everything is built before anything is replaced, so an invalid config leaves the previous one in place.
+async fn reload(pool: &PgPool, scheduler: Option<&Scheduler>) -> Result<(), Box<dyn std::error::Error>> {
+	let config = Config::load(&CLI.config)?;
+
+	let report = validation::validate(&config, pool).await;
+	if report.errors() > 0 {
+		return Err(report.to_string().into());
+	}
+	if !report.is_empty() {
+		warn!("{report}");
+	}
+	if config.source.directory != LIVE_CONFIG.load().source.directory {
+		warn!("Switching between fetching the pdfs and reading them from a directory needs a restart");
+	}
+
+	let plan = match scheduler {
+		Some(_) => {
+			let fetcher: Arc<dyn PdfFetcher> = Arc::new(SubstitutionPDFGetter::from_config(&config.source)?);
+			Some(Plan::new(&config.schedule, fetcher)?)
+		}
+		None => None,
+	};
+	let compiled_templates = templates::compile(&config.notify.templates)?;
+	let registry = Registry::from_config(&config, pool.clone());
+
+	if let (Some(scheduler), Some(plan)) = (scheduler, plan) {
+		scheduler.reload(plan);
+	}
+	templates::TEMPLATES.store(Arc::new(compiled_templates));
+	notifier::replace(registry);
+	LIVE_CONFIG.store(Arc::new(config));
+
+	info!("Reloaded the config");
+	Ok(())
+}
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use arc_swap::ArcSwap;
+use chrono::{Datelike, DateTime, Local};
+use cron::Schedule;
+use rand::Rng;
+use sqlx::PgPool;
+use tokio::sync::{Notify, watch};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{debug, error, info, trace};
+use crate::{notifier, pipeline, Schoolday, systemd};
+use crate::config::ScheduleConfig;
+use crate::fetcher::PdfFetcher;
+
+/// How often a day is fetched.
+#[derive(Debug, Clone)]
+pub enum Cadence {
+	/// A fixed time after the last fetch.
+	Interval(Duration),
+	/// At the earliest upcoming time of any of the cron expressions.
+	Cron(Vec<Schedule>),
+}
+
+/// What and when the scheduler fetches, replaced as a whole when the config is reloaded.
+pub struct Plan {
+	fetcher: Arc<dyn PdfFetcher>,
+	/// Upper bound for a single sleep, so days becoming relevant at midnight are picked up.
+	interval: Duration,
+	jitter: Duration,
+	cadences: HashMap<Schoolday, Cadence>,
+	/// When the digest of the next school day is sent.
+	digest_cron: Vec<Schedule>,
+}
+
+impl Plan {
+	/// # Errors
+	///
+	/// Returns `Err` if a cron expression is invalid.
+	pub fn new(config: &ScheduleConfig, fetcher: Arc<dyn PdfFetcher>) -> Result<Self, cron::error::Error> {
+		Ok(Self {
+			fetcher,
+			interval: Duration::from_secs(config.interval_secs),
+			jitter: Duration::from_secs(config.jitter_secs),
+			cadences: Scheduler::cadences(config)?,
+			digest_cron: config.digest_cron.iter().map(|expression| Schedule::from_str(expression)).collect::<Result<_, _>>()?,
+		})
+	}
+}
+
+/// When a day was fetched last, to tell when it is fetched next.
+#[derive(Debug, Clone, Copy)]
+struct LastRun {
+	at: Instant,
+	local: DateTime<Local>,
+	jitter: Duration,
+}
+
+/// Decides when the pdfs are fetched and runs the fetches.
+/// Clones share the plan, so a reload applies to the running scheduler.
+#[derive(Clone)]
+pub struct Scheduler {
+	pool: PgPool,
+	plan: Arc<ArcSwap<Plan>>,
+	/// Wakes the running scheduler up after the plan was replaced.
+	reloaded: Arc<Notify>,
+}
+
+impl Scheduler {
+	/// # Errors
+	///
+	/// Returns `Err` if a cron expression is invalid.
+	pub fn new(config: &ScheduleConfig, fetcher: Arc<dyn PdfFetcher>, pool: PgPool) -> Result<Self, cron::error::Error> {
+		Ok(Self {
+			pool,
+			plan: Arc::new(ArcSwap::from_pointee(Plan::new(config, fetcher)?)),
+			reloaded: Arc::new(Notify::new()),
+		})
+	}
+
+	/// Replaces the plan, fetches that are running finish with the old one.
+	/// The next fetches of the days are rescheduled with the new cadences.
+	pub fn reload(&self, plan: Plan) {
+		self.plan.store(Arc::new(plan));
+		self.reloaded.notify_waiters();
+	}
+
+	/// Resolves the cadence of every day, cron expressions take precedence over intervals
+	/// and settings of single days over the ones for all days.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if a cron expression is invalid.
+	pub fn cadences(config: &ScheduleConfig) -> Result<HashMap<Schoolday, Cadence>, cron::error::Error> {
+		let parse = |expressions: &Vec<String>| -> Result<Vec<Schedule>, cron::error::Error> {
+			expressions.iter().map(|expression| Schedule::from_str(expression)).collect()
+		};
+		let default_cron = parse(&config.cron)?;
+
+		Schoolday::ALL
+			.into_iter()
+			.map(|day| {
+				let cadence = match (config.day_cron.get(&day), config.day_interval_secs.get(&day)) {
+					(Some(expressions), _) if !expressions.is_empty() => Cadence::Cron(parse(expressions)?),
+					(_, Some(secs)) => Cadence::Interval(Duration::from_secs(*secs)),
+					_ if !default_cron.is_empty() => Cadence::Cron(default_cron.clone()),
+					_ => Cadence::Interval(Duration::from_secs(config.interval_secs)),
+				};
+
+				Ok((day, cadence))
+			})
+			.collect()
+	}
+
+	/// The days whose pdfs are relevant at `now`: the current school day and the one after it.
+	pub fn days_to_fetch(now: DateTime<Local>) -> [Schoolday; 2] {
+		let day = Schoolday::from(now.weekday());
+		[day, day.next_day()]
+	}
+
+	/// When `day` is fetched next, if it was last fetched at `last_run`, which was `now` in local time.
+	/// `jitter` is added on top, so fetches of many instances don't line up.
+	pub fn next_run(plan: &Plan, day: Schoolday, last_run: Instant, now: DateTime<Local>, jitter: Duration) -> Instant {
+		let wait = match plan.cadences.get(&day) {
+			Some(Cadence::Interval(interval)) => *interval,
+			Some(Cadence::Cron(schedules)) => schedules
+				.iter()
+				.filter_map(|schedule| schedule.after(&now).next())
+				.min()
+				.and_then(|next| (next - now).to_std().ok())
+				// Expressions without upcoming times never fire again, a year is as good as never here.
+				.unwrap_or(Duration::from_secs(60 * 60 * 24 * 365)),
+			None => plan.interval,
+		};
+
+		last_run + wait + jitter
+	}
+
+	/// The day the digest sent at `now` is about: the next school day.
+	pub fn digest_day(now: DateTime<Local>) -> Schoolday {
+		Schoolday::from((now + chrono::Duration::days(1)).weekday())
+	}
+
+	/// When the next digest is sent, `None` if there are no digests.
+	fn next_digest(plan: &Plan, now: DateTime<Local>) -> Option<Instant> {
+		plan.digest_cron
+			.iter()
+			.filter_map(|schedule| schedule.after(&now).next())
+			.min()
+			.and_then(|next| (next - now).to_std().ok())
+			.map(|wait| Instant::now() + wait)
+	}
+
+	fn sample_jitter(plan: &Plan) -> Duration {
+		if plan.jitter.is_zero() {
+			return Duration::ZERO;
+		}
+
+		rand::thread_rng().gen_range(Duration::ZERO..=plan.jitter)
+	}
+
+	/// Fetches the relevant days whenever they are due, until `shutdown` changes.
+	/// Fetches that are already running are awaited before this returns.
+	pub async fn run(self, mut shutdown: watch::Receiver<bool>) {
+		let mut last_runs: HashMap<Schoolday, LastRun> = HashMap::new();
+		let mut checks = JoinSet::new();
+		let mut counter: u64 = 0;
+		let mut next_digest = Self::next_digest(&self.plan.load(), Local::now());
+
+		info!("Starting scheduler!");
+		loop {
+			systemd::feed_watchdog();
+
+			// The next runs are derived from the last ones with the current plan, so a reload applies right away.
+			let plan = self.plan.load_full();
+			let now = Instant::now();
+			let local_now = Local::now();
+			let days = Self::days_to_fetch(local_now);
+			trace!("Relevant days: {} and {}", days[0], days[1]);
+
+			let next_run = |day: Schoolday, last_runs: &HashMap<Schoolday, LastRun>| {
+				last_runs.get(&day).map(|last| Self::next_run(&plan, day, last.at, last.local, last.jitter))
+			};
+
+			for day in days {
+				if next_run(day, &last_runs).is_some_and(|next_run| next_run > now) {
+					continue;
+				}
+
+				let fetcher = plan.fetcher.clone();
+				let pool = self.pool.clone();
+				checks.spawn(async move {
+					if let Err(why) = pipeline::update(day, fetcher, pool).await {
+						error!("{why}");
+					}
+				});
+
+				counter += 1;
+				last_runs.insert(day, LastRun {
+					at: now,
+					local: local_now,
+					jitter: Self::sample_jitter(&plan),
+				});
+			}
+			debug!("Scheduler started {counter} fetches so far");
+
+			if next_digest.is_some_and(|next_digest| next_digest <= now) {
+				let day = Self::digest_day(local_now);
+				info!("Sending the digest of {day}");
+				checks.spawn(notifier::dispatch_digest(day));
+				next_digest = Self::next_digest(&plan, local_now);
+			}
+
+			let wake_at = days
+				.iter()
+				.filter_map(|day| next_run(*day, &last_runs))
+				.chain(next_digest)
+				.chain(systemd::watchdog_deadline(now))
+				.min()
+				.unwrap_or(now + plan.interval)
+				.min(now + plan.interval);
+
+			tokio::select! {
+				() = tokio::time::sleep_until(wake_at) => {}
+				() = self.reloaded.notified() => {
+					info!("The schedule was reloaded");
+					next_digest = Self::next_digest(&self.plan.load(), Local::now());
+				}
+				Some(result) = checks.join_next() => {
+					if let Err(why) = result {
+						error!("A fetch panicked: {why}");
+					}
+				}
+				_ = shutdown.changed() => break,
+			}
+		}
+
+		info!("Stopping scheduler, waiting for {} running fetches...", checks.len());
+		while checks.join_next().await.is_some() {}
+		info!("Scheduler stopped");
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use async_trait::async_trait;
+	use bytes::Bytes;
+	use chrono::TimeZone;
+	use super::*;
+
+	struct NoFetcher;
+
+	#[async_trait]
+	impl PdfFetcher for NoFetcher {
+		async fn fetch(&self, _day: Schoolday) -> Result<Bytes, Box<dyn std::error::Error + Send + Sync>> {
+			Err("not fetching in tests".into())
+		}
+	}
+
+	fn plan(config: &ScheduleConfig) -> Plan {
+		Plan::new(config, Arc::new(NoFetcher)).unwrap()
+	}
+
+	/// 2022-05-13 was a Friday.
+	fn friday_at(hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+		Local.with_ymd_and_hms(2022, 5, 13, hour, minute, second).unwrap()
+	}
+
+	fn strings(expressions: &[&str]) -> Vec<String> {
+		expressions.iter().map(ToString::to_string).collect()
+	}
+
+	#[test]
+	fn settings_of_single_days_take_precedence() {
+		let config = ScheduleConfig {
+			interval_secs: 20,
+			day_interval_secs: HashMap::from([(Schoolday::Tuesday, 60), (Schoolday::Wednesday, 90)]),
+			cron: strings(&["0 0 7 * * * *"]),
+			day_cron: HashMap::from([(Schoolday::Wednesday, strings(&["0 30 6 * * * *"])), (Schoolday::Thursday, Vec::new())]),
+			..ScheduleConfig::default()
+		};
+
+		let cadences = Scheduler::cadences(&config).unwrap();
+
+		assert!(matches!(cadences[&Schoolday::Monday], Cadence::Cron(ref schedules) if schedules.len() == 1));
+		assert!(matches!(cadences[&Schoolday::Tuesday], Cadence::Interval(interval) if interval == Duration::from_secs(60)));
+		assert!(matches!(cadences[&Schoolday::Wednesday], Cadence::Cron(ref schedules) if schedules[0].to_string() == "0 30 6 * * * *"));
+		// An empty list of expressions falls back to the settings of all days.
+		assert!(matches!(cadences[&Schoolday::Thursday], Cadence::Cron(ref schedules) if schedules[0].to_string() == "0 0 7 * * * *"));
+	}
+
+	#[test]
+	fn the_interval_applies_without_cron_expressions() {
+		let cadences = Scheduler::cadences(&ScheduleConfig::default()).unwrap();
+
+		assert_eq!(cadences.len(), Schoolday::ALL.len());
+		assert!(cadences.values().all(|cadence| matches!(cadence, Cadence::Interval(interval) if *interval == Duration::from_secs(20))));
+	}
+
+	#[test]
+	fn invalid_cron_expressions_are_rejected() {
+		let config = ScheduleConfig {
+			day_cron: HashMap::from([(Schoolday::Monday, strings(&["every morning"]))]),
+			..ScheduleConfig::default()
+		};
+
+		assert!(Scheduler::cadences(&config).is_err());
+	}
+
+	#[test]
+	fn weekends_fetch_the_coming_week() {
+		assert_eq!(Scheduler::days_to_fetch(friday_at(12, 0, 0)), [Schoolday::Friday, Schoolday::Monday]);
+		assert_eq!(Scheduler::days_to_fetch(friday_at(12, 0, 0) + chrono::Duration::days(1)), [Schoolday::Monday, Schoolday::Tuesday]);
+		assert_eq!(Scheduler::days_to_fetch(friday_at(12, 0, 0) + chrono::Duration::days(2)), [Schoolday::Monday, Schoolday::Tuesday]);
+		assert_eq!(Scheduler::days_to_fetch(friday_at(0, 0, 0) + chrono::Duration::days(3)), [Schoolday::Monday, Schoolday::Tuesday]);
+	}
+
+	#[test]
+	fn the_digest_of_friday_is_about_monday() {
+		assert_eq!(Scheduler::digest_day(friday_at(18, 0, 0)), Schoolday::Monday);
+		assert_eq!(Scheduler::digest_day(friday_at(18, 0, 0) - chrono::Duration::days(1)), Schoolday::Friday);
+	}
+
+	#[test]
+	fn intervals_count_from_the_last_run() {
+		let plan = plan(&ScheduleConfig::default());
+		let last_run = Instant::now();
+
+		let next_run = Scheduler::next_run(&plan, Schoolday::Friday, last_run, friday_at(12, 0, 0), Duration::from_secs(5));
+
+		assert_eq!(next_run, last_run + Duration::from_secs(25));
+	}
+
+	#[test]
+	fn cron_runs_at_the_earliest_upcoming_time() {
+		let plan = plan(&ScheduleConfig {
+			cron: strings(&["0 0 7 * * * *", "0 0 13 * * * *"]),
+			..ScheduleConfig::default()
+		});
+		let last_run = Instant::now();
+		let next_run = |now| Scheduler::next_run(&plan, Schoolday::Friday, last_run, now, Duration::ZERO) - last_run;
+
+		assert_eq!(next_run(friday_at(6, 59, 59)), Duration::from_secs(1));
+		// A fetch right at a time of an expression waits for the next one.
+		assert_eq!(next_run(friday_at(7, 0, 0)), Duration::from_secs(6 * 60 * 60));
+		assert_eq!(next_run(friday_at(13, 0, 0)), Duration::from_secs(18 * 60 * 60));
+	}
+
+	#[test]
+	fn cron_without_upcoming_times_never_runs() {
+		let plan = plan(&ScheduleConfig {
+			cron: strings(&["0 0 7 1 1 * 2000"]),
+			..ScheduleConfig::default()
+		});
+		let last_run = Instant::now();
+
+		let next_run = Scheduler::next_run(&plan, Schoolday::Friday, last_run, friday_at(12, 0, 0), Duration::ZERO);
+
+		assert_eq!(next_run - last_run, Duration::from_secs(60 * 60 * 24 * 365));
+	}
+
+	#[test]
+	fn jitter_stays_within_its_bounds() {
+		assert_eq!(Scheduler::sample_jitter(&plan(&ScheduleConfig::default())), Duration::ZERO);
+
+		let plan = plan(&ScheduleConfig {
+			jitter_secs: 3,
+			..ScheduleConfig::default()
+		});
+		for _ in 0..1000 {
+			assert!(Scheduler::sample_jitter(&plan) <= Duration::from_secs(3));
+		}
+	}
+}
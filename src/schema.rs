@@ -0,0 +1,32 @@
+use lazy_static::lazy_static;
+use schemars::schema_for;
+use substitution_pdf_to_json::SubstitutionSchedule;
+
+lazy_static! {
+	/// The JSON Schema of the documents served per day.
+	pub static ref SCHEDULE_SCHEMA: serde_json::Value = serde_json::to_value(schema_for!(SubstitutionSchedule))
+		.expect("Couldn't serialize the schedule schema");
+}
+
+#[cfg(debug_assertions)]
+lazy_static! {
+	static ref SCHEDULE_VALIDATOR: jsonschema::Validator = jsonschema::validator_for(&SCHEDULE_SCHEMA)
+		.expect("The schedule schema is not a valid JSON Schema");
+}
+
+/// Checks a json document about to be served against the schema and logs every violation.
+/// Only done in debug builds, to catch the schema and the serialization drifting apart during development.
+#[cfg(debug_assertions)]
+pub fn validate_schedule(json: &str) {
+	let value: serde_json::Value = match serde_json::from_str(json) {
+		Ok(value) => value,
+		Err(why) => {
+			tracing::error!("Outgoing schedule is not valid json: {why}");
+			return;
+		}
+	};
+
+	for violation in SCHEDULE_VALIDATOR.iter_errors(&value) {
+		tracing::error!("Outgoing schedule violates the schema at {}: {violation}", violation.instance_path);
+	}
+}
@@ -0,0 +1,17 @@
+use actix_web::{get, HttpResponse, Responder};
+use crate::schema::SCHEDULE_SCHEMA;
+
+#[get("/schema.json")]
+pub async fn get_schema() -> impl Responder {
+	HttpResponse::Ok()
+		.content_type("application/schema+json")
+		.json(&*SCHEDULE_SCHEMA)
+}
+
+/// The protobuf definition of the `application/x-protobuf` responses, for generating client code.
+#[get("/schema.proto")]
+pub async fn get_proto_schema() -> impl Responder {
+	HttpResponse::Ok()
+		.content_type("text/plain; charset=utf-8")
+		.body(include_str!("../proto/schedule.proto"))
+}
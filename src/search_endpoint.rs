@@ -0,0 +1,191 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use actix_web::{get, HttpResponse, Responder, web};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::error;
+use substitution_pdf_to_json::{ClassName, SubstitutionSchedule};
+use crate::Schoolday;
+use crate::stateless::served_schedule;
+use crate::storage::{self, Pools};
+use crate::util::local_midnight;
+
+/// The maximum number of matches read from the history.
+const HISTORY_SEARCH_LIMIT: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+	q: String,
+	/// The first date to search, inclusive.
+	from: Option<NaiveDate>,
+	/// The last date to search, inclusive.
+	to: Option<NaiveDate>,
+}
+
+/// A block whose text contains the search term.
+#[derive(Debug, Serialize, PartialEq, Eq, Hash)]
+struct SearchHit {
+	/// The issue date of the pdf, in milliseconds since the unix epoch.
+	pdf_issue_date: i64,
+	day: Schoolday,
+	class: ClassName,
+	block: usize,
+	text: String,
+}
+
+/// Searches the block texts of the current schedules and of all schedules in the database, ignoring case.
+/// The hits are ordered by date, newest first.
+#[get("/search")]
+pub async fn get_search(query: web::Query<SearchQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let query = query.into_inner();
+	if !query.q.chars().any(char::is_alphanumeric) {
+		return HttpResponse::BadRequest().body("The search term must contain at least one letter or digit");
+	}
+
+	let from = query.from.and_then(local_midnight);
+	let to = query.to.and_then(|date| local_midnight(date + Duration::days(1)));
+	let in_range = |date: i64| from.is_none_or(|from| date >= from) && to.is_none_or(|to| date < to);
+
+	let mut hits = match search_current(&query.q, &pools.read).await {
+		Ok(hits) => hits,
+		Err(why) => {
+			error!("Couldn't search the current schedules: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+	hits.retain(|hit| in_range(hit.pdf_issue_date));
+
+	match search_history(&query.q, from, to, &pools.read).await {
+		Ok(history) => {
+			// The current schedules are stored in the database as well.
+			let mut seen: HashSet<_> = hits.iter()
+				.map(|hit| (hit.pdf_issue_date, hit.class.clone(), hit.block))
+				.collect();
+			hits.extend(history.into_iter().filter(|hit| seen.insert((hit.pdf_issue_date, hit.class.clone(), hit.block))));
+		}
+		Err(why) => {
+			error!("Couldn't search the history: {why}");
+		}
+	}
+
+	hits.sort_by(|a, b| b.pdf_issue_date.cmp(&a.pdf_issue_date)
+		.then_with(|| a.class.cmp(&b.class))
+		.then_with(|| a.block.cmp(&b.block)));
+
+	HttpResponse::Ok().json(hits)
+}
+
+async fn search_current(term: &str, pool: &PgPool) -> Result<Vec<SearchHit>, Box<dyn std::error::Error + Send + Sync>> {
+	let term = term.to_lowercase();
+	let mut hits = Vec::new();
+
+	for day in Schoolday::ALL {
+		if let Some(schedule) = served_schedule(day, pool).await? {
+			search_schedule(&schedule, schedule.pdf_issue_date, day, &term, &mut hits);
+		}
+	}
+
+	Ok(hits)
+}
+
+/// Adds the blocks of `schedule` containing the lowercase `term` to `hits`.
+fn search_schedule(schedule: &SubstitutionSchedule, pdf_issue_date: i64, day: Schoolday, term: &str, hits: &mut Vec<SearchHit>) {
+	for (class, column) in schedule.entries() {
+		for (block, text) in column.blocks() {
+			if text.to_lowercase().contains(term) {
+				hits.push(SearchHit {
+					pdf_issue_date,
+					day,
+					class: class.clone(),
+					block,
+					text: text.to_string(),
+				});
+			}
+		}
+	}
+}
+
+/// Searches the stored schedules.
+/// The trigram index over the block texts narrows the rows down to those containing `term`,
+/// the blocks themselves are matched like the current schedules.
+/// Schedules superseded on the same date are stored as patches, the newest of them are rebuilt and searched like the current schedules.
+async fn search_history(term: &str, from: Option<i64>, to: Option<i64>, pool: &PgPool) -> Result<Vec<SearchHit>, Box<dyn std::error::Error>> {
+	let pattern = format!("%{}%", term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+	let from = from.and_then(|from| Local.timestamp_millis_opt(from).single()).map(|from| from.naive_utc());
+	let to = to.and_then(|to| Local.timestamp_millis_opt(to).single()).map(|to| to.naive_utc());
+
+	let rows = sqlx::query!(
+		r#"
+		SELECT pdf_date, day, class.key AS "class!", block.key AS "block!", block.value AS "text!"
+		FROM substitution_json,
+			jsonb_each(json -> 'entries') AS class,
+			jsonb_each_text(class.value) AS block
+		WHERE block_texts(json -> 'entries') ILIKE $1
+			AND block.value ILIKE $1
+			AND ($2::timestamp IS NULL OR pdf_date >= $2)
+			AND ($3::timestamp IS NULL OR pdf_date < $3)
+		ORDER BY pdf_date DESC
+		LIMIT $4
+		"#,
+		pattern,
+		from,
+		to,
+		HISTORY_SEARCH_LIMIT
+	)
+		.fetch_all(pool)
+		.await?;
+
+	let mut hits: Vec<_> = rows.into_iter()
+		.filter_map(|row| {
+			let pdf_date = Local.from_utc_datetime(&row.pdf_date);
+			Some(SearchHit {
+				pdf_issue_date: pdf_date.timestamp_millis(),
+				day: stored_day(row.day, &pdf_date),
+				class: ClassName::from(row.class),
+				block: row.block.parse().ok()?,
+				text: row.text,
+			})
+		})
+		.collect();
+
+	let patched = sqlx::query!(
+		r#"
+		SELECT hash AS "hash!", pdf_date, day
+		FROM substitution_json
+		WHERE json IS NULL AND patch IS NOT NULL AND hash IS NOT NULL
+			AND ($1::timestamp IS NULL OR pdf_date >= $1)
+			AND ($2::timestamp IS NULL OR pdf_date < $2)
+		ORDER BY pdf_date DESC
+		LIMIT $3
+		"#,
+		from,
+		to,
+		HISTORY_SEARCH_LIMIT
+	)
+		.fetch_all(pool)
+		.await?;
+
+	let term = term.to_lowercase();
+	for row in patched {
+		let schedule: SubstitutionSchedule = match storage::load_json(pool, &row.hash).await? {
+			Some(json) => serde_json::from_value(json)?,
+			None => continue,
+		};
+		let pdf_date = Local.from_utc_datetime(&row.pdf_date);
+		search_schedule(&schedule, pdf_date.timestamp_millis(), stored_day(row.day, &pdf_date), &term, &mut hits);
+	}
+
+	hits.sort_by_key(|hit| Reverse(hit.pdf_issue_date));
+	#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+	hits.truncate(HISTORY_SEARCH_LIMIT as usize);
+
+	Ok(hits)
+}
+
+/// The day a row was stored for, rows stored before the day was recorded fall back to the weekday of their pdf.
+fn stored_day(day: Option<i16>, pdf_date: &DateTime<Local>) -> Schoolday {
+	day.and_then(|day| usize::try_from(day).ok())
+		.and_then(|day| Schoolday::ALL.get(day).copied())
+		.unwrap_or_else(|| Schoolday::from(pdf_date.weekday()))
+}
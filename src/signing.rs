@@ -0,0 +1,62 @@
+use actix_web::body::{BoxBody, MessageBody, to_bytes};
+use actix_web::dev::ServiceResponse;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use crate::CONFIG;
+
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Adds an `X-Signature` header with the HMAC-SHA256 of the body, if a signing key is configured.
+/// The body has to be buffered for this, so responses are only streamed while signing is disabled.
+/// Compressed bodies have to be signed before they are compressed, as clients verify the decoded body,
+/// so responses with a `Content-Encoding` are left as they are.
+///
+/// # Errors
+///
+/// Returns `Err` if the body couldn't be read.
+pub async fn sign_response<B>(res: ServiceResponse<B>) -> Result<ServiceResponse<BoxBody>, actix_web::Error>
+	where B: MessageBody + 'static {
+	if CONFIG.signing_key.is_none() || res.headers().contains_key(header::CONTENT_ENCODING) {
+		return Ok(res.map_into_boxed_body());
+	}
+
+	let (req, res) = res.into_parts();
+	let (res, body) = res.into_parts();
+	let body = to_bytes(body).await.map_err(|why| ErrorInternalServerError(why.into().to_string()))?;
+	let signature = signature(&body).expect("The signing key was checked");
+
+	let mut res = res.set_body(body);
+	res.headers_mut().insert(
+		HeaderName::from_static(SIGNATURE_HEADER),
+		HeaderValue::from_str(&signature).expect("A hex string is a valid header value"),
+	);
+
+	Ok(ServiceResponse::new(req, res.map_into_boxed_body()))
+}
+
+/// The `X-Signature` of `body`, `None` if signing is disabled.
+pub fn signature(body: &[u8]) -> Option<String> {
+	CONFIG.signing_key.as_ref().map(|key| sign(key, body))
+}
+
+fn sign(key: &str, body: &[u8]) -> String {
+	let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+	mac.update(body);
+	format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn signatures_are_hmac_sha256() {
+		// Test case 2 of RFC 4231.
+		assert_eq!(
+			sign("Jefe", b"what do ya want for nothing?"),
+			"sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+		);
+	}
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use substitution_pdf_to_json::SubstitutionSchedule;
+use tokio::sync::RwLock;
+use crate::{CONFIG, JSON_HANDLER, Schoolday, storage};
+use crate::json_handler::{ServedPdf, source_hash, VersionedJson};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+lazy_static! {
+	static ref DB_SCHEDULES: DbSchedules = DbSchedules::new(Duration::from_secs(CONFIG.stateless.cache_secs));
+}
+
+/// The latest schedule of a day as stored in the database, with its prerendered encodings.
+#[derive(Debug)]
+struct StoredDay {
+	hash: String,
+	json: Bytes,
+	msgpack: Bytes,
+	schedule: Arc<SubstitutionSchedule>,
+	/// The schedule stored before it, for the diffs.
+	previous: Option<Arc<SubstitutionSchedule>>,
+}
+
+/// What the database returned for a day, `stored` is `None` if it had no schedule yet.
+struct CacheEntry {
+	read_at: Instant,
+	stored: Option<Arc<StoredDay>>,
+}
+
+/// Caches the latest schedules read from the database for a short time, so not every request hits it.
+struct DbSchedules {
+	max_age: Duration,
+	days: RwLock<HashMap<Schoolday, CacheEntry>>,
+}
+
+impl DbSchedules {
+	fn new(max_age: Duration) -> Self {
+		Self {
+			max_age,
+			days: RwLock::new(HashMap::new()),
+		}
+	}
+
+	async fn get(&self, day: Schoolday, pool: &PgPool) -> Result<Option<Arc<StoredDay>>, BoxError> {
+		{
+			let days = self.days.read().await;
+			if let Some(entry) = days.get(&day) {
+				if entry.read_at.elapsed() < self.max_age {
+					return Ok(entry.stored.clone());
+				}
+			}
+		}
+
+		let stored = load_latest(day, pool).await?.map(Arc::new);
+		let mut days = self.days.write().await;
+		days.insert(day, CacheEntry {
+			read_at: Instant::now(),
+			stored: stored.clone(),
+		});
+
+		Ok(stored)
+	}
+
+	/// Reads `day` from the database on its next request.
+	async fn forget(&self, day: Schoolday) {
+		let _ = self.days.write().await.remove(&day);
+	}
+}
+
+async fn load_latest(day: Schoolday, pool: &PgPool) -> Result<Option<StoredDay>, BoxError> {
+	let rows = sqlx::query!(
+		r#"
+		SELECT hash, json
+		FROM substitution_json
+		WHERE day = $1
+		ORDER BY pdf_date DESC, insertion_time DESC
+		LIMIT 2
+		"#,
+		day as i16
+	)
+		.fetch_all(pool)
+		.await?;
+	let mut rows = rows.into_iter().filter_map(|row| Some((row.hash?, row.json)));
+
+	let (hash, schedule) = match rows.next() {
+		Some((hash, json)) => match load_schedule(day, &hash, json, pool).await? {
+			Some(schedule) => (hash, schedule),
+			None => return Ok(None),
+		},
+		None => return Ok(None),
+	};
+	let previous = match rows.next() {
+		Some((previous_hash, json)) => load_schedule(day, &previous_hash, json, pool).await?.map(Arc::new),
+		None => None,
+	};
+
+	Ok(Some(StoredDay {
+		hash,
+		json: Bytes::from(serde_json::to_vec(&schedule)?),
+		msgpack: Bytes::from(rmp_serde::to_vec_named(&schedule)?),
+		schedule: Arc::new(schedule),
+		previous,
+	}))
+}
+
+/// Parses the schedule of a row, rebuilding it from its patches if it wasn't stored in full.
+async fn load_schedule(day: Schoolday, hash: &str, json: Option<serde_json::Value>, pool: &PgPool) -> Result<Option<SubstitutionSchedule>, BoxError> {
+	let json = match json {
+		Some(json) => json,
+		None => match storage::load_json(pool, hash).await? {
+			Some(json) => json,
+			None => return Ok(None),
+		},
+	};
+
+	let mut schedule: SubstitutionSchedule = serde_json::from_value(json)?;
+	// Rows stored before effective dates existed don't have one.
+	if schedule.effective_date().is_none() {
+		schedule.assign_day(day);
+	}
+	Ok(Some(schedule))
+}
+
+/// Makes this instance read `day` from the database again, once it served a new schedule of it.
+pub async fn forget(day: Schoolday) {
+	if CONFIG.stateless.enabled {
+		DB_SCHEDULES.forget(day).await;
+	}
+}
+
+/// The served json of `day` and the hash of the stored schedule it is, read from the database in stateless mode.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read in stateless mode.
+pub async fn served_versioned_json(day: Schoolday, pool: &PgPool) -> Result<Option<VersionedJson>, BoxError> {
+	if !CONFIG.stateless.enabled {
+		return Ok(JSON_HANDLER.get_versioned_json(day));
+	}
+
+	Ok(DB_SCHEDULES.get(day, pool).await?.map(|stored| VersionedJson {
+		hash: stored.hash.clone(),
+		json: stored.json.clone(),
+	}))
+}
+
+/// The served json of `day`, read from the database in stateless mode.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read in stateless mode.
+pub async fn served_json(day: Schoolday, pool: &PgPool) -> Result<Option<Bytes>, BoxError> {
+	if !CONFIG.stateless.enabled {
+		return Ok(JSON_HANDLER.get_json(day));
+	}
+
+	Ok(DB_SCHEDULES.get(day, pool).await?.map(|stored| stored.json.clone()))
+}
+
+/// The served msgpack of `day`, read from the database in stateless mode.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read in stateless mode.
+pub async fn served_msgpack(day: Schoolday, pool: &PgPool) -> Result<Option<Bytes>, BoxError> {
+	if !CONFIG.stateless.enabled {
+		return Ok(JSON_HANDLER.get_msgpack(day));
+	}
+
+	Ok(DB_SCHEDULES.get(day, pool).await?.map(|stored| stored.msgpack.clone()))
+}
+
+/// The served schedule of `day`, read from the database in stateless mode.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read in stateless mode.
+pub async fn served_schedule(day: Schoolday, pool: &PgPool) -> Result<Option<Arc<SubstitutionSchedule>>, BoxError> {
+	if !CONFIG.stateless.enabled {
+		return Ok(JSON_HANDLER.get_schedule(day));
+	}
+
+	Ok(DB_SCHEDULES.get(day, pool).await?.map(|stored| stored.schedule.clone()))
+}
+
+/// The schedule `day` served before its substitutions last changed, read from the database in stateless mode.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read in stateless mode.
+pub async fn served_previous(day: Schoolday, pool: &PgPool) -> Result<Option<Arc<SubstitutionSchedule>>, BoxError> {
+	if !CONFIG.stateless.enabled {
+		return Ok(JSON_HANDLER.get_previous(day));
+	}
+
+	Ok(DB_SCHEDULES.get(day, pool).await?.and_then(|stored| stored.previous.clone()))
+}
+
+/// The pdf the served schedule of `day` was converted from.
+/// Only the instance that downloaded it has it in memory, the others read it from the database.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read.
+pub async fn served_pdf(day: Schoolday, pool: &PgPool) -> Result<Option<ServedPdf>, BoxError> {
+	if !CONFIG.stateless.enabled {
+		if let Some(pdf) = JSON_HANDLER.get_pdf(day) {
+			return Ok(Some(pdf));
+		}
+	}
+
+	// Days covered by the pdf of another day are stored without it.
+	let hash = match served_versioned_json(day, pool).await? {
+		Some(versioned) => source_hash(&versioned.hash).to_string(),
+		None => return Ok(None),
+	};
+	Ok(storage::load_pdf(pool, &hash).await?.map(|bytes| ServedPdf {
+		hash,
+		bytes: Bytes::from(bytes),
+	}))
+}
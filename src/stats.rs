@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use substitution_pdf_to_json::{CANCELLATION_MARKERS, ClassName, SubstitutionColumn, SubstitutionSchedule};
+use crate::storage;
+use crate::util::local_midnight;
+
+/// The substitutions and cancellations of a class over a period.
+#[derive(Debug, Serialize)]
+pub struct ClassStats {
+	pub class: ClassName,
+	/// Every line of the schedule, cancellations included.
+	pub substitutions: usize,
+	pub cancellations: usize,
+	/// The days the class had at least one line on.
+	pub days: usize,
+}
+
+/// How often a teacher was mentioned in the schedules of a period.
+#[derive(Debug, Serialize)]
+pub struct TeacherStats {
+	pub teacher: String,
+	/// The lines mentioning the teacher, whether as the absent or the substituting one.
+	pub substitutions: usize,
+	/// The lines mentioning the teacher that cancel the lesson, which usually means the teacher was absent.
+	pub cancellations: usize,
+	/// The days the teacher was mentioned on.
+	pub days: usize,
+}
+
+/// The length of the periods [`timeline`] sums up.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+	#[default]
+	Day,
+	/// Weeks from Monday to Sunday.
+	Week,
+}
+
+/// The lines of the schedules within a bucket.
+#[derive(Debug, Serialize)]
+pub struct TimelinePoint {
+	/// The first date of the bucket.
+	pub start: NaiveDate,
+	/// The dates within the bucket a schedule was stored for.
+	pub days: usize,
+	pub substitutions: i64,
+	pub cancellations: i64,
+}
+
+impl Bucket {
+	fn start(self, date: NaiveDate) -> NaiveDate {
+		match self {
+			Self::Day => date,
+			Self::Week => date - Duration::days(i64::from(date.weekday().num_days_from_monday())),
+		}
+	}
+}
+
+/// The last schedule stored for every date from `from` to `to`, both inclusive, ordered by date.
+/// Re-issued pdfs replace the earlier versions of their date, so every date is counted once.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read or a stored schedule couldn't be parsed.
+pub async fn latest_schedules(from: NaiveDate, to: NaiveDate, pool: &PgPool) -> Result<BTreeMap<NaiveDate, SubstitutionSchedule>, Box<dyn std::error::Error>> {
+	let (start, end) = match period_bounds(from, to) {
+		Some(bounds) => bounds,
+		None => return Ok(BTreeMap::new()),
+	};
+
+	let rows = sqlx::query!(
+		r#"
+		SELECT DISTINCT ON (pdf_date) pdf_date, hash AS "hash!", json
+		FROM substitution_json
+		WHERE pdf_date >= $1 AND pdf_date < $2 AND hash IS NOT NULL
+		ORDER BY pdf_date, insertion_time DESC NULLS LAST
+		"#,
+		start,
+		end
+	)
+		.fetch_all(pool)
+		.await?;
+
+	// Issue times of the same date can differ between versions, the later ones win.
+	let mut latest = BTreeMap::new();
+	for row in rows {
+		latest.insert(Local.from_utc_datetime(&row.pdf_date).date_naive(), (row.hash, row.json));
+	}
+
+	let mut schedules = BTreeMap::new();
+	for (date, (hash, json)) in latest {
+		// Stored as a patch of a later schedule.
+		let json = match json {
+			Some(json) => json,
+			None => storage::load_json(pool, &hash).await?.ok_or_else(|| format!("The schedule {hash} disappeared"))?,
+		};
+		schedules.insert(date, serde_json::from_value(json)?);
+	}
+
+	Ok(schedules)
+}
+
+/// Counts the lines and cancellations of the last schedule stored for every date from `from` to `to`, in the database,
+/// and sums them up per bucket. Buckets without any schedule are left out.
+/// Schedules stored as patches can't be counted by the database, they are rebuilt and counted here.
+///
+/// # Errors
+///
+/// Returns `Err` if the database couldn't be read or a schedule stored as a patch couldn't be rebuilt.
+pub async fn timeline(from: NaiveDate, to: NaiveDate, bucket: Bucket, pool: &PgPool) -> Result<Vec<TimelinePoint>, Box<dyn std::error::Error>> {
+	let (start, end) = match period_bounds(from, to) {
+		Some(bounds) => bounds,
+		None => return Ok(Vec::new()),
+	};
+
+	let rows = sqlx::query!(
+		r#"
+		SELECT latest.pdf_date AS "pdf_date!", latest.hash AS "hash!", latest.json IS NULL AS "patched!",
+			count(lines.line) AS "substitutions!",
+			count(lines.line) FILTER (WHERE lines.line ~* $3) AS "cancellations!"
+		FROM (
+			SELECT DISTINCT ON (pdf_date) pdf_date, hash, json
+			FROM substitution_json
+			WHERE pdf_date >= $1 AND pdf_date < $2 AND hash IS NOT NULL
+			ORDER BY pdf_date, insertion_time DESC NULLS LAST
+		) AS latest
+		LEFT JOIN LATERAL (
+			SELECT btrim(line) AS line
+			FROM jsonb_each(latest.json -> 'entries') AS class,
+				jsonb_each_text(class.value) AS block,
+				regexp_split_to_table(block.value, '\n') AS line
+			WHERE btrim(line) <> ''
+		) AS lines ON true
+		GROUP BY latest.pdf_date, latest.hash, latest.json IS NULL
+		ORDER BY latest.pdf_date
+		"#,
+		start,
+		end,
+		cancellation_pattern()
+	)
+		.fetch_all(pool)
+		.await?;
+
+	// Like `latest_schedules`, the last version of a date wins.
+	let mut latest = BTreeMap::new();
+	for row in rows {
+		latest.insert(Local.from_utc_datetime(&row.pdf_date).date_naive(), row);
+	}
+
+	let mut points: BTreeMap<NaiveDate, TimelinePoint> = BTreeMap::new();
+	for (date, row) in latest {
+		let (substitutions, cancellations) = if row.patched {
+			let json = storage::load_json(pool, &row.hash).await?.ok_or_else(|| format!("The schedule {} disappeared", row.hash))?;
+			count_lines(&serde_json::from_value(json)?)
+		} else {
+			(row.substitutions, row.cancellations)
+		};
+
+		let start = bucket.start(date);
+		let point = points.entry(start).or_insert(TimelinePoint {
+			start,
+			days: 0,
+			substitutions: 0,
+			cancellations: 0,
+		});
+		point.days += 1;
+		point.substitutions += substitutions;
+		point.cancellations += cancellations;
+	}
+
+	Ok(points.into_values().collect())
+}
+
+/// Counts the lines and cancellations of a schedule the way [`timeline`] lets the database count them.
+fn count_lines(schedule: &SubstitutionSchedule) -> (i64, i64) {
+	let entries: Vec<_> = schedule.entries()
+		.values()
+		.flat_map(SubstitutionColumn::block_entries)
+		.collect();
+	let cancellations = entries.iter().filter(|entry| entry.cancelled).count();
+
+	#[allow(clippy::cast_possible_wrap)]
+	(entries.len() as i64, cancellations as i64)
+}
+
+/// Counts the lines and cancellations of every class, ordered by class name.
+pub fn class_stats<'a>(schedules: impl IntoIterator<Item = &'a SubstitutionSchedule>) -> Vec<ClassStats> {
+	let mut stats: BTreeMap<ClassName, ClassStats> = BTreeMap::new();
+
+	for schedule in schedules {
+		for (class, column) in schedule.entries() {
+			let entries = column.block_entries();
+			if entries.is_empty() {
+				continue;
+			}
+
+			let class_stats = stats.entry(class.clone()).or_insert_with(|| ClassStats {
+				class: class.clone(),
+				substitutions: 0,
+				cancellations: 0,
+				days: 0,
+			});
+			class_stats.days += 1;
+			class_stats.substitutions += entries.len();
+			class_stats.cancellations += entries.iter().filter(|entry| entry.cancelled).count();
+		}
+	}
+
+	stats.into_values().collect()
+}
+
+/// Counts the lines mentioning every teacher, ordered by the number of lines, most first.
+/// A line mentioning a teacher twice counts once.
+pub fn teacher_stats<'a>(schedules: impl IntoIterator<Item = &'a SubstitutionSchedule>) -> Vec<TeacherStats> {
+	let mut stats: HashMap<String, TeacherStats> = HashMap::new();
+
+	for schedule in schedules {
+		let mut mentioned = HashSet::new();
+		for column in schedule.entries().values() {
+			for entry in column.block_entries() {
+				let cancelled = entry.cancelled;
+				let teachers: HashSet<_> = entry.teachers.into_iter().collect();
+				for teacher in teachers {
+					let teacher_stats = stats.entry(teacher.clone()).or_insert_with(|| TeacherStats {
+						teacher: teacher.clone(),
+						substitutions: 0,
+						cancellations: 0,
+						days: 0,
+					});
+					teacher_stats.substitutions += 1;
+					teacher_stats.cancellations += usize::from(cancelled);
+					if mentioned.insert(teacher) {
+						teacher_stats.days += 1;
+					}
+				}
+			}
+		}
+	}
+
+	let mut stats: Vec<_> = stats.into_values().collect();
+	stats.sort_by(|a, b| b.substitutions.cmp(&a.substitutions).then_with(|| a.teacher.cmp(&b.teacher)));
+	stats
+}
+
+/// The start of `from` and the end of `to` in local time, as the database stores them.
+fn period_bounds(from: NaiveDate, to: NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+	let start = local_midnight(from).and_then(|start| Local.timestamp_millis_opt(start).single())?;
+	let end = local_midnight(to + Duration::days(1)).and_then(|end| Local.timestamp_millis_opt(end).single())?;
+	Some((start.naive_utc(), end.naive_utc()))
+}
+
+/// A case insensitive postgres regex matching the lines of cancelled lessons, like [`BlockEntry::cancelled`](substitution_pdf_to_json::BlockEntry::cancelled).
+fn cancellation_pattern() -> String {
+	CANCELLATION_MARKERS.iter()
+		.map(|marker| regex::escape(marker))
+		.collect::<Vec<_>>()
+		.join("|")
+}
@@ -0,0 +1,137 @@
+use actix_web::{get, HttpRequest, HttpResponse, Responder, web};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use crate::admin_endpoint::check_admin;
+use crate::stats::{self, Bucket, ClassStats, TeacherStats, TimelinePoint};
+use crate::storage::Pools;
+
+/// The longest period the statistics are computed over at once.
+const MAX_STATS_DAYS: i64 = 400;
+
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+	/// The first date, inclusive.
+	from: NaiveDate,
+	/// The last date, inclusive.
+	to: NaiveDate,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimelineQuery {
+	from: NaiveDate,
+	to: NaiveDate,
+	/// `day` or `week`, days by default.
+	#[serde(default)]
+	bucket: Bucket,
+}
+
+#[derive(Debug, Serialize)]
+struct ClassStatsResponse {
+	from: NaiveDate,
+	to: NaiveDate,
+	/// The dates a schedule was stored for.
+	days: usize,
+	classes: Vec<ClassStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct TeacherStatsResponse {
+	from: NaiveDate,
+	to: NaiveDate,
+	days: usize,
+	teachers: Vec<TeacherStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct TimelineResponse {
+	from: NaiveDate,
+	to: NaiveDate,
+	bucket: Bucket,
+	points: Vec<TimelinePoint>,
+}
+
+/// Counts the substitutions and cancellations of every class from the stored schedules of the period.
+/// Only the last version of a re-issued pdf is counted.
+#[get("/stats/classes")]
+pub async fn get_class_stats(query: web::Query<StatsQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let StatsQuery { from, to } = query.into_inner();
+	if let Some(response) = check_period(from, to) {
+		return response;
+	}
+
+	match stats::latest_schedules(from, to, &pools.read).await {
+		Ok(schedules) => HttpResponse::Ok().json(ClassStatsResponse {
+			from,
+			to,
+			days: schedules.len(),
+			classes: stats::class_stats(schedules.values()),
+		}),
+		Err(why) => {
+			error!("Couldn't compute the class statistics from {from} to {to}: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// Counts the lines mentioning every teacher in the stored schedules of the period, most mentioned first.
+/// Tells a lot about the absences of single teachers, so it is only available to admins.
+#[get("/stats/teachers")]
+pub async fn get_teacher_stats(req: HttpRequest, query: web::Query<StatsQuery>, pools: web::Data<Pools>) -> impl Responder {
+	if let Some(response) = check_admin(&req) {
+		return response;
+	}
+
+	let StatsQuery { from, to } = query.into_inner();
+	if let Some(response) = check_period(from, to) {
+		return response;
+	}
+
+	match stats::latest_schedules(from, to, &pools.read).await {
+		Ok(schedules) => HttpResponse::Ok().json(TeacherStatsResponse {
+			from,
+			to,
+			days: schedules.len(),
+			teachers: stats::teacher_stats(schedules.values()),
+		}),
+		Err(why) => {
+			error!("Couldn't compute the teacher statistics from {from} to {to}: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// The substitutions and cancellations per day or week of the period, for charts.
+/// Days or weeks without a stored schedule are left out.
+#[get("/stats/timeline")]
+pub async fn get_timeline(query: web::Query<TimelineQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let TimelineQuery { from, to, bucket } = query.into_inner();
+	if let Some(response) = check_period(from, to) {
+		return response;
+	}
+
+	match stats::timeline(from, to, bucket, &pools.read).await {
+		Ok(points) => HttpResponse::Ok().json(TimelineResponse {
+			from,
+			to,
+			bucket,
+			points,
+		}),
+		Err(why) => {
+			error!("Couldn't compute the timeline from {from} to {to}: {why}");
+			HttpResponse::InternalServerError().finish()
+		}
+	}
+}
+
+/// Returns the response to send instead if the period is empty or too long.
+fn check_period(from: NaiveDate, to: NaiveDate) -> Option<HttpResponse> {
+	if from > to {
+		return Some(HttpResponse::BadRequest().body("`from` must not be after `to`"));
+	}
+	if (to - from).num_days() >= MAX_STATS_DAYS {
+		return Some(HttpResponse::BadRequest().body(format!("The period can be at most {MAX_STATS_DAYS} days long")));
+	}
+
+	None
+}
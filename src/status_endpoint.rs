@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use actix_web::{get, HttpResponse, Responder, web};
+use chrono::{Local, TimeZone};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::error;
+use crate::json_handler::DayStatus;
+use crate::stateless::served_versioned_json;
+use crate::storage::{self, Pools};
+use crate::storage::{CIRCUIT_BREAKER, DatabaseStatus};
+use crate::write_queue::WRITE_QUEUE;
+use crate::{JSON_HANDLER, Schoolday};
+
+/// The freshness of a day, combined with a check against the database.
+#[derive(Debug, Serialize)]
+struct DayFreshness {
+	#[serde(flatten)]
+	status: DayStatus,
+	/// Whether the served json comes from the newest database row for its pdf date.
+	/// `None` if this couldn't be determined.
+	matches_db: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+	#[serde(flatten)]
+	days: HashMap<Schoolday, DayFreshness>,
+	database: DatabaseStatus,
+	/// Schedules waiting for the database to become reachable again.
+	queued_writes: usize,
+}
+
+#[get("/status")]
+pub async fn get_status(pools: web::Data<Pools>) -> impl Responder {
+	let statuses = JSON_HANDLER.get_statuses().await;
+	let mut freshness = HashMap::new();
+
+	for (day, status) in statuses {
+		let matches_db = matches_newest_db_row(day, &status, &pools.read).await;
+		freshness.insert(day, DayFreshness {
+			status,
+			matches_db,
+		});
+	}
+
+	HttpResponse::Ok().json(Status {
+		days: freshness,
+		database: CIRCUIT_BREAKER.status(),
+		queued_writes: WRITE_QUEUE.len().await,
+	})
+}
+
+/// Answers with 503 while any day failed to update `failure_threshold` times in a row.
+#[get("/ready")]
+pub async fn get_ready() -> impl Responder {
+	if JSON_HANDLER.is_healthy().await {
+		HttpResponse::Ok().finish()
+	} else {
+		HttpResponse::ServiceUnavailable().finish()
+	}
+}
+
+#[get("/status/errors")]
+pub async fn get_status_errors() -> impl Responder {
+	let errors = JSON_HANDLER.get_errors().await;
+
+	HttpResponse::Ok().json(errors)
+}
+
+/// Checks if the hash of the served json is the newest one stored for the same day and pdf date.
+async fn matches_newest_db_row(day: Schoolday, status: &DayStatus, pool: &PgPool) -> Option<bool> {
+	let hash = match served_versioned_json(day, pool).await {
+		Ok(versioned) => versioned?.hash,
+		Err(why) => {
+			error!("{why}");
+			return None;
+		}
+	};
+	let pdf_date = Local.timestamp_millis_opt(status.pdf_issue_date?).single()?.naive_utc();
+
+	match storage::newest_hash(pool, day, pdf_date).await {
+		Ok(newest_hash) => Some(newest_hash.as_ref() == Some(&hash)),
+		Err(why) => {
+			error!("{why}");
+			None
+		}
+	}
+}
@@ -0,0 +1,547 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, AtomicU32, Ordering};
+use std::time::Duration;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use sqlx::migrate::MigrateError;
+use sqlx::postgres::PgPoolOptions;
+use thiserror::Error;
+use tokio::sync::{RwLock, watch};
+use tracing::{debug, info, warn};
+use crate::{CONFIG, invalidation, METRICS, Schoolday};
+use crate::config::{DatabaseConfig, HashAlgorithm, PoolConfig};
+use crate::util::local_midnight;
+
+/// Connections are replaced after this long, so they don't pile up server side state.
+const MAX_CONNECTION_LIFETIME: Duration = Duration::from_secs(60 * 60 * 12);
+
+/// Attempts of a single database operation before it is given up.
+const MAX_ATTEMPTS: u32 = 4;
+/// The delay before the first retry, doubled for every further one.
+const BASE_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// How many times smaller than the schedule it replaces a patch has to be to be stored instead.
+const MIN_PATCH_SAVING: usize = 2;
+/// Patches applied to rebuild a schedule before the stored chain is considered broken.
+const MAX_PATCH_CHAIN: usize = 1000;
+/// Operations that failed in a row, after all their retries, before the circuit opens.
+const OPEN_AFTER_FAILURES: u32 = 3;
+/// How long the circuit stays open before a single operation is let through again.
+const OPEN_FOR: Duration = Duration::from_secs(30);
+
+lazy_static! {
+	pub static ref CIRCUIT_BREAKER: CircuitBreaker = CircuitBreaker::default();
+	/// Whether the migrations were applied, schedules are only written after that.
+	static ref MIGRATED: watch::Sender<bool> = watch::channel(false).0;
+	/// The pdf date and hash of the row of every day that was inserted last, so `/status` doesn't query them on every request.
+	static ref NEWEST_ROWS: RwLock<HashMap<Schoolday, (NaiveDateTime, String)>> = RwLock::new(HashMap::new());
+}
+
+/// Separate pools for writing and reading, so reads can't take all connections the writer needs.
+#[derive(Debug, Clone)]
+pub struct Pools {
+	pub write: PgPool,
+	pub read: PgPool,
+}
+
+impl Pools {
+	/// Creates the write pool for `url` and the read pool for the configured replica, or `url` as well if there is none.
+	/// Connections are only opened when they are needed, so the server starts while the database is unreachable.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if a url is invalid.
+	pub fn connect_lazy(url: &str, config: &DatabaseConfig) -> Result<Self, sqlx::Error> {
+		let write = connect_pool(url, &config.write_pool)?;
+		let read = match &config.read_url {
+			Some(read_url) => {
+				info!("Reading from a replica");
+				connect_pool(read_url, &config.read_pool)?
+			}
+			None => connect_pool(url, &config.read_pool)?,
+		};
+
+		Ok(Self {
+			write,
+			read,
+		})
+	}
+}
+
+fn connect_pool(url: &str, config: &PoolConfig) -> Result<PgPool, sqlx::Error> {
+	PgPoolOptions::new()
+		.max_lifetime(MAX_CONNECTION_LIFETIME)
+		.min_connections(config.min_connections)
+		.max_connections(config.max_connections)
+		.connect_lazy(url)
+}
+
+/// Applies the migrations and lets the schedules be written.
+///
+/// # Errors
+///
+/// Returns `Err` if a migration couldn't be applied.
+pub async fn migrate(pool: &PgPool) -> Result<(), MigrateError> {
+	sqlx::migrate!().run(pool).await?;
+	MIGRATED.send_replace(true);
+	Ok(())
+}
+
+/// Waits until the migrations were applied.
+pub async fn migrated() {
+	// Only fails once the sender is dropped, which lives as long as the process.
+	let _ = MIGRATED.subscribe().wait_for(|migrated| *migrated).await;
+}
+
+/// Applies the migrations, waiting for the database if it is unreachable.
+pub async fn migrate_when_reachable(pool: PgPool) {
+	loop {
+		match migrate(&pool).await {
+			Ok(()) => {
+				info!("Migrated the database");
+				return;
+			}
+			Err(why) => warn!("Couldn't migrate the database, trying again in {} seconds: {why}", OPEN_FOR.as_secs()),
+		}
+		tokio::time::sleep(OPEN_FOR).await;
+	}
+}
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+	#[error(transparent)]
+	Database(#[from] sqlx::Error),
+	#[error("The database is unavailable, not trying again before {0}")]
+	CircuitOpen(DateTime<Utc>),
+	#[error("The database isn't migrated yet")]
+	NotMigrated,
+}
+
+impl StorageError {
+	/// Whether the database couldn't be reached, as opposed to rejecting the operation.
+	pub fn is_unavailable(&self) -> bool {
+		match self {
+			StorageError::Database(why) => is_transient(why),
+			StorageError::CircuitOpen(_) | StorageError::NotMigrated => true,
+		}
+	}
+}
+
+/// The state of the circuit breaker, served by `/status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+	/// The database works, operations go through.
+	Closed,
+	/// The database failed repeatedly, operations fail immediately.
+	Open,
+	/// The open period is over, the next operation decides whether the circuit closes again.
+	HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseStatus {
+	pub circuit: CircuitState,
+	pub consecutive_failures: u32,
+	/// Until when operations fail immediately, in milliseconds since the epoch.
+	pub open_until: Option<i64>,
+}
+
+/// Stops hammering the database with operations once it failed repeatedly, so they fail fast instead of piling up.
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+	consecutive_failures: AtomicU32,
+	/// Milliseconds since the epoch, 0 while the circuit is closed.
+	open_until: AtomicI64,
+}
+
+impl CircuitBreaker {
+	pub fn state(&self) -> CircuitState {
+		match self.open_until.load(Ordering::Relaxed) {
+			0 => CircuitState::Closed,
+			until if until > Utc::now().timestamp_millis() => CircuitState::Open,
+			_ => CircuitState::HalfOpen,
+		}
+	}
+
+	pub fn status(&self) -> DatabaseStatus {
+		let open_until = self.open_until.load(Ordering::Relaxed);
+
+		DatabaseStatus {
+			circuit: self.state(),
+			consecutive_failures: self.consecutive_failures.load(Ordering::Relaxed),
+			open_until: (open_until != 0).then_some(open_until),
+		}
+	}
+
+	fn succeeded(&self) {
+		self.consecutive_failures.store(0, Ordering::Relaxed);
+		self.open_until.store(0, Ordering::Relaxed);
+	}
+
+	#[allow(clippy::cast_possible_truncation)]
+	fn failed(&self) {
+		let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+
+		if failures >= OPEN_AFTER_FAILURES {
+			let until = Utc::now().timestamp_millis() + OPEN_FOR.as_millis() as i64;
+			self.open_until.store(until, Ordering::Relaxed);
+			warn!("The database failed {failures} times in a row, opening the circuit for {} seconds", OPEN_FOR.as_secs());
+		}
+	}
+}
+
+/// Runs a database operation, retrying transient failures with an exponential backoff.
+/// Fails immediately while the circuit is open.
+pub async fn with_retry<T, F, Fut>(mut operation: F) -> Result<T, StorageError>
+	where F: FnMut() -> Fut,
+	      Fut: Future<Output=Result<T, sqlx::Error>> {
+	if CIRCUIT_BREAKER.state() == CircuitState::Open {
+		let until = CIRCUIT_BREAKER.open_until.load(Ordering::Relaxed);
+		return Err(StorageError::CircuitOpen(Utc.timestamp_millis_opt(until).unwrap()));
+	}
+
+	let mut delay = BASE_RETRY_DELAY;
+	for attempt in 1..=MAX_ATTEMPTS {
+		match operation().await {
+			Ok(value) => {
+				CIRCUIT_BREAKER.succeeded();
+				return Ok(value);
+			}
+			Err(why) if is_transient(&why) && attempt < MAX_ATTEMPTS => {
+				warn!("Database operation failed on attempt {attempt}, retrying in {delay:?}: {why}");
+				tokio::time::sleep(delay).await;
+				delay *= 2;
+			}
+			Err(why) => {
+				if is_transient(&why) {
+					CIRCUIT_BREAKER.failed();
+				}
+				return Err(StorageError::Database(why));
+			}
+		}
+	}
+
+	unreachable!("The last attempt always returns")
+}
+
+/// Whether the error is about reaching the database rather than about the query.
+pub fn is_transient(why: &sqlx::Error) -> bool {
+	match why {
+		sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::Tls(_) => true,
+		// Class 08 are connection exceptions, 57P01 to 57P03 mean the server is shutting down or starting.
+		sqlx::Error::Database(why) => why.code().is_some_and(|code| code.starts_with("08") || code.starts_with("57P")),
+		_ => false,
+	}
+}
+
+/// Writes the schedule of a pdf and completes its conversion job in one transaction,
+/// so a job can't be dropped without its schedule or linger after it was stored.
+/// A row with the same hash is the same pdf and is kept as it is, returns whether a new row was written.
+/// The `pdf` is stored next to the schedule, further days of a pdf are stored without it.
+/// If `notify` is set, the other instances are told to serve the schedule for `day`.
+/// Duplicates, e.g. after a restart or from another instance, are counted in the metrics.
+pub async fn save_schedule(pool: &PgPool, day: Schoolday, hash: &str, pdf_date: &DateTime<Local>, json: serde_json::Value, pdf: Option<&[u8]>, notify: bool) -> Result<bool, StorageError> {
+	// The row would miss columns, it is queued until the migrations ran instead.
+	if !*MIGRATED.borrow() {
+		return Err(StorageError::NotMigrated);
+	}
+
+	let inserted = with_retry(|| save_schedule_once(pool, day, hash, pdf_date, json.clone(), pdf, notify)).await?;
+	if !inserted {
+		debug!("{day}: The pdf {hash} is stored already");
+		METRICS.duplicate_insert();
+	}
+
+	Ok(inserted)
+}
+
+async fn save_schedule_once(pool: &PgPool, day: Schoolday, hash: &str, pdf_date: &DateTime<Local>, json: serde_json::Value, pdf: Option<&[u8]>, notify: bool) -> Result<bool, sqlx::Error> {
+	let insertion_time = Utc::now().naive_utc();
+	let pdf_date = pdf_date.naive_utc();
+
+	let mut transaction = pool.begin().await?;
+
+	let inserted = sqlx::query!(
+		r#"
+		INSERT INTO substitution_json (hash, pdf_date, insertion_time, json, day, pdf, hash_algorithm)
+		VALUES($1, $2, $3, $4, $5, $6, $7)
+		ON CONFLICT (hash) DO NOTHING
+		"#,
+		hash,
+		pdf_date,
+		insertion_time,
+		json,
+		day as i16,
+		pdf,
+		hash_algorithm(hash)
+	)
+		.execute(&mut transaction)
+		.await?
+		.rows_affected() > 0;
+
+	if inserted {
+		store_previous_as_patch(&mut transaction, day, hash, pdf_date, &json).await?;
+	}
+
+	sqlx::query!("DELETE FROM conversion_jobs WHERE hash = $1", hash)
+		.execute(&mut transaction)
+		.await?;
+
+	// Postgres only delivers the notification once the transaction commits.
+	if notify {
+		sqlx::query!("SELECT pg_notify($1, $2)", invalidation::CHANNEL, invalidation::payload(day, hash))
+			.execute(&mut transaction)
+			.await?;
+	}
+
+	transaction.commit().await?;
+
+	if inserted {
+		let _ = NEWEST_ROWS.write().await.insert(day, (pdf_date, hash.to_string()));
+	}
+	Ok(inserted)
+}
+
+/// The hash of the row of `day` with `pdf_date` that was inserted last.
+/// The database is only read if this instance didn't insert or read it already.
+pub async fn newest_hash(pool: &PgPool, day: Schoolday, pdf_date: NaiveDateTime) -> Result<Option<String>, sqlx::Error> {
+	if let Some((newest_date, hash)) = NEWEST_ROWS.read().await.get(&day) {
+		if *newest_date == pdf_date {
+			return Ok(Some(hash.clone()));
+		}
+	}
+
+	let hash = sqlx::query_scalar!(
+		r#"
+		SELECT hash
+		FROM substitution_json
+		WHERE pdf_date = $1 AND day = $2
+		ORDER BY insertion_time DESC
+		LIMIT 1
+		"#,
+		pdf_date,
+		day as i16
+	)
+		.fetch_optional(pool)
+		.await?
+		.flatten();
+
+	if let Some(hash) = &hash {
+		let _ = NEWEST_ROWS.write().await.insert(day, (pdf_date, hash.clone()));
+	}
+	Ok(hash)
+}
+
+/// Same as [`save_schedule`], but replaces the json of an existing row with the same hash.
+/// Rows stored before pdfs were kept get the `pdf`.
+pub async fn replace_schedule(pool: &PgPool, hash: &str, pdf_date: &DateTime<Local>, json: serde_json::Value, pdf: &[u8]) -> Result<(), StorageError> {
+	with_retry(|| replace_schedule_once(pool, hash, pdf_date, json.clone(), pdf)).await
+}
+
+async fn replace_schedule_once(pool: &PgPool, hash: &str, pdf_date: &DateTime<Local>, json: serde_json::Value, pdf: &[u8]) -> Result<(), sqlx::Error> {
+	let insertion_time = Utc::now().naive_utc();
+	let pdf_date = pdf_date.naive_utc();
+
+	let mut transaction = pool.begin().await?;
+
+	// The patches of the older schedules are relative to the json that is replaced.
+	store_dependents_in_full(&mut transaction, hash).await?;
+
+	sqlx::query!(
+		r#"
+		INSERT INTO substitution_json (hash, pdf_date, insertion_time, json, pdf, hash_algorithm)
+		VALUES($1, $2, $3, $4, $5, $6)
+		ON CONFLICT (hash) DO UPDATE SET pdf_date = EXCLUDED.pdf_date, json = EXCLUDED.json, pdf = COALESCE(substitution_json.pdf, EXCLUDED.pdf),
+			base_hash = NULL, patch = NULL
+		"#,
+		hash,
+		pdf_date,
+		insertion_time,
+		json,
+		pdf,
+		hash_algorithm(hash)
+	)
+		.execute(&mut transaction)
+		.await?;
+
+	sqlx::query!("DELETE FROM conversion_jobs WHERE hash = $1", hash)
+		.execute(&mut transaction)
+		.await?;
+
+	transaction.commit().await
+}
+
+/// The name of the algorithm `hash` was created with, the configured one if it can't be told.
+pub fn hash_algorithm(hash: &str) -> &'static str {
+	HashAlgorithm::of(hash).unwrap_or(CONFIG.hash_algorithm).as_str()
+}
+
+/// Stores the newest other schedule of the same date as a patch of the schedule of `hash` that replaces it,
+/// if the patch is small enough to be worth it.
+/// Schedules of other dates are never patched, so the last schedule of every date is always stored in full.
+async fn store_previous_as_patch(connection: &mut PgConnection, day: Schoolday, hash: &str, pdf_date: NaiveDateTime, json: &serde_json::Value) -> Result<(), sqlx::Error> {
+	let date_start = match local_midnight(Local.from_utc_datetime(&pdf_date).date_naive()).and_then(|start| Local.timestamp_millis_opt(start).single()) {
+		Some(start) => start.naive_utc(),
+		None => return Ok(()),
+	};
+
+	let previous = sqlx::query!(
+		r#"
+		SELECT hash AS "hash!", json AS "json!"
+		FROM substitution_json
+		WHERE day = $1 AND hash <> $2 AND pdf_date <= $3 AND pdf_date >= $4 AND hash IS NOT NULL AND json IS NOT NULL
+		ORDER BY pdf_date DESC, insertion_time DESC
+		LIMIT 1
+		FOR UPDATE
+		"#,
+		day as i16,
+		hash,
+		pdf_date,
+		date_start
+	)
+		.fetch_optional(&mut *connection)
+		.await?;
+
+	let previous = match previous {
+		Some(previous) => previous,
+		None => return Ok(()),
+	};
+
+	let patch = serde_json::to_value(json_patch::diff(json, &previous.json)).map_err(|why| sqlx::Error::Decode(why.into()))?;
+	if patch.to_string().len() * MIN_PATCH_SAVING > previous.json.to_string().len() {
+		return Ok(());
+	}
+
+	debug!("{day}: Storing {} as a patch of {hash}", previous.hash);
+	sqlx::query!("UPDATE substitution_json SET json = NULL, base_hash = $2, patch = $3 WHERE hash = $1", previous.hash, hash, patch)
+		.execute(&mut *connection)
+		.await?;
+
+	Ok(())
+}
+
+/// Stores the schedules that are patches of the schedule of `hash` in full, before the json of `hash` changes.
+async fn store_dependents_in_full(connection: &mut PgConnection, hash: &str) -> Result<(), sqlx::Error> {
+	let dependents = sqlx::query_scalar!(r#"SELECT hash AS "hash!" FROM substitution_json WHERE base_hash = $1 AND hash IS NOT NULL"#, hash)
+		.fetch_all(&mut *connection)
+		.await?;
+
+	for dependent in dependents {
+		let json = load_json_from(connection, &dependent).await?;
+		sqlx::query!("UPDATE substitution_json SET json = $2, base_hash = NULL, patch = NULL WHERE hash = $1", dependent, json)
+			.execute(&mut *connection)
+			.await?;
+	}
+
+	Ok(())
+}
+
+/// Reads the schedule stored under `hash`, applying the patches of superseded schedules to the ones that replaced them.
+/// `None` if there is no row.
+pub async fn load_json(pool: &PgPool, hash: &str) -> Result<Option<serde_json::Value>, sqlx::Error> {
+	let mut connection = pool.acquire().await?;
+	load_json_from(&mut connection, hash).await
+}
+
+async fn load_json_from(connection: &mut PgConnection, hash: &str) -> Result<Option<serde_json::Value>, sqlx::Error> {
+	// Collected from the requested schedule towards the newest one, which is stored in full.
+	let mut patches = Vec::new();
+	let mut current = hash.to_string();
+
+	let mut json = loop {
+		let row = sqlx::query!("SELECT json, base_hash, patch FROM substitution_json WHERE hash = $1", current)
+			.fetch_optional(&mut *connection)
+			.await?;
+
+		match row.map(|row| (row.json, row.base_hash, row.patch)) {
+			Some((Some(json), _, _)) => break json,
+			Some((None, Some(base_hash), Some(patch))) if patches.len() < MAX_PATCH_CHAIN => {
+				patches.push(patch);
+				current = base_hash;
+			}
+			_ if patches.is_empty() => return Ok(None),
+			_ => return Err(sqlx::Error::Decode(format!("The schedule {hash} can't be rebuilt from {current}").into())),
+		}
+	};
+
+	for patch in patches.into_iter().rev() {
+		let patch: json_patch::Patch = serde_json::from_value(patch).map_err(|why| sqlx::Error::Decode(why.into()))?;
+		json_patch::patch(&mut json, &patch).map_err(|why| sqlx::Error::Decode(format!("The stored patch towards {hash} doesn't apply: {why}").into()))?;
+	}
+
+	Ok(Some(json))
+}
+
+/// Reads the pdf stored with the schedule of `hash`, `None` if there is no row or it was stored without its pdf.
+pub async fn load_pdf(pool: &PgPool, hash: &str) -> Result<Option<Vec<u8>>, sqlx::Error> {
+	let pdf = sqlx::query_scalar!("SELECT pdf FROM substitution_json WHERE hash = $1", hash)
+		.fetch_optional(pool)
+		.await?;
+
+	Ok(pdf.flatten())
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::{json, Value};
+	use sqlx::Connection;
+	use super::*;
+
+	/// A schedule with enough classes for its versions to be stored as patches.
+	fn schedule(changed: &str) -> Value {
+		let mut entries: serde_json::Map<String, Value> = (5..25)
+			.map(|grade| (format!("{grade}A"), json!({"1": "Mathe bei Herrn Mueller in Raum 104", "3": "Englisch entfällt"})))
+			.collect();
+		entries.insert("10A".to_string(), json!({"2": changed}));
+
+		json!({"pdf_issue_date": 915_436_800_000_i64, "struct_time": 0, "entries": entries})
+	}
+
+	async fn insert(connection: &mut PgConnection, hash: &str, pdf_date: NaiveDateTime, json: &Value) {
+		sqlx::query("INSERT INTO substitution_json (hash, pdf_date, insertion_time, json, day) VALUES($1, $2, $3, $4, $5)")
+			.bind(hash)
+			.bind(pdf_date)
+			.bind(Utc::now().naive_utc())
+			.bind(json)
+			.bind(Schoolday::Monday as i16)
+			.execute(&mut *connection)
+			.await
+			.unwrap();
+		store_previous_as_patch(connection, Schoolday::Monday, hash, pdf_date, json).await.unwrap();
+	}
+
+	/// Needs a migrated database at `DATABASE_URL`, the rows are rolled back afterwards.
+	#[tokio::test]
+	async fn superseded_versions_are_rebuilt_from_their_patches() {
+		let url = std::env::var("DATABASE_URL").expect("The storage tests need a database at DATABASE_URL");
+		let mut connection = PgConnection::connect(&url).await.unwrap();
+		let mut transaction = connection.begin().await.unwrap();
+
+		// 1999-01-04 was a Monday, so no real schedule shares its date.
+		let morning = Local.with_ymd_and_hms(1999, 1, 4, 7, 0, 0).unwrap().naive_utc();
+		let versions = [
+			("storage-test-1", morning, schedule("Physik in Raum 3")),
+			("storage-test-2", morning + chrono::Duration::hours(1), schedule("Physik in Raum 5")),
+			("storage-test-3", morning + chrono::Duration::hours(2), schedule("Physik entfällt")),
+		];
+		for (hash, pdf_date, json) in &versions {
+			insert(&mut transaction, hash, *pdf_date, json).await;
+		}
+
+		let stored_in_full: Vec<bool> = sqlx::query_scalar("SELECT json IS NOT NULL FROM substitution_json WHERE hash LIKE 'storage-test-%' ORDER BY hash")
+			.fetch_all(&mut *transaction)
+			.await
+			.unwrap();
+		assert_eq!(stored_in_full, [false, false, true]);
+
+		for (hash, _, json) in &versions {
+			assert_eq!(load_json_from(&mut transaction, hash).await.unwrap().as_ref(), Some(json));
+		}
+		assert_eq!(load_json_from(&mut transaction, "storage-test-4").await.unwrap(), None);
+
+		transaction.rollback().await.unwrap();
+	}
+}
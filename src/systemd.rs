@@ -0,0 +1,76 @@
+use std::time::Duration;
+use chrono::Local;
+use lazy_static::lazy_static;
+use sd_notify::NotifyState;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+use crate::JSON_HANDLER;
+use crate::scheduler::Scheduler;
+
+/// How long readiness waits for the first fetches or the stored schedules, see [`notify_when_warm`].
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(120);
+/// How often the cache is checked while waiting for it.
+const WARMUP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+	/// Half of `WatchdogSec` of the unit, `None` if the watchdog isn't enabled or the server doesn't run under systemd.
+	static ref WATCHDOG_INTERVAL: Option<Duration> = watchdog_interval();
+}
+
+fn watchdog_interval() -> Option<Duration> {
+	let mut usec = 0;
+	if !sd_notify::watchdog_enabled(false, &mut usec) {
+		return None;
+	}
+
+	let interval = Duration::from_micros(usec) / 2;
+	info!("Feeding the systemd watchdog every {} seconds", interval.as_secs_f32());
+	Some(interval)
+}
+
+/// Sends `states` to systemd, does nothing if the server wasn't started by it.
+fn notify(states: &[NotifyState]) {
+	if let Err(why) = sd_notify::notify(false, states) {
+		warn!("Couldn't notify systemd: {why}");
+	}
+}
+
+/// Tells systemd that the server isn't stuck, the loops call it before their [`watchdog_deadline`].
+pub fn feed_watchdog() {
+	if WATCHDOG_INTERVAL.is_some() {
+		notify(&[NotifyState::Watchdog]);
+	}
+}
+
+/// The latest time a loop sleeping from `now` has to wake up to feed the watchdog in time.
+pub fn watchdog_deadline(now: Instant) -> Option<Instant> {
+	WATCHDOG_INTERVAL.map(|interval| now + interval)
+}
+
+/// Tells systemd that the server is stopping, so it isn't restarted by the watchdog while the fetches finish.
+pub fn stopping() {
+	notify(&[NotifyState::Stopping]);
+}
+
+/// Signals readiness once the relevant days were fetched or loaded from the database, or after [`WARMUP_TIMEOUT`],
+/// so units ordered after this one start with schedules to serve. Call it once the server is bound.
+pub async fn notify_when_warm() {
+	let deadline = Instant::now() + WARMUP_TIMEOUT;
+
+	loop {
+		let statuses = JSON_HANDLER.get_statuses().await;
+		if Scheduler::days_to_fetch(Local::now()).iter().all(|day| statuses.contains_key(day)) {
+			debug!("The cache is warm");
+			break;
+		}
+		if Instant::now() >= deadline {
+			warn!("The relevant days weren't fetched within {} seconds, reporting ready anyway", WARMUP_TIMEOUT.as_secs());
+			break;
+		}
+
+		tokio::time::sleep(WARMUP_POLL_INTERVAL).await;
+	}
+
+	notify(&[NotifyState::Ready]);
+	info!("Ready");
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use arc_swap::ArcSwap;
+use chrono::{Local, TimeZone};
+use handlebars::{Handlebars, TemplateError};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use substitution_pdf_to_json::SubstitutionSchedule;
+use crate::{CONFIG, Schoolday};
+use crate::diff::{self, Change};
+use crate::notifier::ScheduleChange;
+
+/// The message of the notifiers that list the changes, the same text as `/diff/{schoolday}/text`.
+const DEFAULT_CHANGE_TEMPLATE: &str = "\
+{{#if changes}}Changes on {{day}}:
+
+{{#each changes}}- **{{class}}**: Block {{block}} {{#if after}}now {{after}}{{else}}no longer {{before}}{{/if}}
+{{/each}}{{else}}No changes on {{day}}.
+{{/if}}";
+
+/// The Mastodon status, which only says how many classes are affected to keep it short.
+const DEFAULT_MASTODON_TEMPLATE: &str = "\
+Vertretungsplan für {{day_name}} aktualisiert, {{class_count}} {{#if (eq class_count 1)}}Klasse{{else}}Klassen{{/if}} betroffen";
+
+/// The digest of the next school day, listing the substitutions of every class.
+const DEFAULT_DIGEST_TEMPLATE: &str = "\
+{{#if classes}}Substitutions on {{day}}, {{issue_date}}:
+{{#each classes}}
+**{{name}}**
+{{#each blocks}}- Block {{block}}: {{text}}
+{{/each}}{{/each}}{{else}}No substitutions on {{day}}, {{issue_date}}.
+{{/if}}";
+
+lazy_static! {
+	/// The templates of the notifiers, by notifier name, overridden by the ones in `notify.templates`.
+	/// Replaced when the config is reloaded.
+	pub static ref TEMPLATES: ArcSwap<Handlebars<'static>> = ArcSwap::from_pointee(
+		compile(&CONFIG.notify.templates).unwrap_or_else(|why| panic!("Invalid notification template: {why}"))
+	);
+}
+
+/// Compiles the default templates with `overrides` replacing them.
+///
+/// # Errors
+///
+/// Returns `Err` if an override isn't a valid template.
+pub fn compile(overrides: &HashMap<String, String>) -> Result<Handlebars<'static>, Box<TemplateError>> {
+	let mut registry = Handlebars::new();
+	// The messages are plain text or markdown, not html.
+	registry.register_escape_fn(handlebars::no_escape);
+	registry.set_strict_mode(true);
+
+	for (name, template) in [("matrix", DEFAULT_CHANGE_TEMPLATE), ("webhook", DEFAULT_CHANGE_TEMPLATE), ("users", DEFAULT_CHANGE_TEMPLATE), ("mastodon", DEFAULT_MASTODON_TEMPLATE),
+		("matrix_digest", DEFAULT_DIGEST_TEMPLATE), ("webhook_digest", DEFAULT_DIGEST_TEMPLATE)] {
+		registry.register_template_string(name, template).expect("The default templates are valid");
+	}
+	for (name, template) in overrides {
+		registry.register_template_string(name, template).map_err(Box::new)?;
+	}
+
+	Ok(registry)
+}
+
+/// What the templates can use.
+#[derive(Debug, Serialize)]
+struct Context<'a> {
+	/// The english name of the day, like `Monday`.
+	day: String,
+	/// The german name of the day, like `Montag`.
+	day_name: &'static str,
+	/// The date the schedule was issued for, like `14.03.2022`.
+	issue_date: String,
+	hash: Option<&'a str>,
+	/// The classes with changed blocks.
+	classes: Vec<&'a str>,
+	class_count: usize,
+	changes: Vec<ChangeContext<'a>>,
+	/// The changes as a markdown list, like the default template renders them.
+	summary: String,
+}
+
+/// What the digest templates can use.
+#[derive(Debug, Serialize)]
+struct DigestContext<'a> {
+	day: String,
+	day_name: &'static str,
+	issue_date: String,
+	/// The classes with substitutions, ordered by name.
+	classes: Vec<ClassContext<'a>>,
+	class_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ClassContext<'a> {
+	name: &'a str,
+	blocks: Vec<BlockContext>,
+}
+
+#[derive(Debug, Serialize)]
+struct BlockContext {
+	block: usize,
+	/// The lines of the block joined with `, `.
+	text: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChangeContext<'a> {
+	class: &'a str,
+	block: usize,
+	/// The text of the block before and after the change, its lines joined with `, `.
+	before: Option<String>,
+	after: Option<String>,
+}
+
+/// Renders the template of the notifier `name` for `changes`, which are all or some of the changes of `change`.
+///
+/// # Errors
+///
+/// Returns `Err` if the template uses a value that doesn't exist.
+pub fn render(name: &str, change: &ScheduleChange, changes: &[Change]) -> Result<String, handlebars::RenderError> {
+	let mut classes: Vec<&str> = changes.iter().map(|change| change.class.as_str()).collect();
+	classes.dedup();
+
+	let context = Context {
+		day: change.day.to_string(),
+		day_name: change.day.german_name(),
+		issue_date: issue_date(&change.schedule),
+		hash: change.hash.as_deref(),
+		class_count: classes.len(),
+		classes,
+		changes: changes.iter()
+			.map(|change| ChangeContext {
+				class: change.class.as_str(),
+				block: change.block,
+				before: change.before.map(diff::one_line),
+				after: change.after.map(diff::one_line),
+			})
+			.collect(),
+		summary: diff::render_text(change.day, changes),
+	};
+
+	TEMPLATES.load().render(name, &context)
+}
+
+/// Renders the digest template `name` for the schedule of `day`.
+///
+/// # Errors
+///
+/// Returns `Err` if the template uses a value that doesn't exist.
+pub fn render_digest(name: &str, day: Schoolday, schedule: &SubstitutionSchedule) -> Result<String, handlebars::RenderError> {
+	let mut classes: Vec<ClassContext> = schedule.entries()
+		.iter()
+		.map(|(class, column)| ClassContext {
+			name: class.as_str(),
+			blocks: column.blocks()
+				.map(|(block, text)| BlockContext {
+					block,
+					text: diff::one_line(text),
+				})
+				.collect(),
+		})
+		.filter(|class| !class.blocks.is_empty())
+		.collect();
+	classes.sort_unstable_by_key(|class| class.name);
+
+	let context = DigestContext {
+		day: day.to_string(),
+		day_name: day.german_name(),
+		issue_date: issue_date(schedule),
+		class_count: classes.len(),
+		classes,
+	};
+
+	TEMPLATES.load().render(name, &context)
+}
+
+/// The date the schedule is for, like `14.03.2022`.
+fn issue_date(schedule: &SubstitutionSchedule) -> String {
+	schedule.effective_date()
+		.map(|date| date.format("%d.%m.%Y").to_string())
+		.or_else(|| Local.timestamp_millis_opt(schedule.pdf_issue_date).single().map(|date| date.format("%d.%m.%Y").to_string()))
+		.unwrap_or_default()
+}
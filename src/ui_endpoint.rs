@@ -0,0 +1,51 @@
+use actix_web::{get, HttpRequest, HttpResponse, Responder, web};
+use actix_web::http::header;
+use rust_embed::RustEmbed;
+
+/// The files of the web UI, embedded into the binary.
+#[derive(RustEmbed)]
+#[folder = "web/"]
+struct Assets;
+
+/// Serves the web UI, which shows the schedules of the days with a class filter and when they were fetched.
+#[get("/")]
+pub async fn get_index(req: HttpRequest) -> impl Responder {
+	asset_response(&req, "index.html")
+}
+
+/// Serves the scripts and styles of the web UI.
+#[get("/ui/{file}")]
+pub async fn get_ui_asset(req: HttpRequest, file: web::Path<String>) -> impl Responder {
+	asset_response(&req, &file)
+}
+
+/// Answers with the embedded file, using its hash as ETag.
+fn asset_response(req: &HttpRequest, path: &str) -> HttpResponse {
+	let file = match Assets::get(path) {
+		Some(file) => file,
+		None => return HttpResponse::NotFound().finish(),
+	};
+
+	let etag = format!("\"{}\"", hex::encode(file.metadata.sha256_hash()));
+	let matches = req.headers()
+		.get(header::IF_NONE_MATCH)
+		.and_then(|value| value.to_str().ok())
+		.is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+
+	let mut response = if matches {
+		HttpResponse::NotModified()
+	} else {
+		HttpResponse::Ok()
+	};
+	response
+		.insert_header((header::ETAG, etag))
+		.insert_header((header::CACHE_CONTROL, "no-cache"));
+
+	if matches {
+		return response.finish();
+	}
+
+	response
+		.content_type(file.metadata.mimetype())
+		.body(file.data.into_owned())
+}
@@ -1,25 +1,68 @@
-use std::path::Path;
-use tracing::{trace};
-use uuid::Uuid;
+use std::path::{Path, PathBuf};
+use chrono::{Local, NaiveDate, TimeZone};
+use lazy_static::lazy_static;
+use tracing::{debug, trace, warn};
 use crate::TEMP_ROOT_DIR;
 
-/// Returns a random name (UUID).
-/// Used for temp directories and temp files for example.
-pub fn get_random_name() -> String {
-	trace!("Returning random name");
-	let random_name = Uuid::new_v4();
-	trace!("Random name generated: {random_name}");
-	format!("{random_name}")
+lazy_static! {
+	/// The temp dir of this process inside [`TEMP_ROOT_DIR`], named after the process id,
+	/// so the instances sharing the host can tell whether it is still in use.
+	pub static ref TEMP_DIR: PathBuf = Path::new(TEMP_ROOT_DIR).join(std::process::id().to_string());
 }
 
-/// Creates a temp dir and returns the path as String.
-pub fn make_temp_dir() -> String {
-	trace!("Creating temp directory");
-	let temp_dir_name = get_random_name();
-	let temp_dir = format!("{}/{}", TEMP_ROOT_DIR, temp_dir_name);
-	// Gets unwrapped because we can't continue at all if the temp dir failed to create.
-	std::fs::create_dir(Path::new(&temp_dir)).expect("Could not create temp dir");
-	trace!("Created temp dir {temp_dir}");
+/// Removes the temp dir of this process when it is dropped.
+pub struct TempDirGuard;
 
-	temp_dir
+impl Drop for TempDirGuard {
+	fn drop(&mut self) {
+		if let Err(why) = std::fs::remove_dir_all(&*TEMP_DIR) {
+			warn!("Couldn't remove the temp dir {}: {why}", TEMP_DIR.display());
+		}
+	}
+}
+
+/// Creates [`TEMP_DIR`], after removing the temp dirs of processes that are gone.
+/// Conversions clean up after themselves, so anything found in those was orphaned by a crash or a kill.
+/// The temp dirs of other running instances are left alone.
+pub fn create_temp_dir() -> std::io::Result<TempDirGuard> {
+	let temp_root = Path::new(TEMP_ROOT_DIR);
+	std::fs::create_dir_all(temp_root)?;
+
+	for entry in std::fs::read_dir(temp_root)? {
+		let path = entry?.path();
+		let in_use = path.file_name()
+			.and_then(|name| name.to_str()?.parse::<u32>().ok())
+			.is_some_and(|pid| pid != std::process::id() && is_running(pid));
+		if in_use {
+			continue;
+		}
+		trace!("Removing orphaned temp entry {}", path.display());
+
+		let result = if path.is_dir() {
+			std::fs::remove_dir_all(&path)
+		} else {
+			std::fs::remove_file(&path)
+		};
+
+		if let Err(why) = result {
+			warn!("Couldn't remove orphaned temp entry {}: {why}", path.display());
+		}
+	}
+	debug!("Swept temp dir {}", temp_root.display());
+
+	std::fs::create_dir(&*TEMP_DIR)?;
+	Ok(TempDirGuard)
+}
+
+/// Whether a process with the id `pid` runs on the host. Without `/proc` to tell, every process counts as running.
+fn is_running(pid: u32) -> bool {
+	let proc = Path::new("/proc");
+	!proc.join("self").exists() || proc.join(pid.to_string()).exists()
+}
+
+/// The start of `date` in local time, in milliseconds since the unix epoch.
+pub fn local_midnight(date: NaiveDate) -> Option<i64> {
+	Local.from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+		.earliest()
+		.map(|midnight| midnight.timestamp_millis())
 }
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use chrono::{NaiveDate, SecondsFormat, TimeZone, Utc};
+use serde::Serialize;
+use substitution_pdf_to_json::{BlockEntry, ClassName, SubstitutionSchedule};
+
+/// Wraps a v2 document with where it came from, so wrong data can be traced back to the PDF that produced it.
+#[derive(Debug, Serialize)]
+pub struct Envelope {
+	pub source: Source,
+	/// The version of the converter that produced the schedule.
+	pub converter_version: &'static str,
+	/// Problems found while converting the PDF.
+	pub warnings: Vec<String>,
+	pub schedule: ScheduleV2,
+}
+
+/// The PDF a schedule was converted from.
+#[derive(Debug, Serialize)]
+pub struct Source {
+	pub url: String,
+	/// When the PDF was downloaded, as an RFC 3339 string.
+	pub fetched_at: Option<String>,
+	/// The hex encoded hash of the PDF, BLAKE3 or SHA512 depending on the `hash_algorithm` it was stored with.
+	pub pdf_hash: Option<String>,
+}
+
+/// The v2 document of a schedule.
+/// Every class maps to the list of its blocks with substitutions, instead of an object keyed by block index.
+#[derive(Debug, Serialize)]
+pub struct ScheduleV2 {
+	/// The creation date inside the PDF as an RFC 3339 string.
+	pub pdf_issued_at: String,
+	/// The time the schedule was converted as an RFC 3339 string.
+	pub created_at: String,
+	/// The calendar date the schedule applies to.
+	pub effective_date: Option<NaiveDate>,
+	/// Whether the date inside the PDF isn't on the day it is served for, so the PDF is probably outdated.
+	pub issue_date_mismatch: bool,
+	/// Sorted naturally by class name.
+	pub classes: BTreeMap<ClassName, Vec<BlockV2>>,
+}
+
+/// A block of a class that has substitutions.
+#[derive(Debug, Serialize)]
+pub struct BlockV2 {
+	pub index: usize,
+	/// The text of the block as it was in the PDF, its lines separated by line breaks.
+	pub text: String,
+	/// The lines of the block in order, one per row of the table, with their structured fields.
+	/// Clients should use these instead of splitting `text`.
+	pub entries: Vec<BlockEntry>,
+}
+
+impl From<&SubstitutionSchedule> for ScheduleV2 {
+	#[allow(clippy::cast_possible_wrap)]
+	fn from(schedule: &SubstitutionSchedule) -> Self {
+		let classes = schedule.entries()
+			.iter()
+			.map(|(class, column)| {
+				let mut entries = column.block_entries();
+				let blocks = column.blocks()
+					.map(|(index, text)| {
+						let split = entries.partition_point(|entry| entry.block == index);
+						BlockV2 {
+							index,
+							text: text.to_string(),
+							entries: entries.drain(..split).collect(),
+						}
+					})
+					.collect();
+
+				(class.clone(), blocks)
+			})
+			.collect();
+
+		Self {
+			pdf_issued_at: rfc3339(schedule.pdf_issue_date),
+			created_at: rfc3339(schedule.struct_time() as i64),
+			effective_date: schedule.effective_date(),
+			issue_date_mismatch: schedule.issue_date_mismatch(),
+			classes,
+		}
+	}
+}
+
+/// Formats milliseconds since the unix epoch as an RFC 3339 string in UTC.
+pub fn rfc3339(millis: i64) -> String {
+	Utc.timestamp_millis_opt(millis).unwrap().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
@@ -0,0 +1,47 @@
+use actix_web::{get, HttpResponse, Responder, web};
+use substitution_pdf_to_json::VERSION;
+use tracing::error;
+use crate::{CONFIG, JSON_HANDLER, Schoolday};
+use crate::json_endpoint::{DayQuery, filter_schedule};
+use crate::json_handler::source_hash;
+use crate::stateless::{served_schedule, served_versioned_json};
+use crate::storage::Pools;
+use crate::v2::{Envelope, rfc3339, ScheduleV2, Source};
+
+/// Serves the schedule of a day as a v2 document, wrapped in an envelope with its provenance.
+#[get("/v2/{schoolday}")]
+pub async fn get_schoolday_v2(day: web::Path<Schoolday>, query: web::Query<DayQuery>, pools: web::Data<Pools>) -> impl Responder {
+	let (schedule, versioned) = match (served_schedule(*day, &pools.read).await, served_versioned_json(*day, &pools.read).await) {
+		(Ok(Some(schedule)), Ok(versioned)) => (schedule, versioned),
+		(Ok(None), _) => {
+			return HttpResponse::NoContent()
+				.append_header(("Retry-After", "120"))
+				.finish();
+		}
+		(Err(why), _) | (_, Err(why)) => {
+			error!("Couldn't read the schedule of {day}: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	let document = match &query.classes {
+		Some(classes) => ScheduleV2::from(&filter_schedule(&schedule, classes)),
+		None => ScheduleV2::from(schedule.as_ref()),
+	};
+
+	// A day covered by the pdf of another day comes from the url of that day.
+	let source_day = JSON_HANDLER.get_status(*day).await.and_then(|status| status.covered_by).unwrap_or(*day);
+	let fetched_at = JSON_HANDLER.get_status(source_day).await.and_then(|status| status.last_fetch);
+	let envelope = Envelope {
+		source: Source {
+			url: CONFIG.source.urls[source_day as usize].clone(),
+			fetched_at: fetched_at.map(rfc3339),
+			pdf_hash: versioned.map(|versioned| source_hash(&versioned.hash).to_string()),
+		},
+		converter_version: VERSION,
+		warnings: schedule.warnings().to_vec(),
+		schedule: document,
+	};
+
+	HttpResponse::Ok().json(envelope)
+}
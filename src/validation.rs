@@ -0,0 +1,285 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::time::Duration;
+use handlebars::Handlebars;
+use reqwest::Url;
+use reqwest::header::{HeaderName, HeaderValue};
+use sqlx::PgPool;
+use substitution_pdf_to_json::{ClassNameNormalizer, TABULA_JAR};
+use crate::{classes, quotas, QUARANTINE_LOCATION, SOURCE_URLS, TEMP_ROOT_DIR};
+use crate::config::{BlobStoreConfig, Config, Renderer};
+use crate::scheduler::Scheduler;
+
+/// How long the database gets to answer before it is reported as unreachable.
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether a problem stops the server from starting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+	/// The server would fail or panic later on.
+	Error,
+	/// The server works, but some feature won't.
+	Warning,
+}
+
+/// A problem with the configuration or the environment and how to fix it.
+#[derive(Debug)]
+pub struct Problem {
+	pub severity: Severity,
+	/// The config key or resource the problem is about.
+	pub subject: String,
+	pub message: String,
+	pub hint: String,
+}
+
+/// All problems found on startup, logged as one report.
+#[derive(Debug, Default)]
+pub struct Report {
+	pub problems: Vec<Problem>,
+}
+
+impl Report {
+	fn error(&mut self, subject: impl Into<String>, message: impl ToString, hint: impl Into<String>) {
+		self.push(Severity::Error, subject.into(), message.to_string(), hint.into());
+	}
+
+	fn warning(&mut self, subject: impl Into<String>, message: impl ToString, hint: impl Into<String>) {
+		self.push(Severity::Warning, subject.into(), message.to_string(), hint.into());
+	}
+
+	fn push(&mut self, severity: Severity, subject: String, message: String, hint: String) {
+		self.problems.push(Problem {
+			severity,
+			subject,
+			message,
+			hint,
+		});
+	}
+
+	pub fn errors(&self) -> usize {
+		self.problems.iter().filter(|problem| problem.severity == Severity::Error).count()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.problems.is_empty()
+	}
+
+	/// Reports an error if `url` isn't an absolute http(s) url.
+	fn check_url(&mut self, subject: &str, url: &str) {
+		match Url::parse(url) {
+			Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+			Ok(parsed) => self.error(subject, format!("{url} uses the scheme {}", parsed.scheme()), "Use an http:// or https:// url"),
+			Err(why) => self.error(subject, format!("{url} is not a valid url: {why}"), "Use an absolute url like https://example.org/path"),
+		}
+	}
+
+	/// Reports an error if files can't be created in `dir`, creating it if it is missing.
+	async fn check_writable(&mut self, subject: &str, dir: &str) {
+		let probe = Path::new(dir).join(".write-test");
+		let result = async {
+			tokio::fs::create_dir_all(dir).await?;
+			tokio::fs::write(&probe, b"").await?;
+			tokio::fs::remove_file(&probe).await
+		}.await;
+
+		if let Err(why) = result {
+			self.error(subject, format!("{dir} is not writable: {why}"), "Create the directory and give the user running the server write access");
+		}
+	}
+}
+
+impl Display for Report {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		writeln!(f, "Found {} errors and {} warnings in the configuration:", self.errors(), self.problems.len() - self.errors())?;
+		for problem in &self.problems {
+			let severity = match problem.severity {
+				Severity::Error => "error",
+				Severity::Warning => "warning",
+			};
+			writeln!(f, "  {severity} in {}: {}", problem.subject, problem.message)?;
+			writeln!(f, "    -> {}", problem.hint)?;
+		}
+		Ok(())
+	}
+}
+
+/// Checks everything the server needs before it starts fetching and serving, so misconfigurations are reported together
+/// instead of as panics or failed updates later on.
+/// Includes the checks of [`validate_config`].
+pub async fn validate(config: &Config, pool: &PgPool) -> Report {
+	let mut report = validate_config(config);
+
+	match tokio::time::timeout(DATABASE_TIMEOUT, sqlx::query("SELECT 1").execute(pool)).await {
+		Ok(Ok(_)) => {}
+		Ok(Err(why)) => report.warning("DATABASE_URL", format!("The database is unreachable: {why}"), "Schedules are only served from memory and written once it is reachable, check the url and the credentials"),
+		Err(_) => report.warning("DATABASE_URL", format!("The database didn't answer within {} seconds", DATABASE_TIMEOUT.as_secs()), "Check that the database is running and reachable from this host"),
+	}
+
+	check_source(config, &mut report).await;
+	check_tools(config, &mut report);
+
+	report.check_writable("temp directory", TEMP_ROOT_DIR).await;
+	report.check_writable("quarantine", QUARANTINE_LOCATION).await;
+	if let BlobStoreConfig::Disk { directory } = &config.blobs {
+		report.check_writable("blobs.directory", directory).await;
+	}
+
+	report
+}
+
+/// Checks the configuration itself, without looking at the environment.
+/// Every subcommand runs these, as the patterns and schedules are compiled on first use and would panic there otherwise.
+pub fn validate_config(config: &Config) -> Report {
+	let mut report = Report::default();
+
+	for (name, pool) in [("database.write_pool", &config.database.write_pool), ("database.read_pool", &config.database.read_pool)] {
+		if pool.max_connections == 0 || pool.min_connections > pool.max_connections {
+			report.error(name, format!("Invalid pool size {}..={}", pool.min_connections, pool.max_connections), "max_connections has to be at least 1 and at least min_connections");
+		}
+	}
+
+	if config.http.workers == 0 {
+		report.error("http.workers", "There are no workers", "Set it to at least 1");
+	}
+	if config.convert.enabled && (config.convert.max_size_bytes == 0 || config.convert.max_pages == 0) {
+		report.error("convert", "Every upload would be rejected", "Set max_size_bytes and max_pages to at least 1, or disable the endpoint");
+	}
+	if config.http.max_connections == 0 {
+		report.error("http.max_connections", "No connections would be accepted", "Set it to at least 1");
+	}
+
+	check_quotas(config, &mut report);
+	check_schedule(config, &mut report);
+
+	if let BlobStoreConfig::S3(s3) = &config.blobs {
+		report.check_url("blobs.endpoint", &s3.endpoint);
+	}
+
+	if let Err(why) = ClassNameNormalizer::new(&config.class_names) {
+		report.error("class_names.rewrites", why, "Fix the regex of the rewrite");
+	}
+	if let Err(why) = classes::compile_groups(&config.class_groups) {
+		report.error("class_groups", why, "Fix the regex of the class group");
+	}
+
+	check_notifiers(config, &mut report);
+
+	report
+}
+
+fn check_quotas(config: &Config, report: &mut Report) {
+	let quotas = &config.quotas;
+	for (network, tier) in &quotas.networks {
+		if let Err(why) = network.parse::<ipnet::IpNet>() {
+			report.error("quotas.networks", format!("{network}: {why}"), "Write networks like 10.0.0.0/8 or fd00::/8");
+		}
+		if !quotas.tiers.contains_key(tier) {
+			report.error("quotas.networks", format!("There is no tier called {tier}"), "Add it to [quotas.tiers] or use one of the tiers there");
+		}
+	}
+	for tier in quotas.keys.values() {
+		if !quotas.tiers.contains_key(tier) {
+			report.error("quotas.keys", format!("There is no tier called {tier}"), "Add it to [quotas.tiers] or use one of the tiers there");
+		}
+	}
+	if quotas.ipv6_prefix_len > 128 {
+		report.error("quotas.ipv6_prefix_len", format!("{} is longer than an IPv6 address", quotas.ipv6_prefix_len), "Use a prefix length of at most 128, like 64");
+	}
+	if quotas.enabled && !quotas.tiers.contains_key(quotas::ANONYMOUS_TIER) {
+		report.warning("quotas.tiers", "There is no anonymous tier", "Clients without a key or a known address aren't limited, add [quotas.tiers.anonymous] to limit them");
+	}
+}
+
+async fn check_source(config: &Config, report: &mut Report) {
+	if let Some(directory) = &config.source.directory {
+		match tokio::fs::metadata(directory).await {
+			Ok(metadata) if metadata.is_dir() => {}
+			Ok(_) => report.error("source.directory", format!("{directory} is not a directory"), "Point it at the directory the pdfs are put into"),
+			Err(why) => report.error("source.directory", format!("{directory} can't be read: {why}"), "Create or mount the directory before starting the server"),
+		}
+		return;
+	}
+
+	if config.source.urls.len() != SOURCE_URLS.len() {
+		report.error("source.urls", format!("Expected {} urls, found {}", SOURCE_URLS.len(), config.source.urls.len()), "List one url per school day, from Monday to Friday");
+	}
+	for url in &config.source.urls {
+		report.check_url("source.urls", url);
+	}
+
+	for (name, value) in &config.source.headers {
+		if HeaderName::try_from(name.as_str()).is_err() {
+			report.error("source.headers", format!("{name} is not a valid header name"), "Header names can't contain spaces or special characters");
+		}
+		if HeaderValue::try_from(value.as_str()).is_err() {
+			report.error("source.headers", format!("The value of {name} is not a valid header value"), "Header values can't contain line breaks or other control characters");
+		}
+	}
+}
+
+fn check_schedule(config: &Config, report: &mut Report) {
+	if let Err(why) = Scheduler::cadences(&config.schedule) {
+		report.error("schedule.cron", why, "Cron expressions have a seconds field, like \"0 */2 6-15 * * Mon-Fri\"");
+	}
+	for expression in &config.schedule.digest_cron {
+		if let Err(why) = expression.parse::<cron::Schedule>() {
+			report.error("schedule.digest_cron", format!("{expression}: {why}"), "Cron expressions have a seconds field, like \"0 0 18 * * Sun-Thu\"");
+		}
+	}
+	// The digests are sent by the scheduler, which doesn't run while the pdfs are read from a directory.
+	if !config.schedule.digest_cron.is_empty() && config.source.directory.is_some() {
+		report.error("schedule.digest_cron", "Digests are never sent while source.directory is set", "Remove digest_cron or fetch the pdfs from source.urls");
+	}
+	if config.schedule.interval_secs == 0 && config.schedule.cron.is_empty() {
+		report.warning("schedule.interval_secs", "The pdfs are fetched without any pause", "Set an interval of a few seconds at least");
+	}
+}
+
+fn check_tools(config: &Config, report: &mut Report) {
+	if !Path::new(TABULA_JAR).is_file() {
+		report.error("tabula", format!("{TABULA_JAR} doesn't exist"), "Download the tabula jar with dependencies into ./tabula, or start the server from the directory containing it");
+	}
+	if !on_path("java") {
+		report.error("tabula", "java is not installed", "Install a Java runtime, tabula needs it to convert the pdfs");
+	}
+
+	let renderer = match config.preview.renderer {
+		Renderer::Ghostscript => "gs",
+		Renderer::Pdftoppm => "pdftoppm",
+	};
+	if !on_path(renderer) {
+		report.warning("preview.renderer", format!("{renderer} is not installed"), "Install it or choose the other renderer, /{schoolday}/preview.png fails without it");
+	}
+}
+
+fn check_notifiers(config: &Config, report: &mut Report) {
+	for url in &config.notify.webhooks {
+		report.check_url("notify.webhooks", url);
+	}
+	if let Some(url) = &config.alert.webhook_url {
+		report.check_url("alert.webhook_url", url);
+	}
+	if let Some(matrix) = &config.matrix {
+		report.check_url("matrix.homeserver", &matrix.homeserver);
+	}
+	if let Some(mastodon) = &config.mastodon {
+		report.check_url("mastodon.instance", &mastodon.instance);
+	}
+	if let Some(oidc) = config.auth.as_ref().and_then(|auth| auth.oidc.as_ref()) {
+		report.check_url("auth.oidc.issuer", &oidc.issuer);
+		report.check_url("auth.oidc.redirect_uri", &oidc.redirect_uri);
+	}
+
+	let mut registry = Handlebars::new();
+	for (name, template) in &config.notify.templates {
+		if let Err(why) = registry.register_template_string(name, template) {
+			report.error(format!("notify.templates.{name}"), why, "Fix the handlebars syntax of the template");
+		}
+	}
+}
+
+/// Whether `program` is an executable file in one of the directories of `PATH`.
+fn on_path(program: &str) -> bool {
+	std::env::var_os("PATH")
+		.is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
@@ -0,0 +1,62 @@
+use actix_web::{get, HttpResponse, Responder, web};
+use serde::Serialize;
+use substitution_pdf_to_json::{BlockEntry, ClassName};
+use tracing::error;
+use crate::Schoolday;
+use crate::stateless::served_schedule;
+use crate::storage::Pools;
+
+/// A line of the schedule together with the class it belongs to.
+#[derive(Debug, Serialize)]
+struct ClassEntry {
+	class: ClassName,
+	#[serde(flatten)]
+	entry: BlockEntry,
+}
+
+/// Lists every substitution of the day that involves the teacher with the given abbreviation.
+#[get("/{schoolday}/teachers/{abbrev}")]
+pub async fn get_teacher_view(path: web::Path<(Schoolday, String)>, pools: web::Data<Pools>) -> impl Responder {
+	let (day, abbrev) = path.into_inner();
+	entries_matching(day, &pools, |entry| entry.involves_teacher(&abbrev)).await
+}
+
+/// Lists every substitution of the day that moves a lesson into or out of the given room.
+/// Two classes in the same room and block point at a double-booking.
+#[get("/{schoolday}/rooms/{room}")]
+pub async fn get_room_view(path: web::Path<(Schoolday, String)>, pools: web::Data<Pools>) -> impl Responder {
+	let (day, room) = path.into_inner();
+	entries_matching(day, &pools, |entry| entry.involves_room(&room)).await
+}
+
+/// Responds with all entries of `day` that match `filter`, ordered by block.
+async fn entries_matching(day: Schoolday, pools: &Pools, filter: impl Fn(&BlockEntry) -> bool) -> HttpResponse {
+	let schedule = match served_schedule(day, &pools.read).await {
+		Ok(Some(schedule)) => schedule,
+		Ok(None) => {
+			return HttpResponse::NoContent()
+				.append_header(("Retry-After", "120"))
+				.finish();
+		}
+		Err(why) => {
+			error!("Couldn't read the schedule of {day}: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	let mut entries: Vec<ClassEntry> = schedule.entries()
+		.iter()
+		.flat_map(|(class, column)| {
+			column.block_entries()
+				.into_iter()
+				.map(move |entry| ClassEntry {
+					class: class.clone(),
+					entry,
+				})
+		})
+		.filter(|entry| filter(&entry.entry))
+		.collect();
+	entries.sort_by_key(|entry| entry.entry.block);
+
+	HttpResponse::Ok().json(entries)
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sqlx::PgPool;
+use substitution_pdf_to_json::{ClassName, SubstitutionColumn, SubstitutionSchedule};
+use tokio::sync::RwLock;
+use tracing::debug;
+use crate::Schoolday;
+use crate::stateless::served_schedule;
+
+lazy_static! {
+	static ref WEEK: RwLock<Option<Arc<Week>>> = RwLock::new(None);
+}
+
+/// The served schedules of the whole week, transposed to the days of every class.
+#[derive(Debug)]
+pub struct Week {
+	/// Which schedule every day was built from, to tell when one of them changed.
+	versions: Vec<Option<(i64, u64)>>,
+	pub days: BTreeMap<Schoolday, WeekDay>,
+	/// The blocks of every class on the days it has substitutions on.
+	pub classes: BTreeMap<ClassName, BTreeMap<Schoolday, SubstitutionColumn>>,
+}
+
+/// The schedule a day of the week comes from.
+#[derive(Debug, Serialize)]
+pub struct WeekDay {
+	/// The issue date of the pdf, in milliseconds since the unix epoch.
+	pub pdf_issue_date: i64,
+	pub effective_date: Option<NaiveDate>,
+}
+
+/// The week of the currently served schedules, built again once any of the days changed.
+///
+/// # Errors
+///
+/// Returns `Err` if the schedules couldn't be read from the database in stateless mode.
+pub async fn current(pool: &PgPool) -> Result<Arc<Week>, Box<dyn std::error::Error + Send + Sync>> {
+	let mut schedules = Vec::with_capacity(Schoolday::ALL.len());
+	for day in Schoolday::ALL {
+		schedules.push((day, served_schedule(day, pool).await?));
+	}
+	let versions: Vec<_> = schedules.iter()
+		.map(|(_, schedule)| schedule.as_ref().map(|schedule| (schedule.pdf_issue_date, schedule.struct_time())))
+		.collect();
+
+	if let Some(week) = WEEK.read().await.as_ref().filter(|week| week.versions == versions) {
+		return Ok(week.clone());
+	}
+
+	debug!("Building the week of every class again");
+	let week = Arc::new(build(versions, &schedules));
+	*WEEK.write().await = Some(week.clone());
+
+	Ok(week)
+}
+
+fn build(versions: Vec<Option<(i64, u64)>>, schedules: &[(Schoolday, Option<Arc<SubstitutionSchedule>>)]) -> Week {
+	let mut days = BTreeMap::new();
+	let mut classes: BTreeMap<ClassName, BTreeMap<Schoolday, SubstitutionColumn>> = BTreeMap::new();
+
+	for (day, schedule) in schedules {
+		let schedule = match schedule {
+			Some(schedule) => schedule,
+			None => continue,
+		};
+
+		days.insert(*day, WeekDay {
+			pdf_issue_date: schedule.pdf_issue_date,
+			effective_date: schedule.effective_date(),
+		});
+		for (class, column) in schedule.entries() {
+			classes.entry(class.clone()).or_default().insert(*day, column.clone());
+		}
+	}
+
+	Week {
+		versions,
+		days,
+		classes,
+	}
+}
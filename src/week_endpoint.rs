@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+use actix_web::{get, HttpResponse, Responder, web};
+use serde::Serialize;
+use substitution_pdf_to_json::{ClassName, SubstitutionColumn};
+use tracing::error;
+use crate::{Schoolday, week};
+use crate::classes::ClassSelector;
+use crate::storage::Pools;
+use crate::week::WeekDay;
+
+#[derive(Debug, Serialize)]
+struct WeekResponse<'a> {
+	/// The days of the week that have a schedule.
+	days: &'a BTreeMap<Schoolday, WeekDay>,
+	/// The blocks of every requested class by day, days without substitutions are left out.
+	classes: BTreeMap<&'a ClassName, &'a BTreeMap<Schoolday, SubstitutionColumn>>,
+}
+
+/// Serves the substitutions of a class, or of every class in a configured group, on all days of the week at once.
+#[get("/week/{class}")]
+pub async fn get_week(class: web::Path<String>, pools: web::Data<Pools>) -> impl Responder {
+	let week = match week::current(&pools.read).await {
+		Ok(week) => week,
+		Err(why) => {
+			error!("Couldn't build the week: {why}");
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+
+	if week.days.is_empty() {
+		return HttpResponse::NoContent()
+			.append_header(("Retry-After", "120"))
+			.finish();
+	}
+
+	let selector = ClassSelector::new(&class);
+	let classes: BTreeMap<_, _> = week.classes
+		.iter()
+		.filter(|(class, _)| selector.matches(class))
+		.collect();
+	if classes.is_empty() {
+		return HttpResponse::NotFound().finish();
+	}
+
+	HttpResponse::Ok().json(WeekResponse {
+		days: &week.days,
+		classes,
+	})
+}
@@ -0,0 +1,39 @@
+use actix_web::HttpResponse;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use chrono::Weekday;
+use crate::Schoolday;
+
+pub const REQUESTED_DAY: HeaderName = HeaderName::from_static("x-requested-day");
+pub const EFFECTIVE_DAY: HeaderName = HeaderName::from_static("x-effective-day");
+
+/// The English name of `weekday`, matching the names of [`Schoolday`].
+pub fn weekday_name(weekday: Weekday) -> &'static str {
+	match weekday {
+		Weekday::Mon => "Monday",
+		Weekday::Tue => "Tuesday",
+		Weekday::Wed => "Wednesday",
+		Weekday::Thu => "Thursday",
+		Weekday::Fri => "Friday",
+		Weekday::Sat => "Saturday",
+		Weekday::Sun => "Sunday",
+	}
+}
+
+/// Marks a response to a weekend request with the day that was asked for and the one that is served instead.
+pub fn mark_fallback(mut response: HttpResponse, requested: Weekday, effective: Schoolday) -> HttpResponse {
+	let headers = response.headers_mut();
+	headers.insert(REQUESTED_DAY, HeaderValue::from_static(weekday_name(requested)));
+	if let Ok(effective) = HeaderValue::from_str(&effective.to_string()) {
+		headers.insert(EFFECTIVE_DAY, effective);
+	}
+
+	response
+}
+
+/// Answers a weekend request with a pointer to where the next schedule is.
+pub fn not_found(requested: Weekday, location: &str) -> HttpResponse {
+	HttpResponse::NotFound()
+		.insert_header((header::LOCATION, location))
+		.insert_header((REQUESTED_DAY, weekday_name(requested)))
+		.body(format!("There are no schedules for {}, the next one is at {location}", weekday_name(requested)))
+}
@@ -0,0 +1,82 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use bytes::Bytes;
+use chrono::{DateTime, Local};
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use crate::{Schoolday, storage};
+
+/// Schedules kept while the database is unreachable, the oldest is dropped once there are more.
+const MAX_PENDING_WRITES: usize = 64;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+	pub static ref WRITE_QUEUE: WriteQueue = WriteQueue::default();
+}
+
+/// A schedule that couldn't be written because the database was unreachable.
+#[derive(Debug)]
+pub struct PendingWrite {
+	pub day: Schoolday,
+	pub hash: String,
+	pub pdf_date: DateTime<Local>,
+	pub json: serde_json::Value,
+	pub pdf: Option<Bytes>,
+	pub notify: bool,
+}
+
+/// Keeps schedules in memory during a database outage and writes them once it is over.
+#[derive(Debug, Default)]
+pub struct WriteQueue {
+	pending: Mutex<VecDeque<PendingWrite>>,
+}
+
+impl WriteQueue {
+	pub async fn push(&self, write: PendingWrite) {
+		let mut pending = self.pending.lock().await;
+
+		if pending.len() >= MAX_PENDING_WRITES {
+			if let Some(dropped) = pending.pop_front() {
+				warn!("Too many writes are waiting for the database, dropping the schedule {} of {}", dropped.hash, dropped.day);
+			}
+		}
+
+		info!("{}: Queued the schedule {} until the database is reachable again", write.day, write.hash);
+		pending.push_back(write);
+	}
+
+	pub async fn len(&self) -> usize {
+		self.pending.lock().await.len()
+	}
+
+	/// Writes the queued schedules in order, stops at the first one that fails and keeps it and the rest.
+	async fn flush(&self, pool: &PgPool) {
+		let mut pending = self.pending.lock().await;
+
+		while let Some(write) = pending.front() {
+			match storage::save_schedule(pool, write.day, &write.hash, &write.pdf_date, write.json.clone(), write.pdf.as_deref(), write.notify).await {
+				Ok(_) => {
+					info!("{}: Wrote the queued schedule {}", write.day, write.hash);
+					pending.pop_front();
+				}
+				Err(why) if why.is_unavailable() => return,
+				Err(why) => {
+					warn!("{}: Dropping the queued schedule {}, the database rejected it: {why}", write.day, write.hash);
+					pending.pop_front();
+				}
+			}
+		}
+	}
+}
+
+/// Periodically tries to write the queued schedules, once the migrations were applied.
+pub async fn flush_loop(pool: PgPool) {
+	storage::migrated().await;
+
+	loop {
+		tokio::time::sleep(FLUSH_INTERVAL).await;
+		WRITE_QUEUE.flush(&pool).await;
+	}
+}
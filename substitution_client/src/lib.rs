@@ -0,0 +1,124 @@
+//! An async client for the substitution pdf server.
+//! The responses are deserialized into the same types the server serializes them from.
+
+use std::time::Duration;
+use reqwest::StatusCode;
+use thiserror::Error;
+
+pub use substitution_pdf_to_json::{ClassName, Schoolday, SubstitutionColumn, SubstitutionSchedule};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+	#[error("The request failed: {0}")]
+	Request(#[from] reqwest::Error),
+	#[error("The server answered with {0}")]
+	Status(StatusCode),
+	#[error("The base url can't be joined with a path")]
+	InvalidBaseUrl,
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+	http: reqwest::Client,
+	base_url: String,
+}
+
+impl Client {
+	/// Creates a client for the server at `base_url`, e.g. `http://127.0.0.1:8081`.
+	pub fn new<T: Into<String>>(base_url: T) -> Self {
+		Self::with_http_client(reqwest::Client::new(), base_url)
+	}
+
+	/// Same as [`Self::new`], but reuses an existing reqwest client.
+	pub fn with_http_client<T: Into<String>>(http: reqwest::Client, base_url: T) -> Self {
+		let base_url = base_url.into().trim_end_matches('/').to_string();
+
+		Self {
+			http,
+			base_url,
+		}
+	}
+
+	/// Gets the schedule of `day`.
+	/// Returns `None` if the server has no schedule for the day yet.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if the request failed or the server answered with an error.
+	pub async fn get_day(&self, day: Schoolday) -> Result<Option<SubstitutionSchedule>, ClientError> {
+		self.get_schedule(&format!("{}/{day}", self.base_url)).await
+	}
+
+	/// Gets the schedule of a single class, or of every class in a group configured on the server.
+	/// Returns `None` if the server has no schedule for the day yet or the class has no substitutions.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if the request failed or the server answered with an error.
+	pub async fn get_class(&self, day: Schoolday, class: &str) -> Result<Option<SubstitutionSchedule>, ClientError> {
+		// The class is pushed as a path segment, so spaces and slashes in class names are escaped.
+		let mut url = reqwest::Url::parse(&format!("{}/{day}/classes/", self.base_url))
+			.map_err(|_| ClientError::InvalidBaseUrl)?;
+		url.path_segments_mut()
+			.map_err(|_| ClientError::InvalidBaseUrl)?
+			.pop_if_empty()
+			.push(class);
+
+		self.get_schedule(url.as_str()).await
+	}
+
+	/// Watches the schedule of `day`, polling the server every `interval`.
+	pub fn watch_changes(&self, day: Schoolday, interval: Duration) -> Watcher {
+		Watcher {
+			client: self.clone(),
+			day,
+			interval,
+			polled: false,
+			last_struct_time: None,
+		}
+	}
+
+	async fn get_schedule(&self, url: &str) -> Result<Option<SubstitutionSchedule>, ClientError> {
+		let response = self.http.get(url).send().await?;
+
+		match response.status() {
+			StatusCode::NO_CONTENT | StatusCode::NOT_FOUND => Ok(None),
+			status if status.is_success() => Ok(Some(response.json().await?)),
+			status => Err(ClientError::Status(status)),
+		}
+	}
+}
+
+/// Yields the schedule of a day every time the server converted a new pdf for it.
+#[derive(Debug)]
+pub struct Watcher {
+	client: Client,
+	day: Schoolday,
+	interval: Duration,
+	polled: bool,
+	/// Changes every time the server converts a new pdf, so it identifies the schedule.
+	last_struct_time: Option<u64>,
+}
+
+impl Watcher {
+	/// Waits until the schedule differs from the one returned last, the first call returns the current schedule.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if a poll failed, the next call continues watching.
+	pub async fn next(&mut self) -> Result<SubstitutionSchedule, ClientError> {
+		loop {
+			if self.polled {
+				tokio::time::sleep(self.interval).await;
+			}
+			self.polled = true;
+
+			if let Some(schedule) = self.client.get_day(self.day).await? {
+				if self.last_struct_time != Some(schedule.struct_time()) {
+					self.last_struct_time = Some(schedule.struct_time());
+					return Ok(schedule);
+				}
+			}
+		}
+	}
+}
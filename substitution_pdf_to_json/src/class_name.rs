@@ -0,0 +1,321 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+#[cfg(feature = "parse")]
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+
+#[cfg(feature = "parse")]
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The name of a class as it appears in the header of the PDF table.
+/// Orders naturally, so "5A" comes before "10A", which makes maps keyed by it serialize in a stable, human friendly order.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct ClassName(String);
+
+impl ClassName {
+	pub fn new<T: Into<String>>(name: T) -> Self {
+		Self(name.into())
+	}
+
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+}
+
+impl Deref for ClassName {
+	type Target = str;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl From<&str> for ClassName {
+	fn from(name: &str) -> Self {
+		Self::new(name)
+	}
+}
+
+impl From<String> for ClassName {
+	fn from(name: String) -> Self {
+		Self(name)
+	}
+}
+
+impl Display for ClassName {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl Ord for ClassName {
+	fn cmp(&self, other: &Self) -> Ordering {
+		natural_cmp(&self.0, &other.0)
+	}
+}
+
+impl PartialOrd for ClassName {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+/// Compares two strings chunk by chunk, comparing runs of digits by their numeric value and everything else case-insensitively.
+/// Falls back to plain string comparison, so only equal strings compare as equal.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+	let mut a_chunks = Chunks(a);
+	let mut b_chunks = Chunks(b);
+
+	loop {
+		let ordering = match (a_chunks.next(), b_chunks.next()) {
+			(None, None) => return a.cmp(b),
+			(None, Some(_)) => return Ordering::Less,
+			(Some(_), None) => return Ordering::Greater,
+			(Some(a_chunk), Some(b_chunk)) => compare_chunks(a_chunk, b_chunk),
+		};
+
+		if ordering != Ordering::Equal {
+			return ordering;
+		}
+	}
+}
+
+fn compare_chunks(a: &str, b: &str) -> Ordering {
+	let a_is_number = a.starts_with(|c: char| c.is_ascii_digit());
+	let b_is_number = b.starts_with(|c: char| c.is_ascii_digit());
+
+	match (a_is_number, b_is_number) {
+		(true, true) => {
+			let a_trimmed = a.trim_start_matches('0');
+			let b_trimmed = b.trim_start_matches('0');
+			// A longer number without leading zeros is always the bigger one, this avoids overflowing on parse.
+			a_trimmed.len()
+				.cmp(&b_trimmed.len())
+				.then_with(|| a_trimmed.cmp(b_trimmed))
+		}
+		(true, false) => Ordering::Less,
+		(false, true) => Ordering::Greater,
+		(false, false) => a.to_lowercase().cmp(&b.to_lowercase()),
+	}
+}
+
+/// Splits a string into alternating runs of ascii digits and other characters.
+struct Chunks<'a>(&'a str);
+
+impl<'a> Iterator for Chunks<'a> {
+	type Item = &'a str;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let first = self.0.chars().next()?;
+		let is_digit = first.is_ascii_digit();
+		let end = self.0
+			.find(|c: char| c.is_ascii_digit() != is_digit)
+			.unwrap_or(self.0.len());
+
+		let (chunk, rest) = self.0.split_at(end);
+		self.0 = rest;
+		Some(chunk)
+	}
+}
+
+/// How class names from the PDF header are cleaned up before they are used as keys.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct NormalizationConfig {
+	/// Remove leading and trailing whitespace.
+	pub trim: bool,
+	/// Replace every run of whitespace, including line breaks, with a single space.
+	pub collapse_whitespace: bool,
+	/// The casing all names are converted to.
+	pub casing: Casing,
+	/// Regex rewrites applied in order, after the steps above.
+	pub rewrites: Vec<Rewrite>,
+	/// Canonical class names and the names that are served under them instead.
+	/// Aliases are compared after all other normalization steps.
+	pub aliases: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for NormalizationConfig {
+	fn default() -> Self {
+		Self {
+			trim: true,
+			collapse_whitespace: true,
+			casing: Casing::Keep,
+			rewrites: Vec::new(),
+			aliases: BTreeMap::new(),
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Casing {
+	Keep,
+	Upper,
+	Lower,
+}
+
+/// Replaces every match of `pattern` with `replacement`, which may reference capture groups like `$1`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Rewrite {
+	pub pattern: String,
+	pub replacement: String,
+}
+
+/// Applies a [`NormalizationConfig`] to class names.
+#[cfg(feature = "parse")]
+#[derive(Clone, Debug, Default)]
+pub struct ClassNameNormalizer {
+	config: NormalizationConfig,
+	rewrites: Vec<(Regex, String)>,
+	aliases: HashMap<ClassName, ClassName>,
+}
+
+#[cfg(feature = "parse")]
+impl ClassNameNormalizer {
+	/// Compiles the rewrites of the config.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if a rewrite pattern is not a valid regex.
+	pub fn new(config: &NormalizationConfig) -> Result<Self, regex::Error> {
+		let rewrites = config.rewrites
+			.iter()
+			.map(|rewrite| Ok((Regex::new(&rewrite.pattern)?, rewrite.replacement.clone())))
+			.collect::<Result<Vec<_>, regex::Error>>()?;
+
+		let mut normalizer = Self {
+			config: config.clone(),
+			rewrites,
+			aliases: HashMap::new(),
+		};
+
+		for (canonical, aliases) in &config.aliases {
+			let canonical = normalizer.normalize(canonical);
+			for alias in aliases {
+				normalizer.aliases.insert(normalizer.normalize(alias), canonical.clone());
+			}
+		}
+
+		Ok(normalizer)
+	}
+
+	pub fn normalize(&self, name: &str) -> ClassName {
+		let mut name = if self.config.collapse_whitespace {
+			name.split_whitespace().collect::<Vec<&str>>().join(" ")
+		} else {
+			name.to_string()
+		};
+
+		if self.config.trim {
+			name = name.trim().to_string();
+		}
+
+		match self.config.casing {
+			Casing::Keep => {}
+			Casing::Upper => name = name.to_uppercase(),
+			Casing::Lower => name = name.to_lowercase(),
+		}
+
+		for (pattern, replacement) in &self.rewrites {
+			name = pattern.replace_all(&name, replacement.as_str()).into_owned();
+		}
+
+		let name = ClassName(name);
+		match self.aliases.get(&name) {
+			Some(canonical) => canonical.clone(),
+			None => name,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sorted(names: &[&str]) -> Vec<String> {
+		let mut names: Vec<ClassName> = names.iter().map(|name| ClassName::from(*name)).collect();
+		names.sort();
+		names.into_iter().map(|name| name.to_string()).collect()
+	}
+
+	#[test]
+	fn numbers_compare_by_their_value() {
+		assert_eq!(sorted(&["10A", "5A", "EF", "9B", "05C", "Q1"]), ["5A", "05C", "9B", "10A", "EF", "Q1"]);
+	}
+
+	#[test]
+	fn letters_compare_ignoring_case() {
+		assert_eq!(sorted(&["5b", "5A", "5c"]), ["5A", "5b", "5c"]);
+		assert_eq!(ClassName::from("5a").cmp(&ClassName::from("5A")), "5a".cmp("5A"));
+		assert_eq!(ClassName::from("5a").cmp(&ClassName::from("5a")), Ordering::Equal);
+	}
+
+	#[test]
+	fn long_numbers_dont_overflow() {
+		assert_eq!(sorted(&["100000000000000000000001", "99999999999999999999"]), ["99999999999999999999", "100000000000000000000001"]);
+	}
+
+	#[cfg(feature = "parse")]
+	fn normalizer(config: NormalizationConfig) -> ClassNameNormalizer {
+		ClassNameNormalizer::new(&config).unwrap()
+	}
+
+	#[cfg(feature = "parse")]
+	#[test]
+	fn whitespace_is_trimmed_and_collapsed() {
+		let normalizer = normalizer(NormalizationConfig::default());
+		assert_eq!(normalizer.normalize("  10\n  A "), ClassName::from("10 A"));
+	}
+
+	#[cfg(feature = "parse")]
+	#[test]
+	fn casing_is_applied() {
+		let normalizer = normalizer(NormalizationConfig {
+			casing: Casing::Upper,
+			..NormalizationConfig::default()
+		});
+		assert_eq!(normalizer.normalize("10a"), ClassName::from("10A"));
+	}
+
+	#[cfg(feature = "parse")]
+	#[test]
+	fn rewrites_are_applied_in_order() {
+		let normalizer = normalizer(NormalizationConfig {
+			rewrites: vec![
+				Rewrite { pattern: r"^(\d+) ([A-Z])$".to_string(), replacement: "$1$2".to_string() },
+				Rewrite { pattern: "^10".to_string(), replacement: "EF".to_string() },
+			],
+			..NormalizationConfig::default()
+		});
+		assert_eq!(normalizer.normalize("10 A"), ClassName::from("EFA"));
+		assert_eq!(normalizer.normalize("9 B"), ClassName::from("9B"));
+	}
+
+	#[cfg(feature = "parse")]
+	#[test]
+	fn invalid_rewrites_are_rejected() {
+		let config = NormalizationConfig {
+			rewrites: vec![Rewrite { pattern: "(".to_string(), replacement: String::new() }],
+			..NormalizationConfig::default()
+		};
+		assert!(ClassNameNormalizer::new(&config).is_err());
+	}
+
+	#[cfg(feature = "parse")]
+	#[test]
+	fn aliases_are_served_under_the_canonical_name() {
+		let normalizer = normalizer(NormalizationConfig {
+			aliases: BTreeMap::from([("BGT11-1".to_string(), vec!["BGT 11/1".to_string(), " bgt  11-1".to_string()])]),
+			casing: Casing::Upper,
+			..NormalizationConfig::default()
+		});
+		assert_eq!(normalizer.normalize("BGT 11/1"), ClassName::from("BGT11-1"));
+		assert_eq!(normalizer.normalize("bgt 11-1"), ClassName::from("BGT11-1"));
+		assert_eq!(normalizer.normalize("BGT11-2"), ClassName::from("BGT11-2"));
+	}
+}
@@ -0,0 +1,175 @@
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::Path;
+use std::str;
+use std::time::Instant;
+use lopdf::Document;
+use tokio::process::Command;
+use tracing::debug;
+#[cfg(feature = "ocr")]
+use crate::ocr;
+use crate::{ConversionTimings, PDFJsonError, SubstitutionSchedule, Table};
+use crate::tabula::{parse_issue_date, parse_tabula_pages};
+
+/// The tabula jar the pdfs are converted with, relative to the working directory.
+pub const TABULA_JAR: &str = "./tabula/tabula.jar";
+
+impl SubstitutionSchedule {
+	/// Constructs an instance of `Self` from a document saved on disk.
+	/// Tabula is run as a child process without blocking the async runtime.
+	/// Only the first day is returned if the document covers several, see [`Self::from_pdf_bytes_timed`].
+	pub async fn from_pdf<T: AsRef<Path> + AsRef<OsStr>>(path: T) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+		let pdf = match Document::load(&path) {
+			Ok(pdf) => pdf,
+			Err(_) => return Err(Box::new(PDFJsonError::PDFReadError)),
+		};
+		let sections = get_issue_dates(&pdf)?;
+
+		let path: &Path = path.as_ref();
+		let tables = run_tabula(path).await?;
+
+		Ok(Self::from_sections(&tables, &sections).swap_remove(0))
+	}
+
+	/// Constructs an instance of `Self` from the raw bytes of a document.
+	/// Tabula can only read from disk, so the bytes are staged in a temp file that is removed when this returns.
+	/// Only the first day is returned if the document covers several, see [`Self::from_pdf_bytes_timed`].
+	pub async fn from_pdf_bytes(pdf: &[u8]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+		Self::from_pdf_bytes_in(pdf, std::env::temp_dir()).await
+	}
+
+	/// Same as [`Self::from_pdf_bytes`], but stages the temp file inside `temp_dir`.
+	pub async fn from_pdf_bytes_in<T: AsRef<Path>>(pdf: &[u8], temp_dir: T) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+		let (mut schedules, _) = Self::from_pdf_bytes_timed(pdf, temp_dir).await?;
+		Ok(schedules.swap_remove(0))
+	}
+
+	/// Converts every day the document covers, in the order of the document, and reports how long each conversion step took.
+	/// A new day starts on every page with a `Datum:` line, e.g. when the school publishes one PDF for the days before holidays.
+	/// With the `ocr` feature, scans without any text are read with tesseract instead of tabula.
+	/// The result is never empty.
+	pub async fn from_pdf_bytes_timed<T: AsRef<Path>>(pdf: &[u8], temp_dir: T) -> Result<(Vec<Self>, ConversionTimings), Box<dyn std::error::Error + Send + Sync>> {
+		let parse_start = Instant::now();
+		let sections = match Document::load_mem(pdf) {
+			Ok(document) => get_issue_dates(&document),
+			Err(_) => return Err(Box::new(PDFJsonError::PDFReadError)),
+		};
+		let mut parse = parse_start.elapsed();
+
+		let mut temp_file = tempfile::Builder::new()
+			.suffix(".pdf")
+			.tempfile_in(&temp_dir)?;
+		temp_file.write_all(pdf)?;
+		temp_file.flush()?;
+
+		let sections = match sections {
+			Ok(sections) => sections,
+			#[cfg(feature = "ocr")]
+			Err(why) if matches!(why.downcast_ref(), Some(PDFJsonError::NoText)) => {
+				debug!("The PDF has no text, reading it with OCR");
+				let ocr_start = Instant::now();
+				let ocr_dir = tempfile::Builder::new().tempdir_in(&temp_dir)?;
+				let scan = ocr::read_scan(temp_file.path(), ocr_dir.path()).await?;
+				let tabula = ocr_start.elapsed();
+
+				let mut schedules = Self::from_sections(&scan.tables, &scan.sections);
+				for schedule in &mut schedules {
+					schedule.ocr_confidence = Some(scan.confidence);
+					schedule.warnings.push(format!("The PDF is a scan and was read with OCR, {:.0}% confidence", scan.confidence));
+				}
+				return Ok((schedules, ConversionTimings { tabula, parse }));
+			}
+			Err(why) => return Err(why),
+		};
+
+		let tabula_start = Instant::now();
+		let output = call_tabula(temp_file.path()).await?;
+		let tabula = tabula_start.elapsed();
+
+		let parse_start = Instant::now();
+		let tables = parse_tabula_pages(str::from_utf8(&output)?)?;
+		let schedules = Self::from_sections(&tables, &sections);
+		parse += parse_start.elapsed();
+
+		Ok((schedules, ConversionTimings { tabula, parse }))
+	}
+}
+
+/// Counts the pages of the pdf without converting it, to reject documents that are too large beforehand.
+///
+/// # Errors
+///
+/// Returns `Err` if the bytes aren't a readable pdf.
+pub fn page_count(pdf: &[u8]) -> Result<usize, PDFJsonError> {
+	match Document::load_mem(pdf) {
+		Ok(document) => Ok(document.get_pages().len()),
+		Err(_) => Err(PDFJsonError::PDFReadError),
+	}
+}
+
+/// Gets all pages from the pdf document.
+fn get_all_page_numbers(pdf: &Document) -> Box<[u32]> {
+	let pages = pdf
+		.get_pages()
+		.keys()
+		.copied()
+		.collect::<Box<[u32]>>();
+
+	pages
+}
+
+/// Reads the dates from the `Datum:` lines of the document and returns them as milliseconds since the epoch,
+/// together with the page each of them is on. Only the first date of a page counts.
+fn get_issue_dates(pdf: &Document) -> Result<Vec<(u32, i64)>, Box<dyn std::error::Error + Send + Sync>> {
+	let mut sections = Vec::new();
+	let mut has_text = false;
+	for page in get_all_page_numbers(pdf).iter() {
+		let text = pdf.extract_text(&[*page])?;
+		has_text |= !text.trim().is_empty();
+		if text.contains("Datum: ") {
+			sections.push((*page, parse_issue_date(&text)?));
+		}
+	}
+
+	if !has_text {
+		return Err(Box::new(PDFJsonError::NoText));
+	}
+	if sections.is_empty() {
+		return Err("date not found".into());
+	}
+
+	Ok(sections)
+}
+
+/// Runs tabula on the document at `path` and returns the extracted tables with the pages they are on.
+async fn run_tabula(path: &Path) -> Result<Vec<(u32, Table)>, Box<dyn std::error::Error + Send + Sync>> {
+	let output = call_tabula(path).await?;
+
+	debug!("Parsing tabulas json");
+	parse_tabula_pages(str::from_utf8(&output)?)
+}
+
+/// Runs tabula on the document at `path` and returns its raw json output.
+async fn call_tabula(path: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+	debug!("Calling tabula");
+	let output = Command::new("java")
+		.arg("-jar")
+		.arg(TABULA_JAR)
+		.arg("-g")
+		.arg("-f")
+		.arg("JSON")
+		.arg("-p")
+		.arg("all")
+		.arg(path)
+		.output()
+		.await?;
+
+	if !output.status.success() {
+		return Err(Box::new(PDFJsonError::TabulaError {
+			status: output.status,
+			stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+		}));
+	}
+
+	Ok(output.stdout)
+}
@@ -0,0 +1,132 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Phrases marking a line as a cancelled lesson, compared in lower case.
+/// PDFs written without umlauts spell them "ae".
+pub const CANCELLATION_MARKERS: [&str; 5] = ["entfällt", "fällt aus", "entfaellt", "faellt aus", "---"];
+
+lazy_static! {
+	/// Teacher abbreviations are written in capitals, like "MÜL" or "SCH".
+	/// Only the ones right before or after "vertritt", "für" and "statt" are taken, like in "MÜL vertritt SCH",
+	/// other words in capitals are mostly subjects and groups like "EN", "AG" or "IT".
+	static ref TEACHER_PATTERNS: [Regex; 2] = [
+		Regex::new(r"\b([A-ZÄÖÜ]{2,4})\s+(?:vertritt|für|statt)\b").unwrap(),
+		Regex::new(r"\b(?:vertritt|für|statt)\s+([A-ZÄÖÜ]{2,4})\b").unwrap(),
+	];
+	/// Rooms are either prefixed with "R"/"Raum", or a number with at least three digits and an optional building letter.
+	static ref ROOM_PATTERN: Regex = Regex::new(r"\bR(?:aum)?\.?\s*([A-Z]?\d+(?:\.\d+)?[a-z]?)\b|\b([A-Z]?\d{3}[a-z]?)\b").unwrap();
+}
+
+/// One line of a block, with the teachers and rooms found in it.
+/// The fields are extracted heuristically, `text` always holds the line as it was in the PDF, with its whitespace collapsed.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+pub struct BlockEntry {
+	/// The index of the block the line is in.
+	pub block: usize,
+	/// The index of the line within the block, every line is a row of the table.
+	pub line: usize,
+	pub text: String,
+	/// The abbreviations of all teachers mentioned in the line.
+	pub teachers: Vec<String>,
+	/// All rooms mentioned in the line.
+	pub rooms: Vec<String>,
+	/// Whether the line says the lesson is cancelled, like "Mathe entfällt".
+	pub cancelled: bool,
+}
+
+impl BlockEntry {
+	/// Parses a line of a block, its whitespace is collapsed to single spaces.
+	pub fn parse(block: usize, line: usize, text: &str) -> Self {
+		let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+		let mut teachers: Vec<(usize, &str)> = TEACHER_PATTERNS.iter()
+			.flat_map(|pattern| pattern.captures_iter(&text))
+			.filter_map(|captures| captures.get(1))
+			.map(|teacher| (teacher.start(), teacher.as_str()))
+			.collect();
+		teachers.sort_unstable();
+		teachers.dedup();
+		let teachers = teachers.into_iter().map(|(_, teacher)| teacher.to_string()).collect();
+
+		let rooms = ROOM_PATTERN
+			.captures_iter(&text)
+			.filter_map(|captures| captures.get(1).or_else(|| captures.get(2)))
+			.map(|room| room.as_str().to_string())
+			.collect();
+
+		let lowercase = text.to_lowercase();
+		let cancelled = CANCELLATION_MARKERS.iter().any(|marker| lowercase.contains(marker));
+
+		Self {
+			block,
+			line,
+			text,
+			teachers,
+			rooms,
+			cancelled,
+		}
+	}
+
+	/// Checks if `teacher` is mentioned in the line, ignoring case.
+	pub fn involves_teacher(&self, teacher: &str) -> bool {
+		self.teachers
+			.iter()
+			.any(|candidate| candidate.to_lowercase() == teacher.to_lowercase())
+	}
+
+	/// Checks if `room` is mentioned in the line, ignoring case.
+	pub fn involves_room(&self, room: &str) -> bool {
+		self.rooms
+			.iter()
+			.any(|candidate| candidate.to_lowercase() == room.to_lowercase())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn finds_the_rooms_of_a_line() {
+		let entry = BlockEntry::parse(0, 0, "Mathe: Herr Mueller vertritt Frau Schmidt in Raum 104");
+
+		assert_eq!(entry.text, "Mathe: Herr Mueller vertritt Frau Schmidt in Raum 104");
+		assert!(entry.teachers.is_empty());
+		assert_eq!(entry.rooms, ["104"]);
+		assert!(!entry.cancelled);
+		assert_eq!(BlockEntry::parse(1, 1, "Chemie in Raum 3").rooms, ["3"]);
+	}
+
+	#[test]
+	fn finds_the_teachers_around_vertritt() {
+		let entry = BlockEntry::parse(2, 0, "EN MÜL vertritt SCH in R 204");
+
+		assert_eq!(entry.teachers, ["MÜL", "SCH"]);
+		assert_eq!(entry.rooms, ["204"]);
+		assert_eq!(BlockEntry::parse(2, 0, "IT WEB für KRA").teachers, ["WEB", "KRA"]);
+	}
+
+	#[test]
+	fn subjects_in_capitals_are_no_teachers() {
+		assert!(BlockEntry::parse(3, 0, "AG IT entfällt").teachers.is_empty());
+		assert!(BlockEntry::parse(3, 0, "Latein: Frau Weber vertritt").teachers.is_empty());
+	}
+
+	#[test]
+	fn marks_cancelled_lessons() {
+		assert!(BlockEntry::parse(1, 0, "Physik entfällt").cancelled);
+		assert!(BlockEntry::parse(1, 0, "Sport fällt aus").cancelled);
+		assert!(BlockEntry::parse(1, 0, "Sport faellt aus").cancelled);
+		assert!(BlockEntry::parse(1, 0, "---").cancelled);
+		assert!(!BlockEntry::parse(1, 1, "Chemie in Raum 3").cancelled);
+	}
+
+	#[test]
+	fn collapses_the_whitespace_of_wrapped_cells() {
+		let entry = BlockEntry::parse(0, 0, "Mathe: Herr Mueller vertritt\rFrau Schmidt  in Raum 104");
+
+		assert_eq!(entry.text, "Mathe: Herr Mueller vertritt Frau Schmidt in Raum 104");
+	}
+}
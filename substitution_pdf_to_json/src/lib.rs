@@ -1,20 +1,43 @@
-use std::collections::HashMap;
-use std::ffi::OsStr;
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
-use std::path::Path;
-use std::process::Command;
-use std::str;
-use std::time::SystemTime;
+use std::process::ExitStatus;
+use std::time::Duration;
 use thiserror::Error;
 
-use chrono::{Local, NaiveDate, Offset, Utc};
-use lopdf::Document;
+pub use class_name::{Casing, ClassName, NormalizationConfig, Rewrite};
+pub use schoolday::Schoolday;
+
+#[cfg(feature = "parse")]
+pub use class_name::ClassNameNormalizer;
+#[cfg(feature = "parse")]
+pub use entry::{BlockEntry, CANCELLATION_MARKERS};
+#[cfg(feature = "parse")]
+pub use tabula::{parse_tabula_json, parse_tabula_pages};
+#[cfg(all(feature = "convert", not(target_arch = "wasm32")))]
+pub use convert::{page_count, TABULA_JAR};
+
+mod class_name;
+// Running tabula and reading the PDFs needs processes and a file system, which wasm32 doesn't have.
+// Everything else only turns tabulas json into schedules and compiles to wasm32 as well.
+#[cfg(all(feature = "convert", not(target_arch = "wasm32")))]
+mod convert;
+#[cfg(feature = "parse")]
+mod entry;
+#[cfg(all(feature = "ocr", not(target_arch = "wasm32")))]
+mod ocr;
+mod schoolday;
+#[cfg(feature = "parse")]
+mod tabula;
+
+use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use tracing::{debug};
+
+/// The rows of a table tabula extracted, each as the texts of its cells.
+pub type Table = Vec<Vec<String>>;
 
 /// One column with Substitutions from the PDF
-#[derive(Serialize, Deserialize, PartialOrd, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, PartialOrd, PartialEq, Clone, Debug)]
 pub struct SubstitutionColumn {
 	#[serde(rename(serialize = "0"))]
 	#[serde(rename(deserialize = "0"))]
@@ -55,6 +78,72 @@ impl SubstitutionColumn {
 			block_5: None,
 		}
 	}
+
+	/// The blocks that have substitutions, with their index.
+	pub fn blocks(&self) -> impl Iterator<Item = (usize, &str)> {
+		[
+			&self.block_0,
+			&self.block_1,
+			&self.block_2,
+			&self.block_3,
+			&self.block_4,
+			&self.block_5,
+		]
+			.into_iter()
+			.enumerate()
+			.filter_map(|(idx, block)| block.as_deref().map(|block| (idx, block)))
+	}
+
+	/// Splits every block into its lines and extracts their structured fields.
+	/// A wrapped cell is a single line, its line breaks are carriage returns.
+	#[cfg(feature = "parse")]
+	pub fn block_entries(&self) -> Vec<BlockEntry> {
+		self.blocks()
+			.flat_map(|(idx, block)| {
+				block
+					.lines()
+					.filter(|line| !line.trim().is_empty())
+					.enumerate()
+					.map(move |(line, text)| BlockEntry::parse(idx, line, text))
+			})
+			.collect()
+	}
+
+	/// Appends `line` to the block with the index `block`, separated by a line break, so every row of the table is a line of the block.
+	/// The text is kept as it is, the line breaks of a wrapped cell are only joined by [`Self::block_entries`].
+	pub fn push_line(&mut self, block: usize, line: &str) {
+		let block = match self.block_mut(block) {
+			Some(block) => block,
+			None => return,
+		};
+
+		match block {
+			Some(block) => {
+				block.push('\n');
+				block.push_str(line);
+			}
+			None => *block = Some(line.to_string()),
+		}
+	}
+
+	/// Appends the blocks of `other` to the blocks of `self`, separated by a line break.
+	pub fn merge(&mut self, other: Self) {
+		for (idx, block) in other.blocks() {
+			self.push_line(idx, block);
+		}
+	}
+
+	fn block_mut(&mut self, idx: usize) -> Option<&mut Option<String>> {
+		match idx {
+			0 => Some(&mut self.block_0),
+			1 => Some(&mut self.block_1),
+			2 => Some(&mut self.block_2),
+			3 => Some(&mut self.block_3),
+			4 => Some(&mut self.block_4),
+			5 => Some(&mut self.block_5),
+			_ => None,
+		}
+	}
 }
 
 impl Default for SubstitutionColumn {
@@ -69,218 +158,152 @@ impl Display for SubstitutionColumn {
 	}
 }
 
+/// How long the individual steps of a conversion took.
+#[derive(Debug, Clone, Copy)]
+pub struct ConversionTimings {
+	/// Time spent waiting for the tabula process.
+	pub tabula: Duration,
+	/// Time spent reading the issue date and turning tabulas json into the schedule.
+	pub parse: Duration,
+}
+
+/// The version of the converter, to tell which release produced a schedule.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 /// Contains the extracted PDF data of the schedule PDF
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct SubstitutionSchedule {
 	/// The creation date inside the PDF in milliseconds.
 	pub pdf_issue_date: i64,
 	/// The name of the class is the Key and the Value is a Substitutions struct.
-	entries: HashMap<String, SubstitutionColumn>,
+	/// Sorted naturally by class name, so the serialized output is the same for the same content.
+	entries: BTreeMap<ClassName, SubstitutionColumn>,
 	/// The time when the struct was created, used for comparing the age.
 	struct_time: u64,
+	/// The calendar date the schedule applies to, the first date of its school day on or after the date inside the PDF.
+	/// Only known once the schedule was assigned to a day.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[schemars(with = "Option<String>")]
+	effective_date: Option<NaiveDate>,
+	/// Whether the date inside the PDF isn't on the school day it is served for,
+	/// which usually means the school hasn't uploaded the new PDF for that day yet.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	issue_date_mismatch: bool,
+	/// The mean confidence of the OCR in percent, if the PDF was a scan without text and had to be read with OCR.
+	/// The lower it is, the more likely the text contains misread characters.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	ocr_confidence: Option<f32>,
+	/// Problems with the PDF that didn't stop the conversion but might make the data wrong.
+	#[serde(skip)]
+	warnings: Vec<String>,
 }
 
 impl SubstitutionSchedule {
-	/// Constructs an instance of `Self` from a document saved on disk.
-	pub fn from_pdf<T: AsRef<Path> + AsRef<OsStr>>(path: T) -> Result<Self, Box<dyn std::error::Error>> {
-		let pdf = match Document::load(&path) {
-			Ok(pdf) => pdf,
-			Err(_) => return Err(Box::new(PDFJsonError::PDFReadError)),
-		};
-
-		let page_numbers = get_all_page_numbers(&pdf);
-		let pdf = pdf.extract_text(&*page_numbers)?;
-
-		let date_idx_start = pdf.find("Datum: ").ok_or("date not found")?;
-		let date_idx_end = pdf[date_idx_start..].find('\n').ok_or("date end not found")? + date_idx_start;
-
-		let date_str: Vec<u32> = pdf[date_idx_start..date_idx_end].split(", ")
-			.last()
-			.ok_or("date string has no ','")?
-			.split('.')
-			.collect::<Vec<&str>>()
-			.iter()
-			.map(|s| (*s).parse::<u32>().unwrap())
-			.collect();
-
-		#[allow(clippy::cast_possible_wrap)]
-			let date = chrono::Date::<Local>::from_utc(
-			NaiveDate::from_ymd(date_str[2] as i32, date_str[1], date_str[0]),
-			Utc.fix(),
-		).and_hms_milli(0, 0, 0, 0).timestamp_millis();
-
-		debug!("Calling tabula");
-		let output = Command::new("java")
-			.arg("-jar")
-			.arg("./tabula/tabula.jar")
-			.arg("-g")
-			.arg("-f")
-			.arg("JSON")
-			.arg("-p")
-			.arg("all")
-			.arg(path)
-			.output()?;
-
-		debug!("Parsing tabulas json");
-		let table = parse_tabula_json(str::from_utf8(&output.stdout).unwrap())?;
-
-		Ok(Self::from_table(&table, date))
+	/// The time when the struct was created in milliseconds since the epoch.
+	pub fn struct_time(&self) -> u64 {
+		self.struct_time
 	}
 
-	/// Constructs an instance of `Self` from a table.
-	#[allow(clippy::ptr_arg)]
-	pub fn from_table(tables: &Vec<Vec<Vec<String>>>, pdf_create_date: i64) -> Self {
-		let mut entries = HashMap::new();
-
-		for table in tables {
-			entries.extend(Self::table_to_substitutions(table));
-		}
-
-		let time_now = SystemTime::now();
-		let since_the_epoch = time_now
-			.duration_since(SystemTime::UNIX_EPOCH)
-			.expect("Time got fucked");
-
-		#[allow(clippy::cast_possible_truncation)]
-			let time_millis = since_the_epoch.as_millis() as u64;
-
-		Self {
-			pdf_issue_date: pdf_create_date,
-			entries,
-			struct_time: time_millis,
-		}
+	/// Problems found while converting the PDF.
+	pub fn warnings(&self) -> &[String] {
+		&self.warnings
 	}
 
-	/// Grabs the classes and their substitutions from a table and turns them into a HashMap.
-	#[allow(clippy::ptr_arg)]
-	fn table_to_substitutions(table: &Vec<Vec<String>>) -> HashMap<String, SubstitutionColumn> {
-		let mut entries: HashMap<String, SubstitutionColumn> = HashMap::new();
-
-		let classes = &table[0][1..];
-
-		for class in classes {
-			entries.insert(class.to_string(), SubstitutionColumn::new());
-		}
-
-		let mut row = 1;
-
-		for lesson_idx in 0..5 {
-			loop {
-				for (i, substitution_part) in table[row][1..].iter().enumerate() {
-					let substitutions = entries.get_mut(&classes[i]).unwrap();
-
-					let block_option = match lesson_idx {
-						0 => &mut substitutions.block_0,
-						1 => &mut substitutions.block_1,
-						2 => &mut substitutions.block_2,
-						3 => &mut substitutions.block_3,
-						4 => &mut substitutions.block_4,
-						5 => &mut substitutions.block_5,
-						_ => panic!("more then 5 lessons used"),
-					};
-
-					if !substitution_part.is_empty() {
-						if let Some(block) = block_option {
-							block.push_str(&format!("\n{}", substitution_part.clone()));
-						} else {
-							let _ = block_option.insert(substitution_part.clone());
-						}
-					}
-				}
+	/// The calendar date the schedule applies to, if it was assigned to a day.
+	pub fn effective_date(&self) -> Option<NaiveDate> {
+		self.effective_date
+	}
 
-				if table[row][0].starts_with('-') {
-					break;
-				}
-				row += 1;
-			}
+	/// The school day the PDF is dated on, or `None` if it is dated on a weekend.
+	pub fn issue_day(&self) -> Option<Schoolday> {
+		Utc.timestamp_millis_opt(self.pdf_issue_date)
+			.single()
+			.and_then(|issued| Schoolday::from_weekday(issued.weekday()))
+	}
 
-			row += 1;
-		}
+	/// The mean confidence of the OCR in percent, or `None` if the PDF had text and didn't need OCR.
+	pub fn ocr_confidence(&self) -> Option<f32> {
+		self.ocr_confidence
+	}
 
-		entries
+	/// Whether the date inside the PDF isn't on the school day the schedule was assigned to.
+	pub fn issue_date_mismatch(&self) -> bool {
+		self.issue_date_mismatch
 	}
-}
 
-/// Gets all pages from the pdf document.
-fn get_all_page_numbers(pdf: &Document) -> Box<[u32]> {
-	let pages = pdf
-		.get_pages()
-		.keys()
-		.map(|page| *page)
-		.collect::<Box<[u32]>>();
+	/// Assigns the schedule to the school day it is served for, which determines its effective date
+	/// and whether the date inside the PDF matches the day.
+	/// A PDF for Monday issued on a Friday applies to the Monday after that Friday.
+	pub fn assign_day(&mut self, day: Schoolday) {
+		let issued = match Utc.timestamp_millis_opt(self.pdf_issue_date).single() {
+			Some(issued) => issued.date_naive(),
+			None => return,
+		};
 
-	pages
-}
+		let days_ahead = (day as i64 - i64::from(issued.weekday().num_days_from_monday())).rem_euclid(7);
+		self.effective_date = Some(issued + chrono::Duration::days(days_ahead));
 
-/// Extracts the text from the rows and cells in the json that gets outputted by tabula.
-pub fn parse_tabula_json(content: &str) -> Result<Vec<Vec<Vec<String>>>, Box<dyn std::error::Error>> {
-	let json: Value = serde_json::from_str(content)?;
-	let array = json.as_array().ok_or("Json malformed")?;
-
-	let mut tables = Vec::new();
-	for entry in array {
-		let object = entry.as_object().ok_or("Json malformed")?;
-		let data = object.get("data").ok_or("Json data field missing")?;
-
-		let mut table_rows = Vec::new();
-		for row in data.as_array().ok_or("Json data missing")? {
-			let row: Vec<Cell> = serde_json::from_value(row.clone())?;
-			let row = Row {
-				row
-			};
-			table_rows.push(row);
+		self.issue_date_mismatch = days_ahead != 0;
+		if self.issue_date_mismatch {
+			self.warnings.push(format!("The PDF is dated {issued}, which is not a {day}"));
 		}
-		tables.push(table_rows);
 	}
 
-	let mut tables_with_rows_as_text = Vec::new();
-	for table_rows in tables {
-		let mut rows_as_text = Vec::new();
-		for mut row in table_rows {
-			rows_as_text.push(row.extract_text());
-		}
-		tables_with_rows_as_text.push(rows_as_text);
+	/// The substitutions of every class, keyed by class name.
+	pub fn entries(&self) -> &BTreeMap<ClassName, SubstitutionColumn> {
+		&self.entries
 	}
 
-	Ok(tables_with_rows_as_text)
-}
-
-/// A row in the substitution table
-#[derive(Debug, Deserialize, Serialize)]
-struct Row {
-	row: Vec<Cell>,
-}
+	/// Returns a copy of `self` that only contains the classes for which `keep` returns true.
+	#[must_use]
+	pub fn filter_classes<F: FnMut(&ClassName) -> bool>(&self, mut keep: F) -> Self {
+		let entries = self.entries
+			.iter()
+			.filter(|(class, _)| keep(class))
+			.map(|(class, column)| (class.clone(), column.clone()))
+			.collect();
 
-impl Row {
-	/// Gets the string content of every Cell inside the Row
-	pub fn extract_text(&mut self) -> Vec<String> {
-		let mut text = Vec::new();
-		for cell in &self.row {
-			text.push(cell.text.clone());
+		Self {
+			pdf_issue_date: self.pdf_issue_date,
+			entries,
+			struct_time: self.struct_time,
+			effective_date: self.effective_date,
+			issue_date_mismatch: self.issue_date_mismatch,
+			ocr_confidence: self.ocr_confidence,
+			warnings: self.warnings.clone(),
 		}
-
-		text
 	}
-}
-
-/// A cell in the substitution table
-#[derive(Debug, Deserialize, Serialize)]
-struct Cell {
-	top: f64,
-	left: f64,
-	width: f64,
-	height: f64,
-	text: String,
-}
 
-impl Display for Cell {
-	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		write!(f, "{}", self.text)
+	/// Re-keys the entries with normalized class names.
+	/// Columns whose names normalize to the same class are merged.
+	#[cfg(feature = "parse")]
+	pub fn normalize_class_names(&mut self, normalizer: &ClassNameNormalizer) {
+		let entries = std::mem::take(&mut self.entries);
+
+		for (class, column) in entries {
+			let normalized = normalizer.normalize(&class);
+			match self.entries.get_mut(&normalized) {
+				Some(existing) => {
+					self.warnings.push(format!("Class {class} was merged into {normalized}"));
+					existing.merge(column);
+				}
+				None => {
+					self.entries.insert(normalized, column);
+				}
+			}
+		}
 	}
 }
 
 #[derive(Error, Debug)]
 pub enum PDFJsonError {
 	#[error("There was an error while reading the PDF File.")]
-	PDFReadError
+	PDFReadError,
+	#[error("The PDF contains no text, it is probably a scan.")]
+	NoText,
+	#[error("Tabula exited with {status}: {stderr}")]
+	TabulaError {
+		status: ExitStatus,
+		stderr: String,
+	},
 }
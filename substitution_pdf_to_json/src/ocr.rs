@@ -0,0 +1,216 @@
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::debug;
+use crate::{PDFJsonError, Table};
+use crate::tabula::parse_issue_date;
+
+/// Resolution the pages are rendered at before they are read, tesseract works best at around 300 dpi.
+const RENDER_DPI: &str = "300";
+
+/// The tables and dates read from a scanned document.
+pub(crate) struct ScannedDocument {
+	/// The tables with the pages they are on.
+	pub(crate) tables: Vec<(u32, Table)>,
+	/// The first page and date of every day, ordered by page.
+	pub(crate) sections: Vec<(u32, i64)>,
+	/// The mean confidence of tesseract in the words it read, in percent.
+	pub(crate) confidence: f32,
+}
+
+/// A word tesseract read, with its bounding box in pixels.
+struct Word {
+	left: u32,
+	top: u32,
+	width: u32,
+	height: u32,
+	text: String,
+}
+
+impl Word {
+	fn center_x(&self) -> u32 {
+		self.left + self.width / 2
+	}
+
+	fn center_y(&self) -> u32 {
+		self.top + self.height / 2
+	}
+}
+
+/// Renders every page of the document at `path` with `pdftoppm` and reads it with `tesseract`.
+/// The rendered pages are written to `temp_dir`.
+pub(crate) async fn read_scan(path: &Path, temp_dir: &Path) -> Result<ScannedDocument, Box<dyn std::error::Error + Send + Sync>> {
+	let pages = render_pages(path, temp_dir).await?;
+
+	let mut tables = Vec::new();
+	let mut sections = Vec::new();
+	let mut confidence_sum = 0.0;
+	let mut word_count = 0_u32;
+
+	for (page, image) in pages {
+		let tsv = call_tesseract(&image).await?;
+		let (words, confidences) = parse_tsv(&tsv);
+		confidence_sum += confidences.iter().sum::<f32>();
+		#[allow(clippy::cast_possible_truncation)]
+		{
+			word_count += confidences.len() as u32;
+		}
+
+		let rows = group_rows(words);
+		let text = rows.iter()
+			.map(|row| row.iter().map(|word| word.text.as_str()).collect::<Vec<_>>().join(" "))
+			.collect::<Vec<_>>()
+			.join("\n") + "\n";
+		if text.contains("Datum: ") {
+			sections.push((page, parse_issue_date(&text)?));
+		}
+
+		if let Some(table) = reconstruct_table(rows) {
+			tables.push((page, table));
+		}
+	}
+
+	if sections.is_empty() {
+		return Err(Box::new(PDFJsonError::NoText));
+	}
+
+	#[allow(clippy::cast_precision_loss)]
+	let confidence = if word_count == 0 { 0.0 } else { confidence_sum / word_count as f32 };
+
+	Ok(ScannedDocument {
+		tables,
+		sections,
+		confidence,
+	})
+}
+
+/// Renders the pages to png files and returns them with their page numbers, in order.
+async fn render_pages(path: &Path, temp_dir: &Path) -> Result<Vec<(u32, PathBuf)>, Box<dyn std::error::Error + Send + Sync>> {
+	debug!("Rendering the scanned pages");
+	let prefix = temp_dir.join("page");
+	let output = Command::new("pdftoppm")
+		.arg("-r")
+		.arg(RENDER_DPI)
+		.arg("-png")
+		.arg(path)
+		.arg(&prefix)
+		.output()
+		.await?;
+
+	if !output.status.success() {
+		return Err(format!("pdftoppm exited with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()).into());
+	}
+
+	// The files are named like `page-1.png`, zero padded to the length of the last page number.
+	let mut pages = Vec::new();
+	let mut entries = tokio::fs::read_dir(temp_dir).await?;
+	while let Some(entry) = entries.next_entry().await? {
+		let path = entry.path();
+		let page = path.file_stem()
+			.and_then(|stem| stem.to_str())
+			.and_then(|stem| stem.strip_prefix("page-"))
+			.and_then(|number| number.parse().ok());
+		if let Some(page) = page {
+			pages.push((page, path));
+		}
+	}
+	pages.sort_unstable_by_key(|(page, _)| *page);
+
+	Ok(pages)
+}
+
+/// Runs tesseract on the image and returns its tsv output.
+async fn call_tesseract(image: &Path) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+	debug!("Calling tesseract on {}", image.display());
+	let output = Command::new("tesseract")
+		.arg(image)
+		.arg("stdout")
+		.arg("-l")
+		.arg("deu")
+		.arg("tsv")
+		.output()
+		.await?;
+
+	if !output.status.success() {
+		return Err(format!("tesseract exited with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()).into());
+	}
+
+	Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Reads the words and their confidences from tesseracts tsv output.
+/// The columns are `level page_num block_num par_num line_num word_num left top width height conf text`.
+fn parse_tsv(tsv: &str) -> (Vec<Word>, Vec<f32>) {
+	let mut words = Vec::new();
+	let mut confidences = Vec::new();
+
+	for line in tsv.lines().skip(1) {
+		let columns: Vec<&str> = line.split('\t').collect();
+		if columns.len() < 12 || columns[0] != "5" || columns[11].trim().is_empty() {
+			continue;
+		}
+
+		let number = |idx: usize| columns[idx].parse::<u32>().ok();
+		if let (Some(left), Some(top), Some(width), Some(height)) = (number(6), number(7), number(8), number(9)) {
+			if let Ok(confidence) = columns[10].parse::<f32>() {
+				confidences.push(confidence);
+			}
+			words.push(Word {
+				left,
+				top,
+				width,
+				height,
+				text: columns[11].trim().to_string(),
+			});
+		}
+	}
+
+	(words, confidences)
+}
+
+/// Groups the words into rows of words whose vertical centers are closer than half a word height, ordered from left to right.
+fn group_rows(mut words: Vec<Word>) -> Vec<Vec<Word>> {
+	words.sort_unstable_by_key(Word::center_y);
+
+	let mut rows: Vec<Vec<Word>> = Vec::new();
+	for word in words {
+		match rows.last_mut() {
+			Some(row) if row.last().is_some_and(|last| word.center_y().abs_diff(last.center_y()) <= last.height / 2) => row.push(word),
+			_ => rows.push(vec![word]),
+		}
+	}
+
+	for row in &mut rows {
+		row.sort_unstable_by_key(|word| word.left);
+	}
+
+	rows
+}
+
+/// Turns the rows below the header into a table like the ones tabula extracts.
+/// The header is the first row with at least three words, one per class after the block label column.
+/// Every other word goes into the column whose header starts left of its center.
+fn reconstruct_table(rows: Vec<Vec<Word>>) -> Option<Table> {
+	let header_idx = rows.iter().position(|row| row.len() >= 3 && !row.iter().any(|word| word.text.starts_with("Datum")))?;
+	let mut rows = rows.into_iter().skip(header_idx);
+	let header = rows.next()?;
+
+	// The label column has no header, it spans everything left of the first class.
+	let column_starts: Vec<u32> = header.iter().map(|word| word.left).collect();
+	let mut table = vec![std::iter::once(String::new())
+		.chain(header.into_iter().map(|word| word.text))
+		.collect::<Vec<_>>()];
+
+	for row in rows {
+		let mut cells = vec![String::new(); column_starts.len() + 1];
+		for word in row {
+			let column = column_starts.iter().rposition(|start| *start <= word.center_x()).map_or(0, |idx| idx + 1);
+			if !cells[column].is_empty() {
+				cells[column].push(' ');
+			}
+			cells[column].push_str(&word.text);
+		}
+		table.push(cells);
+	}
+
+	Some(table)
+}
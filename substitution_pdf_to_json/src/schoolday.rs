@@ -0,0 +1,149 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::Weekday;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::Error;
+
+/// Enum with the weekdays where a Substitution PDF is available.
+/// Parses the English and German names of the days, ignoring case. It is always serialized with the English name.
+#[derive(Debug, PartialOrd, Ord, PartialEq, Clone, Copy, Hash, Eq, Serialize, JsonSchema)]
+pub enum Schoolday {
+	Monday = 0,
+	Tuesday = 1,
+	Wednesday = 2,
+	Thursday = 3,
+	Friday = 4,
+}
+
+impl Schoolday {
+	pub const ALL: [Schoolday; 5] = [
+		Schoolday::Monday,
+		Schoolday::Tuesday,
+		Schoolday::Wednesday,
+		Schoolday::Thursday,
+		Schoolday::Friday,
+	];
+
+	/// Returns the next valid school day, from the given day.
+	/// # Examples
+	///
+	/// ```
+	/// use substitution_pdf_to_json::Schoolday;
+	///
+	/// let today = Schoolday::Monday;
+	/// let next_valid_day = today.next_day();
+	/// ```
+	//It is not &self, just self here due to https://rust-lang.github.io/rust-clippy/master/index.html#trivially_copy_pass_by_ref
+	//Thank clippy :p
+	#[must_use]
+	pub fn next_day(self) -> Self {
+		match self {
+			Schoolday::Monday => Schoolday::Tuesday,
+			Schoolday::Tuesday => Schoolday::Wednesday,
+			Schoolday::Wednesday => Schoolday::Thursday,
+			Schoolday::Thursday => Schoolday::Friday,
+			Schoolday::Friday => Schoolday::Monday,
+		}
+	}
+
+	/// The German name of the day, as used by the school.
+	#[must_use]
+	pub fn german_name(self) -> &'static str {
+		match self {
+			Schoolday::Monday => "Montag",
+			Schoolday::Tuesday => "Dienstag",
+			Schoolday::Wednesday => "Mittwoch",
+			Schoolday::Thursday => "Donnerstag",
+			Schoolday::Friday => "Freitag",
+		}
+	}
+
+	/// The school day of `weekday`, or `None` on weekends.
+	#[must_use]
+	pub fn from_weekday(weekday: Weekday) -> Option<Self> {
+		match weekday {
+			Weekday::Sat | Weekday::Sun => None,
+			weekday => Some(Self::from(weekday)),
+		}
+	}
+}
+
+impl Display for Schoolday {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let self_as_string = match self {
+			Schoolday::Monday => "Monday",
+			Schoolday::Tuesday => "Tuesday",
+			Schoolday::Wednesday => "Wednesday",
+			Schoolday::Thursday => "Thursday",
+			Schoolday::Friday => "Friday",
+		};
+
+		write!(f, "{}", self_as_string)
+	}
+}
+
+impl FromStr for Schoolday {
+	type Err = String;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		match name.to_lowercase().as_str() {
+			"monday" | "montag" => Ok(Schoolday::Monday),
+			"tuesday" | "dienstag" => Ok(Schoolday::Tuesday),
+			"wednesday" | "mittwoch" => Ok(Schoolday::Wednesday),
+			"thursday" | "donnerstag" => Ok(Schoolday::Thursday),
+			"friday" | "freitag" => Ok(Schoolday::Friday),
+			_ => Err(format!("{name} is not a school day")),
+		}
+	}
+}
+
+/// Path segments, query parameters and config keys all go through this, so every one of them accepts the aliases.
+impl<'de> Deserialize<'de> for Schoolday {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let name = String::deserialize(deserializer)?;
+		name.parse().map_err(D::Error::custom)
+	}
+}
+
+/// Weekends become Monday, use [`Schoolday::from_weekday`] to tell them apart.
+impl From<Weekday> for Schoolday {
+	fn from(day: Weekday) -> Self {
+		match day {
+			Weekday::Tue => Schoolday::Tuesday,
+			Weekday::Wed => Schoolday::Wednesday,
+			Weekday::Thu => Schoolday::Thursday,
+			Weekday::Fri => Schoolday::Friday,
+			_ => Schoolday::Monday,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn german_names_are_accepted_ignoring_case() {
+		assert_eq!("Montag".parse(), Ok(Schoolday::Monday));
+		assert_eq!("dienstag".parse(), Ok(Schoolday::Tuesday));
+		assert_eq!("MITTWOCH".parse(), Ok(Schoolday::Wednesday));
+		assert_eq!("Donnerstag".parse(), Ok(Schoolday::Thursday));
+		assert_eq!("freitag".parse(), Ok(Schoolday::Friday));
+		assert_eq!("friday".parse(), Ok(Schoolday::Friday));
+		assert!("Samstag".parse::<Schoolday>().is_err());
+	}
+
+	#[test]
+	fn every_day_parses_from_its_german_name() {
+		for day in Schoolday::ALL {
+			assert_eq!(day.german_name().parse(), Ok(day));
+			assert_eq!(serde_json::from_value::<Schoolday>(serde_json::json!(day.german_name())).unwrap(), day);
+		}
+	}
+
+	#[test]
+	fn days_are_serialized_with_the_english_name() {
+		assert_eq!(serde_json::to_value(Schoolday::Wednesday).unwrap(), serde_json::json!("Wednesday"));
+	}
+}
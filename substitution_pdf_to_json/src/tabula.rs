@@ -0,0 +1,456 @@
+use std::collections::BTreeMap;
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde::Deserialize;
+use crate::{ClassName, SubstitutionColumn, SubstitutionSchedule, Table};
+
+impl SubstitutionSchedule {
+	/// Builds one schedule per section from the tables and the pages they are on.
+	/// `sections` are the first pages and dates of the days, ordered by page and not empty.
+	/// Tables before the first section belong to it.
+	/// Consecutive sections of the same date are one section, as every page of a day repeats its header.
+	#[cfg_attr(any(not(feature = "convert"), target_arch = "wasm32"), allow(dead_code))]
+	pub(crate) fn from_sections(tables: &[(u32, Table)], sections: &[(u32, i64)]) -> Vec<Self> {
+		let mut merged: Vec<(u32, i64)> = Vec::with_capacity(sections.len());
+		for section in sections {
+			if merged.last().is_none_or(|(_, date)| *date != section.1) {
+				merged.push(*section);
+			}
+		}
+		let sections = merged;
+
+		let mut section_tables = vec![Vec::new(); sections.len()];
+		for (page, table) in tables {
+			let section = sections.iter().rposition(|(first_page, _)| first_page <= page).unwrap_or(0);
+			section_tables[section].push(table.clone());
+		}
+
+		sections.iter()
+			.zip(section_tables)
+			.map(|((_, date), tables)| Self::from_table(&tables, *date))
+			.collect()
+	}
+
+	/// Constructs an instance of `Self` from the json tabula outputs for a document,
+	/// e.g. a cached one, without running tabula.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` if the json isn't tabulas output.
+	pub fn from_tabula_json(content: &str, pdf_issue_date: i64) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+		Ok(Self::from_table(&parse_tabula_json(content)?, pdf_issue_date))
+	}
+
+	/// Constructs an instance of `Self` from a table.
+	#[allow(clippy::ptr_arg)]
+	pub fn from_table(tables: &Vec<Vec<Vec<String>>>, pdf_create_date: i64) -> Self {
+		let mut entries = BTreeMap::new();
+		let mut warnings = Vec::new();
+
+		for (table_idx, table) in tables.iter().enumerate() {
+			for (class, column) in Self::table_to_substitutions(table, table_idx, &mut warnings) {
+				if entries.insert(class.clone(), column).is_some() {
+					warnings.push(format!("Class {class} appears in more than one table, only the last one is kept"));
+				}
+			}
+		}
+
+		// `SystemTime::now` panics on wasm32, chrono asks the browser there.
+		#[allow(clippy::cast_sign_loss)]
+			let time_millis = Utc::now().timestamp_millis() as u64;
+
+		Self {
+			pdf_issue_date: pdf_create_date,
+			entries,
+			struct_time: time_millis,
+			effective_date: None,
+			issue_date_mismatch: false,
+			ocr_confidence: None,
+			warnings,
+		}
+	}
+
+	/// Grabs the classes and their substitutions from a table and turns them into a map.
+	/// Anything unexpected about the layout of the table is added to `warnings`.
+	#[allow(clippy::ptr_arg)]
+	fn table_to_substitutions(table: &Vec<Vec<String>>, table_idx: usize, warnings: &mut Vec<String>) -> BTreeMap<ClassName, SubstitutionColumn> {
+		let mut entries: BTreeMap<ClassName, SubstitutionColumn> = BTreeMap::new();
+
+		let header = match table.first() {
+			Some(header) if !header.is_empty() => header,
+			_ => {
+				warnings.push(format!("Table {table_idx} is empty"));
+				return entries;
+			}
+		};
+
+		let classes: Vec<ClassName> = header[1..]
+			.iter()
+			.map(|class| ClassName::from(class.as_str()))
+			.collect();
+
+		for (i, class) in classes.iter().enumerate() {
+			if class.trim().is_empty() {
+				warnings.push(format!("Column {} of table {table_idx} has no class name", i + 1));
+			}
+			if entries.insert(class.clone(), SubstitutionColumn::new()).is_some() {
+				warnings.push(format!("Class {class} appears twice in table {table_idx}"));
+			}
+		}
+
+		let mut row = 1;
+
+		for lesson_idx in 0..5 {
+			loop {
+				let cells = match table.get(row) {
+					Some(cells) => cells,
+					None => {
+						warnings.push(format!("Table {table_idx} ends before block {lesson_idx}"));
+						return entries;
+					}
+				};
+
+				if cells.len() != header.len() {
+					warnings.push(format!("Row {row} of table {table_idx} has {} cells instead of {}", cells.len(), header.len()));
+				}
+
+				for (i, substitution_part) in cells.iter().skip(1).take(classes.len()).enumerate() {
+					if !substitution_part.is_empty() {
+						entries.get_mut(&classes[i]).unwrap().push_line(lesson_idx, substitution_part);
+					}
+				}
+
+				if cells.first().is_none_or(|label| label.starts_with('-')) {
+					break;
+				}
+				row += 1;
+			}
+
+			row += 1;
+		}
+
+		entries
+	}
+}
+
+/// Parses the first `Datum:` line of `text` into milliseconds since the epoch.
+#[cfg_attr(any(not(feature = "convert"), target_arch = "wasm32"), allow(dead_code))]
+pub(crate) fn parse_issue_date(text: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+	let date_idx_start = text.find("Datum: ").ok_or("date not found")?;
+	let date_idx_end = text[date_idx_start..].find('\n').ok_or("date end not found")? + date_idx_start;
+
+	let date_str: Vec<u32> = text[date_idx_start..date_idx_end].split(", ")
+		.last()
+		.ok_or("date string has no ','")?
+		.split('.')
+		.map(|s| s.trim().parse::<u32>())
+		.collect::<Result<_, _>>()
+		.map_err(|why| format!("date has a part that isn't a number: {why}"))?;
+
+	let (day, month, year) = match date_str[..] {
+		[day, month, year, ..] => (day, month, year),
+		_ => return Err("date has less than 3 parts".into()),
+	};
+
+	#[allow(clippy::cast_possible_wrap)]
+		let date = NaiveDate::from_ymd_opt(year as i32, month, day)
+		.and_then(|date| date.and_hms_milli_opt(0, 0, 0, 0))
+		.ok_or("date is not a valid calendar date")?;
+
+	Ok(Utc.from_utc_datetime(&date).timestamp_millis())
+}
+
+/// Extracts the text from the rows and cells in the json that gets outputted by tabula.
+pub fn parse_tabula_json(content: &str) -> Result<Vec<Vec<Vec<String>>>, Box<dyn std::error::Error + Send + Sync>> {
+	Ok(parse_tabula_pages(content)?
+		.into_iter()
+		.map(|(_, table)| table)
+		.collect())
+}
+
+/// Same as [`parse_tabula_json`], but also returns the page every table is on.
+/// Tables tabula didn't report a page for are put on the first one.
+/// The json is decoded straight into the tables, only the texts of the cells are kept,
+/// after the rows tabula split off a wrapped text are merged back into it.
+pub fn parse_tabula_pages(content: &str) -> Result<Vec<(u32, Table)>, Box<dyn std::error::Error + Send + Sync>> {
+	let tables: Vec<TabulaTable> = serde_json::from_str(content)?;
+
+	Ok(tables.into_iter()
+		.map(|table| (table.page_number.unwrap_or(1), merge_continuation_rows(table.data)))
+		.collect())
+}
+
+/// Rows further from the previous row than this multiple of the usual gap between the rows of the table never continue its texts.
+const CONTINUATION_GAP: f64 = 1.25;
+
+/// Appends the texts of every continuation row to the row it continues, as part of the same line.
+/// Wrapped lines are spaced like the separate entries of a block, so the previous line tells them apart:
+/// a continuation row has no block label, follows the previous row no further than the rows usually follow each other,
+/// only has texts in columns the previous row has texts in, and the first word of each of them wouldn't have fit behind the previous line.
+fn merge_continuation_rows(rows: Vec<Vec<Cell>>) -> Table {
+	let spans: Vec<Option<Span>> = rows.iter().map(|row| Span::of(row)).collect();
+	let max_gap = median_gap(&spans) * CONTINUATION_GAP;
+	let column_ends = column_ends(&rows);
+
+	let mut table: Table = Vec::with_capacity(rows.len());
+	let mut previous_span: Option<Span> = None;
+	// Where the last line of every cell of the previous row ends.
+	let mut previous_ends: Vec<f64> = Vec::new();
+
+	for (row, span) in rows.into_iter().zip(spans) {
+		// The header with the class names is never continued.
+		let below_header = table.len() > 1;
+		let continued = match (table.last_mut().filter(|_| below_header), previous_span, span) {
+			(Some(previous), Some(last_span), Some(span))
+				if max_gap > 0.0 && span.top - last_span.bottom <= max_gap && is_continuation(previous, &previous_ends, &row, &column_ends) => {
+				for (column, cell) in row.iter().enumerate().filter(|(_, cell)| !cell.text.trim().is_empty()) {
+					previous[column].push(' ');
+					previous[column].push_str(cell.text.trim());
+					previous_ends[column] = cell.end();
+				}
+				previous_span = Some(Span {
+					top: last_span.top,
+					bottom: last_span.bottom.max(span.bottom),
+				});
+				true
+			}
+			_ => false,
+		};
+
+		if !continued {
+			previous_ends = row.iter().map(Cell::end).collect();
+			table.push(row.into_iter().map(|cell| cell.text).collect());
+			previous_span = span;
+		}
+	}
+
+	table
+}
+
+/// The median distance between consecutive rows below the header, 0 if there are none.
+/// Rows of ruled tables touch, so none of their rows is a continuation.
+fn median_gap(spans: &[Option<Span>]) -> f64 {
+	let mut gaps: Vec<f64> = spans.iter()
+		.skip(1)
+		.zip(spans.iter().skip(2))
+		.filter_map(|(previous, span)| Some(span.as_ref()?.top - previous.as_ref()?.bottom))
+		.collect();
+	if gaps.is_empty() {
+		return 0.0;
+	}
+
+	gaps.sort_unstable_by(f64::total_cmp);
+	gaps[gaps.len() / 2]
+}
+
+fn is_continuation(previous: &[String], previous_ends: &[f64], cells: &[Cell], column_ends: &[f64]) -> bool {
+	let unlabeled = cells.first().is_none_or(|label| label.text.trim().is_empty());
+	let texts: Vec<(usize, &Cell)> = cells.iter()
+		.enumerate()
+		.skip(1)
+		.filter(|(_, cell)| !cell.text.trim().is_empty())
+		.collect();
+	let continues_texts = texts.iter().all(|(column, cell)| {
+		previous.get(*column).is_some_and(|text| !text.trim().is_empty())
+			&& previous_ends.get(*column)
+				.zip(column_ends.get(*column))
+				.is_some_and(|(end, column_end)| cell.wraps_after(*end, *column_end))
+	});
+
+	unlabeled && !texts.is_empty() && continues_texts
+}
+
+/// Where the longest text of every column ends, tabula doesn't output the borders of the columns of tables without rulings.
+fn column_ends(rows: &[Vec<Cell>]) -> Vec<f64> {
+	let mut ends: Vec<f64> = Vec::new();
+	for row in rows {
+		if ends.len() < row.len() {
+			ends.resize(row.len(), 0.0);
+		}
+		for (end, cell) in ends.iter_mut().zip(row) {
+			*end = end.max(cell.end());
+		}
+	}
+
+	ends
+}
+
+/// The vertical extent of the texts of a row on the page.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+	top: f64,
+	bottom: f64,
+}
+
+impl Span {
+	/// `None` if no cell of the row has text with a position.
+	fn of(row: &[Cell]) -> Option<Self> {
+		row.iter()
+			.filter(|cell| !cell.text.trim().is_empty() && cell.height > 0.0)
+			.map(|cell| Self {
+				top: cell.top,
+				bottom: cell.top + cell.height,
+			})
+			.reduce(|a, b| Self {
+				top: a.top.min(b.top),
+				bottom: a.bottom.max(b.bottom),
+			})
+	}
+}
+
+/// A table in the json tabula outputs.
+#[derive(Debug, Deserialize)]
+struct TabulaTable {
+	page_number: Option<u32>,
+	data: Vec<Vec<Cell>>,
+}
+
+/// A cell in the substitution table.
+/// For tables without rulings, the position and size are the ones of the text, empty cells have none.
+#[derive(Debug, Deserialize)]
+struct Cell {
+	#[serde(default)]
+	top: f64,
+	#[serde(default)]
+	left: f64,
+	#[serde(default)]
+	width: f64,
+	#[serde(default)]
+	height: f64,
+	text: String,
+}
+
+impl Cell {
+	fn end(&self) -> f64 {
+		self.left + self.width
+	}
+
+	/// Whether the first word of the text wouldn't have fit between `end` and `column_end`,
+	/// measured by the average width of the characters of the text.
+	fn wraps_after(&self, end: f64, column_end: f64) -> bool {
+		let text = self.text.trim();
+		let characters = text.chars().count();
+		if characters == 0 || self.width <= 0.0 {
+			return false;
+		}
+		let first_word = text.split_whitespace().next().map_or(0, |word| word.chars().count());
+
+		#[allow(clippy::cast_precision_loss)]
+			let character_width = self.width / characters as f64;
+		#[allow(clippy::cast_precision_loss)]
+			let word_end = end + character_width * (first_word + 1) as f64;
+		word_end > column_end
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MONDAY: i64 = 1_648_425_600_000;
+	const TUESDAY: i64 = MONDAY + 24 * 60 * 60 * 1000;
+
+	/// A table with the header and the five blocks of `class`, with `text` in the first block.
+	fn table(class: &str, text: &str) -> Table {
+		let mut table = vec![vec![String::new(), class.to_string()]];
+		for block in 0..5 {
+			let text = if block == 0 { text } else { "" };
+			table.push(vec![format!("{}", block + 1), text.to_string()]);
+			table.push(vec!["-".to_string(), String::new()]);
+		}
+		table
+	}
+
+	#[test]
+	fn parses_the_issue_date() {
+		assert_eq!(parse_issue_date("Vertretungsplan\nDatum: Montag, 28.03.2022\n").unwrap(), MONDAY);
+	}
+
+	#[test]
+	fn rejects_malformed_issue_dates() {
+		assert!(parse_issue_date("Datum: Montag, 28.O3.2022\n").is_err());
+		assert!(parse_issue_date("Datum: Montag, 28.03\n").is_err());
+		assert!(parse_issue_date("Datum: Montag, 2022\n").is_err());
+		assert!(parse_issue_date("Datum: Montag, 31.02.2022\n").is_err());
+	}
+
+	/// Tabulas output for a page without rulings, every line of the blocks is a row and all rows are spaced alike.
+	/// Block 1 of 5a wraps into a second line, block 2 of 8d has two entries.
+	const STREAM_JSON: &str = include_str!("../test_data/tabula_stream.json");
+	/// Tabulas output for a page with rulings around every row.
+	/// Block 1 of 5a has a cell with a wrapped line and a second entry in a row of its own.
+	const RULED_JSON: &str = include_str!("../test_data/tabula_ruled.json");
+
+	fn block(schedule: &SubstitutionSchedule, class: &str, block: usize) -> Option<String> {
+		schedule.entries()
+			.get(&ClassName::from(class))?
+			.blocks()
+			.find(|(idx, _)| *idx == block)
+			.map(|(_, text)| text.to_string())
+	}
+
+	#[test]
+	fn wrapped_cells_are_one_entry() {
+		let schedule = SubstitutionSchedule::from_tabula_json(STREAM_JSON, MONDAY).unwrap();
+
+		assert!(schedule.warnings().is_empty(), "{:?}", schedule.warnings());
+		assert_eq!(block(&schedule, "5a", 0).unwrap(), "Mathe: Herr Mueller vertritt Frau Schmidt in Raum 104");
+	}
+
+	#[test]
+	fn separate_entries_of_a_block_stay_apart() {
+		let schedule = SubstitutionSchedule::from_tabula_json(STREAM_JSON, MONDAY).unwrap();
+
+		assert_eq!(block(&schedule, "8d", 1).unwrap(), "Physik entfaellt\nChemie in Raum 3");
+		assert_eq!(block(&schedule, "8d", 2).unwrap(), "Latein: Frau Weber vertritt");
+	}
+
+	#[test]
+	fn cancelled_lessons_of_the_pdf_are_marked() {
+		let schedule = SubstitutionSchedule::from_tabula_json(STREAM_JSON, MONDAY).unwrap();
+		let entries = schedule.entries()[&ClassName::from("8d")].block_entries();
+
+		let physik = entries.iter().find(|entry| entry.text == "Physik entfaellt").unwrap();
+		assert!(physik.cancelled);
+		let chemie = entries.iter().find(|entry| entry.text == "Chemie in Raum 3").unwrap();
+		assert!(!chemie.cancelled);
+	}
+
+	#[test]
+	fn rows_of_ruled_tables_are_never_merged() {
+		let schedule = SubstitutionSchedule::from_tabula_json(RULED_JSON, MONDAY).unwrap();
+
+		assert!(schedule.warnings().is_empty(), "{:?}", schedule.warnings());
+		assert_eq!(
+			block(&schedule, "5a", 0).unwrap(),
+			"Mathe: Herr Mueller vertritt\rFrau Schmidt in Raum 104\nEnglisch: Frau Weber vertritt"
+		);
+		assert_eq!(block(&schedule, "8d", 1).unwrap(), "Physik entfaellt");
+	}
+
+	#[test]
+	fn wrapped_cells_are_one_block_entry() {
+		let schedule = SubstitutionSchedule::from_tabula_json(RULED_JSON, MONDAY).unwrap();
+		let entries: Vec<String> = schedule.entries()[&ClassName::from("5a")]
+			.block_entries()
+			.into_iter()
+			.filter(|entry| entry.block == 0)
+			.map(|entry| entry.text)
+			.collect();
+
+		assert_eq!(entries, ["Mathe: Herr Mueller vertritt Frau Schmidt in Raum 104", "Englisch: Frau Weber vertritt"]);
+	}
+
+	#[test]
+	fn pages_repeating_the_header_stay_in_their_section() {
+		let tables = vec![(1, table("5a", "Mathe")), (2, table("6b", "Deutsch")), (3, table("7c", "Englisch"))];
+		let sections = [(1, MONDAY), (2, MONDAY), (3, TUESDAY)];
+
+		let schedules = SubstitutionSchedule::from_sections(&tables, &sections);
+
+		assert_eq!(schedules.len(), 2);
+		assert_eq!(schedules[0].pdf_issue_date, MONDAY);
+		assert_eq!(schedules[0].entries().keys().map(ToString::to_string).collect::<Vec<_>>(), ["5a", "6b"]);
+		assert_eq!(schedules[1].pdf_issue_date, TUESDAY);
+		assert_eq!(schedules[1].entries().keys().map(ToString::to_string).collect::<Vec<_>>(), ["7c"]);
+	}
+}
@@ -0,0 +1,121 @@
+"use strict";
+
+const DAYS = [
+	["monday", "Mo"],
+	["tuesday", "Di"],
+	["wednesday", "Mi"],
+	["thursday", "Do"],
+	["friday", "Fr"],
+];
+const BLOCKS = [0, 1, 2, 3, 4, 5];
+// Days that weren't fetched for this long are marked as stale.
+const STALE_AFTER_MS = 30 * 60 * 1000;
+
+const classesInput = document.getElementById("classes");
+const daysNav = document.getElementById("days");
+const freshness = document.getElementById("freshness");
+const scheduleDiv = document.getElementById("schedule");
+
+let selectedDay = DAYS[Math.min(Math.max(new Date().getDay() - 1, 0), 4)][0];
+classesInput.value = localStorage.getItem("classes") || "";
+
+function formatTime(millis) {
+	return new Date(millis).toLocaleString("de-DE");
+}
+
+function renderDays() {
+	daysNav.replaceChildren(...DAYS.map(([day, label]) => {
+		const button = document.createElement("button");
+		button.textContent = label;
+		button.classList.toggle("active", day === selectedDay);
+		button.addEventListener("click", () => {
+			selectedDay = day;
+			renderDays();
+			load();
+		});
+		return button;
+	}));
+}
+
+function renderSchedule(schedule) {
+	const classes = Object.keys(schedule.entries);
+	if (classes.length === 0) {
+		scheduleDiv.textContent = "Keine Vertretungen.";
+		return;
+	}
+
+	const table = document.createElement("table");
+	const header = table.createTHead().insertRow();
+	for (const title of ["Klasse", ...BLOCKS.map((block) => `Block ${block}`)]) {
+		const th = document.createElement("th");
+		th.textContent = title;
+		header.appendChild(th);
+	}
+
+	const body = table.createTBody();
+	for (const name of classes) {
+		const row = body.insertRow();
+		row.insertCell().textContent = name;
+		for (const block of BLOCKS) {
+			row.insertCell().textContent = schedule.entries[name][block] || "";
+		}
+	}
+
+	scheduleDiv.replaceChildren(table);
+}
+
+function renderFreshness(status) {
+	if (!status || !status.last_fetch) {
+		freshness.textContent = "";
+		return;
+	}
+
+	const parts = [`Abgerufen: ${formatTime(status.last_fetch)}`];
+	if (status.last_change) {
+		parts.push(`zuletzt geändert: ${formatTime(status.last_change)}`);
+	}
+	if (status.consecutive_failures > 0) {
+		parts.push(`${status.consecutive_failures} fehlgeschlagene Abrufe`);
+	}
+	freshness.textContent = parts.join(", ");
+	freshness.classList.toggle("stale", Date.now() - status.last_fetch > STALE_AFTER_MS || status.consecutive_failures > 0);
+}
+
+async function load() {
+	const params = new URLSearchParams();
+	const classes = classesInput.value.trim();
+	if (classes) {
+		params.set("classes", classes);
+	}
+
+	const [scheduleResponse, statusResponse] = await Promise.all([
+		fetch(`/${selectedDay}?${params}`, { headers: { Accept: "application/json" } }),
+		fetch("/status"),
+	]);
+
+	if (statusResponse.ok) {
+		const statuses = await statusResponse.json();
+		renderFreshness(Object.entries(statuses).find(([day]) => day.toLowerCase() === selectedDay)?.[1]);
+	}
+
+	if (scheduleResponse.status === 200) {
+		renderSchedule(await scheduleResponse.json());
+	} else if (scheduleResponse.status === 204) {
+		scheduleDiv.textContent = "Noch kein Plan für diesen Tag.";
+	} else {
+		scheduleDiv.textContent = `Der Plan konnte nicht geladen werden (${scheduleResponse.status}).`;
+	}
+}
+
+document.getElementById("filter").addEventListener("submit", (event) => {
+	event.preventDefault();
+	localStorage.setItem("classes", classesInput.value.trim());
+	load();
+});
+classesInput.addEventListener("change", () => {
+	localStorage.setItem("classes", classesInput.value.trim());
+	load();
+});
+
+renderDays();
+load();